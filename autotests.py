@@ -1,49 +1,106 @@
+import os
 import subprocess
 
-command_template = "sudo ./target/debug/curn --command {} --mount ./ubuntu-fs --uid 0 --add ../lim/tests/:/tmp/lim/ --tool ./curn-tool"
+# CURN_ROOTLESS=1 runs the tests as the current user
+rootless = os.environ.get("CURN_ROOTLESS") == "1"
+
+command_template = ("" if rootless else "sudo ") + "./target/debug/curn run --mount ./ubuntu-fs --uid 0 --add ../lim/tests/:/tmp/lim/ --tool ./curn-tool -- {}"
 commands = [
-    '"/bin/ls"', 
-    '"/bin/ls -l"', 
-    '"/bin/pwd"', 
-    '"/bin/date"', 
-    '"/bin/whoami"', 
-    '"/bin/echo"', 
-    '"/bin/echo hello"', 
-    '"/bin/ps"', 
-    '"/bin/exit"', 
-    '"/bin/mkdir /tmp/lest"', 
-
-    '"ecurn lasm -i /tmp/lim/123.lasm -o /tmp/lest/123.lim"', 
-    '"ecurn delasm -i /tmp/lest/123.lim"', 
-    '"ecurn lime -i /tmp/lest/123.lim"', 
-    '"/bin/rm /tmp/lest/123.lim"', 
-
-    '"ecurn lasm -i /tmp/lim/alloc.lasm -o /tmp/lest/alloc.lim"', 
-    '"ecurn delasm -i /tmp/lest/alloc.lim"', 
-    '"ecurn lime -i /tmp/lest/alloc.lim"', 
-    '"/bin/rm /tmp/lest/alloc.lim"', 
-
-    '"ecurn lasm -i /tmp/lim/e.lasm -o /tmp/lest/e.lim"', 
-    '"ecurn delasm -i /tmp/lest/e.lim"', 
-    '"ecurn lime -i /tmp/lest/e.lim"', 
-    '"/bin/rm /tmp/lest/e.lim"', 
-
-    '"ecurn lasm -i /tmp/lim/f123.lasm -o /tmp/lest/f123.lim"', 
-    '"ecurn delasm -i /tmp/lest/f123.lim"', 
-    '"ecurn lime -i /tmp/lest/f123.lim"', 
-    '"/bin/rm /tmp/lest/f123.lim"', 
-
-    '"ecurn lasm -i /tmp/lim/fib.lasm -o /tmp/lest/fib.lim"', 
-    '"ecurn delasm -i /tmp/lest/fib.lim"', 
-    '"ecurn lime -i /tmp/lest/fib.lim"', 
-    '"/bin/rm /tmp/lest/fib.lim"', 
-
-    '"ecurn lasm -i /tmp/lim/pi.lasm -o /tmp/lest/pi.lim"', 
-    '"ecurn delasm -i /tmp/lest/pi.lim"', 
-    '"ecurn lime -i /tmp/lest/pi.lim"', 
-    '"/bin/rm /tmp/lest/pi.lim"', 
-
-    '"/bin/rmdir /tmp/lest"', 
+    '/bin/ls', 
+    '/bin/ls -l', 
+    '/bin/pwd', 
+    '/bin/date', 
+    '/bin/whoami', 
+    '/bin/echo', 
+    '/bin/echo hello', 
+    '/bin/ps', 
+    '/bin/exit', 
+    '/bin/mkdir /tmp/lest', 
+
+    'ecurn lasm -i /tmp/lim/123.lasm -o /tmp/lest/123.lim', 
+    'ecurn delasm -i /tmp/lest/123.lim', 
+    'ecurn lime -i /tmp/lest/123.lim', 
+    '/bin/rm /tmp/lest/123.lim', 
+
+    'ecurn lasm -i /tmp/lim/alloc.lasm -o /tmp/lest/alloc.lim', 
+    'ecurn delasm -i /tmp/lest/alloc.lim', 
+    'ecurn lime -i /tmp/lest/alloc.lim', 
+    '/bin/rm /tmp/lest/alloc.lim', 
+
+    'ecurn lasm -i /tmp/lim/e.lasm -o /tmp/lest/e.lim', 
+    'ecurn delasm -i /tmp/lest/e.lim', 
+    'ecurn lime -i /tmp/lest/e.lim', 
+    '/bin/rm /tmp/lest/e.lim', 
+
+    'ecurn lasm -i /tmp/lim/f123.lasm -o /tmp/lest/f123.lim', 
+    'ecurn delasm -i /tmp/lest/f123.lim', 
+    'ecurn lime -i /tmp/lest/f123.lim', 
+    '/bin/rm /tmp/lest/f123.lim', 
+
+    'ecurn lasm -i /tmp/lim/fib.lasm -o /tmp/lest/fib.lim', 
+    'ecurn delasm -i /tmp/lest/fib.lim', 
+    'ecurn lime -i /tmp/lest/fib.lim', 
+    '/bin/rm /tmp/lest/fib.lim', 
+
+    'ecurn lasm -i /tmp/lim/pi.lasm -o /tmp/lest/pi.lim', 
+    'ecurn delasm -i /tmp/lest/pi.lim', 
+    'ecurn lime -i /tmp/lest/pi.lim', 
+    '/bin/rm /tmp/lest/pi.lim', 
+
+    '/bin/rmdir /tmp/lest', 
+]
+
+# commands whose exit code is propagated by curn
+expected_retcodes = [
+    ('/bin/true', 0),
+    ('/bin/false', 1),
+    ('/bin/not-exist', 127),
+    # arguments are passed as is and commands are searched in the default PATH
+    ('true', 0),
+    ('/bin/sh -c \'test "$1" = "a b"\' sh "a b"', 0),
+    # /dev is a tmpfs with the standard devices, ptys and shared memory
+    ('/bin/sh -c "echo > /dev/null && test -c /dev/urandom && test -L /dev/fd"', 0),
+    ('/bin/grep -q " /dev/pts devpts" /proc/mounts', 0),
+    ('/bin/grep -q " /dev/shm tmpfs" /proc/mounts', 0),
+    # /sys and /proc/sys are read-only, /proc/kcore is masked
+    ('/bin/sh -c "echo 1 > /proc/sys/kernel/ns_last_pid"', 2),
+    ('/bin/grep -q " /sys sysfs ro" /proc/mounts', 0),
+    ('/bin/sh -c "test ! -s /proc/kcore"', 0),
+    # the container is attached to the curn0 bridge, or has only loopback when rootless
+    ('/bin/grep -q eth0 /proc/net/dev', 1 if rootless else 0),
+]
+
+# commands run with a seccomp mode and their expected exit code
+seccomp_retcodes = [
+    # syscalls of typical userland are allowed
+    ('allowlist', '/bin/ls /', 0),
+    ('allowlist-kill', '/bin/ls /', 0),
+    # unshare of a user namespace is denied by EPERM, or kills the command by SIGSYS
+    ('allowlist', '/usr/bin/unshare --user /bin/true', 1),
+    ('allowlist-kill', '/usr/bin/unshare --user /bin/true', 128 + 31),
+    # caught syscalls are still refused when logged, and allowed while learning
+    ('allowlist --seccomp-log', '/usr/bin/unshare --user /bin/true', 1),
+    ('allowlist --seccomp-learn /tmp/curn-learned.json', '/usr/bin/unshare --user /bin/true', 0),
+    # learning from a profile refusing everything, sending the listener is never caught
+    ('--seccomp-profile /tmp/curn-empty.json --seccomp-learn /tmp/curn-learned.json', '/bin/ls /', 0),
+]
+
+with open("/tmp/curn-empty.json", "w") as profile:
+    profile.write('{ "defaultAction": "SCMP_ACT_ERRNO", "syscalls": [] }')
+
+# commands run with extra mounts and their expected exit code
+mount_retcodes = [
+    # read-only volumes refuse writes, single files can be bound
+    ('--volume ../lim/tests/:/data:ro,nosuid', '/bin/touch /data/curn-ro', 1),
+    ('--volume /etc/hostname:/tmp/host-name:ro', '/bin/cat /tmp/host-name', 0),
+    ('--tmpfs /run:size=1m,mode=755', '/bin/grep -q " /run tmpfs" /proc/mounts', 0),
+    # only the root is read-only, its volumes and tmpfs mounts stay writable
+    ('--read-only', '/bin/touch /curn-ro', 1),
+    ('--read-only --tmpfs /tmp', '/bin/touch /tmp/curn-rw', 0),
+    ('--read-only', '/bin/sh -c "echo >> /etc/hosts"', 0),
+    # malformed specs are refused before the container is created
+    ('--volume ../lim/tests/', '/bin/true', 200),
+    ('--tmpfs /run:size', '/bin/true', 200),
 ]
 
 total_commands = 0
@@ -68,5 +125,45 @@ for cmd in commands:
     
     total_commands += 1
 
+for cmd, retcode in expected_retcodes:
+    command = command_template.format(cmd)
+    result = subprocess.run(command, shell=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL)
+
+    if result.returncode == retcode:
+        print(f"Command {cmd} returned expected code {retcode}.")
+        passed_commands += 1
+    else:
+        print(f"Command {cmd} returned {result.returncode}, expected {retcode}.")
+        failed_commands += 1
+
+    total_commands += 1
+
+for mode, cmd, retcode in seccomp_retcodes:
+    opts = mode if mode.startswith("--") else f"--seccomp {mode}"
+    command = command_template.replace(" run ", f" run {opts} ").format(cmd)
+    result = subprocess.run(command, shell=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL)
+
+    if result.returncode == retcode:
+        print(f"Command {cmd} with seccomp {mode} returned expected code {retcode}.")
+        passed_commands += 1
+    else:
+        print(f"Command {cmd} with seccomp {mode} returned {result.returncode}, expected {retcode}.")
+        failed_commands += 1
+
+    total_commands += 1
+
+for opts, cmd, retcode in mount_retcodes:
+    command = command_template.replace(" run ", f" run {opts} ").format(cmd)
+    result = subprocess.run(command, shell=True, stdout=subprocess.DEVNULL, stderr=subprocess.DEVNULL)
+
+    if result.returncode == retcode:
+        print(f"Command {cmd} with {opts} returned expected code {retcode}.")
+        passed_commands += 1
+    else:
+        print(f"Command {cmd} with {opts} returned {result.returncode}, expected {retcode}.")
+        failed_commands += 1
+
+    total_commands += 1
+
 print("\nSummary:")
 print(f"Total: {total_commands}, Passed: {passed_commands}, Failed: {failed_commands}")
@@ -4,7 +4,8 @@
 #include <bpf/bpf_core_read.h>
 #include "snoop.bpf.h"
 
-SEC("uretprobe/./ubuntu-fs/bin/bash:readline")
+/* attached by curn to readline of the container's /bin/bash */
+SEC("uretprobe")
 int BPF_KRETPROBE(printret, void *ret)
 {
     const char ecurn[] = "ecurn";
@@ -45,30 +46,110 @@ struct {
     __uint(value_size, sizeof(u32));
 } events SEC(".maps");
 
-const volatile int ppid_target = 0;
+/* struct event is too large for the BPF stack */
+struct {
+    __uint(type, BPF_MAP_TYPE_PERCPU_ARRAY);
+    __uint(max_entries, 1);
+    __type(key, u32);
+    __type(value, struct event);
+} heap SEC(".maps");
+
+/* processes of the container, seeded by curn with the init pid */
+struct {
+    __uint(type, BPF_MAP_TYPE_HASH);
+    __uint(max_entries, MAX_TRACKED_PIDS);
+    __type(key, pid_t);
+    __type(value, u8);
+} tracked SEC(".maps");
+
+SEC("tracepoint/sched/sched_process_fork")
+int tracepoint__sched__sched_process_fork(struct trace_event_raw_sched_process_fork* ctx)
+{
+    pid_t parent = bpf_get_current_pid_tgid() >> 32;
+    pid_t child = BPF_CORE_READ(ctx, child_pid);
+    u8 one = 1;
+
+    /* threads are added too until they exit, the main thread is looked up by tgid */
+    if (bpf_map_lookup_elem(&tracked, &parent)) {
+        bpf_map_update_elem(&tracked, &child, &one, BPF_ANY);
+    }
+    return 0;
+}
+
+SEC("tracepoint/sched/sched_process_exit")
+int tracepoint__sched__sched_process_exit(struct trace_event_raw_sched_process_template* ctx)
+{
+    u64 id = bpf_get_current_pid_tgid();
+    pid_t pid = (u32)id;
+    pid_t tgid = id >> 32;
+    struct task_struct *task = (struct task_struct*)bpf_get_current_task();
+
+    if (pid != tgid) {
+        bpf_map_delete_elem(&tracked, &pid);
+    }
+    /* the main thread may exit first, the tgid is kept until the last thread of the group */
+    if (BPF_CORE_READ(task, signal, live.counter) == 0) {
+        bpf_map_delete_elem(&tracked, &tgid);
+    }
+    return 0;
+}
 
 SEC("tracepoint/syscalls/sys_enter_execve")
 int tracepoint__syscalls__sys_enter_execve(struct trace_event_raw_sys_enter* ctx)
 {
     u64 id;
-    pid_t pid, tgid;
-    struct event event = {0};
+    pid_t tgid;
+    u32 zero = 0;
+    struct event *event;
     struct task_struct *task = 0;
+    const char **argv;
+    const char *argp;
+    unsigned int len;
+    long n;
+
+    event = bpf_map_lookup_elem(&heap, &zero);
+    if (!event) {
+        return 0;
+    }
 
     uid_t uid = (u32)bpf_get_current_uid_gid();
     id = bpf_get_current_pid_tgid();
     tgid = id >> 32;
 
-    event.pid = tgid;
-    event.uid = uid;
-    task = (struct task_struct*)bpf_get_current_task();
-    event.ppid = BPF_CORE_READ(task, real_parent, tgid);
-    if (event.ppid != ppid_target) {
+    if (!bpf_map_lookup_elem(&tracked, &tgid)) {
         return 0;
     }
+
+    event->pid = tgid;
+    event->uid = uid;
+    task = (struct task_struct*)bpf_get_current_task();
+    event->ppid = BPF_CORE_READ(task, real_parent, tgid);
+    bpf_get_current_comm(&event->comm, sizeof(event->comm));
     char *cmd_ptr = (char *) BPF_CORE_READ(ctx, args[0]);
-    bpf_probe_read_str(&event.comm, sizeof(event.comm), cmd_ptr);
-    bpf_perf_event_output(ctx, &events, BPF_F_CURRENT_CPU, &event, sizeof(event));
+    bpf_probe_read_user_str(&event->filename, sizeof(event->filename), cmd_ptr);
+
+    event->args_count = 0;
+    event->args_size = 0;
+    argv = (const char **) BPF_CORE_READ(ctx, args[1]);
+    #pragma unroll
+    for (int i = 0; i < MAX_ARGS; i++) {
+        argp = 0;
+        bpf_probe_read_user(&argp, sizeof(argp), &argv[i]);
+        if (!argp || event->args_size > FULL_MAX_ARGS_ARR - ARG_SIZE) {
+            break;
+        }
+        n = bpf_probe_read_user_str(&event->args[event->args_size], ARG_SIZE, argp);
+        if (n < 0) {
+            break;
+        }
+        event->args_count++;
+        event->args_size += n;
+    }
+
+    len = EVENT_SIZE(event);
+    if (len <= sizeof(*event)) {
+        bpf_perf_event_output(ctx, &events, BPF_F_CURRENT_CPU, event, len);
+    }
 
     return 0;
 }
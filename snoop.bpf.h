@@ -4,6 +4,10 @@
 #define TASK_COMM_LEN 16
 #define MAX_LINE_SIZE 80
 #define MAX_PATH_LEN  256
+#define MAX_ARGS      20
+#define ARG_SIZE      128
+#define FULL_MAX_ARGS_ARR (MAX_ARGS * ARG_SIZE)
+#define MAX_TRACKED_PIDS 8192
 
 struct event {
     int pid;
@@ -12,6 +16,14 @@ struct event {
     int retval;
     bool is_exit;
     char comm[TASK_COMM_LEN];
+    char filename[MAX_PATH_LEN];
+    /* argv of execve, each argument ends with NUL */
+    int args_count;
+    unsigned int args_size;
+    char args[FULL_MAX_ARGS_ARR];
 };
 
+/* only the used part of args is sent to user space */
+#define EVENT_SIZE(e) (sizeof(struct event) - FULL_MAX_ARGS_ARR + (e)->args_size)
+
 #endif
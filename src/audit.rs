@@ -0,0 +1,204 @@
+use crate::{
+    config::ContainerOpts, ebpf::Event, error::ErrorCode, seccomp::SeccompAction, store::store_root,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    path::PathBuf,
+    time::SystemTime,
+};
+
+/// One event of the container, stored as a line of JSON
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AuditRecord {
+    /// RFC 3339 time in UTC when curn received the event
+    pub timestamp: String,
+    pub container_id: String,
+    pub hostname: String,
+    pub pid: i32,
+    pub uid: i32,
+    /// Name of the process causing the event
+    pub comm: String,
+    #[serde(flatten)]
+    pub event: AuditEvent,
+}
+
+/// Records of both kinds share the file, they are told apart by their fields
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AuditEvent {
+    Exec {
+        ppid: i32,
+        path: String,
+        argv: Vec<String>,
+    },
+    /// Syscall caught by seccomp, `allowed` while learning a profile
+    Seccomp {
+        syscall: String,
+        args: Vec<u64>,
+        action: SeccompAction,
+        allowed: bool,
+    },
+}
+
+impl AuditRecord {
+    pub fn new(event: &Event, config: &ContainerOpts) -> Self {
+        Self {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            container_id: config.container_id.clone(),
+            hostname: config.hostname.clone(),
+            pid: event.pid,
+            uid: event.uid,
+            comm: event.comm(),
+            event: AuditEvent::Exec {
+                ppid: event.ppid,
+                path: event.filename(),
+                argv: event.argv(),
+            },
+        }
+    }
+
+    pub fn seccomp(
+        pid: i32,
+        uid: i32,
+        comm: String,
+        event: AuditEvent,
+        config: &ContainerOpts,
+    ) -> Self {
+        Self {
+            timestamp: humantime::format_rfc3339_millis(SystemTime::now()).to_string(),
+            container_id: config.container_id.clone(),
+            hostname: config.hostname.clone(),
+            pid,
+            uid,
+            comm,
+            event,
+        }
+    }
+
+    pub fn time(&self) -> Option<SystemTime> {
+        humantime::parse_rfc3339(&self.timestamp).ok()
+    }
+}
+
+impl fmt::Display for AuditRecord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.event {
+            AuditEvent::Exec { ppid, path, argv } => write!(
+                f,
+                "{} pid {} ppid {} uid {} exec {} {:?}",
+                self.timestamp, self.pid, ppid, self.uid, path, argv
+            ),
+            AuditEvent::Seccomp {
+                syscall,
+                args,
+                allowed,
+                ..
+            } => {
+                let args = args
+                    .iter()
+                    .map(|a| format!("{:#x}", a))
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                let verdict = if *allowed { "allowed" } else { "refused" };
+                write!(
+                    f,
+                    "{} pid {} uid {} seccomp {} {}({})",
+                    self.timestamp, self.pid, self.uid, verdict, syscall, args
+                )
+            }
+        }
+    }
+}
+
+/// Return the directory of audit files, e.g. /var/lib/curn/logs
+fn audit_dir() -> PathBuf {
+    store_root().join("logs")
+}
+
+/// Return the audit file of a container, e.g. /var/lib/curn/logs/cunrc.xxx....jsonl
+pub fn audit_path(container_id: &str) -> PathBuf {
+    audit_dir().join(format!("{}.jsonl", container_id))
+}
+
+pub struct AuditLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl AuditLog {
+    /// Audit files are kept after the container is deleted
+    pub fn create(container_id: &str) -> Result<Self, ErrorCode> {
+        let path = audit_path(container_id);
+        let file = fs::create_dir_all(audit_dir())
+            .and_then(|_| File::create(&path))
+            .and_then(|_| OpenOptions::new().append(true).open(&path));
+
+        match file {
+            Ok(file) => Ok(Self { path, file }),
+            Err(e) => {
+                log::error!("Cannot create audit file {:?}: {}", path, e);
+                Err(ErrorCode::AuditError(0))
+            }
+        }
+    }
+
+    /// Append to the file of another writer of the same container
+    pub fn open(container_id: &str) -> Result<Self, ErrorCode> {
+        let path = audit_path(container_id);
+        let file = fs::create_dir_all(audit_dir())
+            .and_then(|_| OpenOptions::new().create(true).append(true).open(&path));
+
+        match file {
+            Ok(file) => Ok(Self { path, file }),
+            Err(e) => {
+                log::error!("Cannot open audit file {:?}: {}", path, e);
+                Err(ErrorCode::AuditError(0))
+            }
+        }
+    }
+
+    pub fn append(&mut self, record: &AuditRecord) -> Result<(), ErrorCode> {
+        let line = serde_json::to_string(record).map_err(|e| {
+            log::error!("Cannot serialize audit record: {}", e);
+            ErrorCode::AuditError(0)
+        })?;
+
+        // one write per record so a reader never sees half a line
+        if let Err(e) = self.file.write_all(format!("{}\n", line).as_bytes()) {
+            log::error!("Cannot write audit file {:?}: {}", self.path, e);
+            return Err(ErrorCode::AuditError(0));
+        }
+        Ok(())
+    }
+}
+
+/// Read all records of a container, in the order they were received
+pub fn read_audit(container_id: &str) -> Result<Vec<AuditRecord>, ErrorCode> {
+    let path = audit_path(container_id);
+    let file = File::open(&path).map_err(|e| {
+        log::error!("Cannot open audit file {:?}: {}", path, e);
+        ErrorCode::AuditError(1)
+    })?;
+
+    let mut records = Vec::new();
+    for (i, line) in BufReader::new(file).lines().enumerate() {
+        let line = line.map_err(|e| {
+            log::error!("Cannot read audit file {:?}: {}", path, e);
+            ErrorCode::AuditError(1)
+        })?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(&line) {
+            Ok(record) => records.push(record),
+            Err(e) => {
+                log::error!("Invalid record at line {} of {:?}: {}", i + 1, path, e);
+                return Err(ErrorCode::AuditError(2));
+            }
+        }
+    }
+    Ok(records)
+}
@@ -1,41 +1,195 @@
 use crate::error::ErrorCode;
-use capctl::{Cap, FullCapState};
+use capctl::{ambient, bounding, prctl, Cap, CapSet, CapState, FullCapState};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
 
-const CAPABILITIES_DROP: [Cap; 21] = [
-    Cap::AUDIT_CONTROL,
-    Cap::AUDIT_READ,
-    Cap::AUDIT_WRITE,
-    Cap::BLOCK_SUSPEND,
-    Cap::DAC_READ_SEARCH,
+// https://github.com/moby/moby/blob/master/oci/caps/defaults.go
+const CAPABILITIES_DEFAULT: [Cap; 14] = [
+    Cap::CHOWN,
     Cap::DAC_OVERRIDE,
     Cap::FSETID,
-    Cap::IPC_LOCK,
-    Cap::MAC_ADMIN,
-    Cap::MAC_OVERRIDE,
+    Cap::FOWNER,
     Cap::MKNOD,
+    Cap::NET_RAW,
+    Cap::SETGID,
+    Cap::SETUID,
     Cap::SETFCAP,
-    Cap::SYSLOG,
-    Cap::SYS_ADMIN,
-    // https://github.com/moby/moby/issues/9448
-    Cap::SYS_BOOT,
-    Cap::SYS_MODULE,
-    Cap::SYS_NICE,
-    Cap::SYS_RAWIO,
-    Cap::SYS_RESOURCE,
-    Cap::SYS_TIME,
-    Cap::WAKE_ALARM,
+    Cap::SETPCAP,
+    Cap::NET_BIND_SERVICE,
+    Cap::SYS_CHROOT,
+    Cap::KILL,
+    Cap::AUDIT_WRITE,
 ];
 
-pub fn set_capabilities() -> Result<(), ErrorCode> {
-    log::debug!("Restricting unwanted capabilities ...");
+/// Capability given to `--cap-add` or `--cap-drop`, e.g. NET_ADMIN, CAP_NET_ADMIN or ALL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CapName {
+    All,
+    Cap(Cap),
+}
+
+impl FromStr for CapName {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("ALL") {
+            return Ok(CapName::All);
+        }
+        // byte 4 may not be a char boundary, e.g. `aéé`
+        let name = match s.get(..4).is_some_and(|p| p.eq_ignore_ascii_case("CAP_")) {
+            true => s.to_string(),
+            false => format!("CAP_{}", s),
+        };
+        Cap::from_str(&name)
+            .map(CapName::Cap)
+            .map_err(|_| format!("Unknown capability `{}`", s))
+    }
+}
+
+/// The five capability sets of the container process
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Capabilities {
+    pub bounding: CapSet,
+    pub effective: CapSet,
+    pub permitted: CapSet,
+    pub inheritable: CapSet,
+    pub ambient: CapSet,
+}
+
+impl Default for Capabilities {
+    /// Allowlist of Docker, nothing is inheritable or ambient
+    fn default() -> Self {
+        let caps = CapSet::from_iter(CAPABILITIES_DEFAULT);
+        Self {
+            bounding: caps,
+            effective: caps,
+            permitted: caps,
+            inheritable: CapSet::empty(),
+            ambient: CapSet::empty(),
+        }
+    }
+}
+
+impl Capabilities {
+    pub fn empty() -> Self {
+        Self {
+            bounding: CapSet::empty(),
+            effective: CapSet::empty(),
+            permitted: CapSet::empty(),
+            inheritable: CapSet::empty(),
+            ambient: CapSet::empty(),
+        }
+    }
+
+    /// Drop then add capabilities like Docker, `ALL` of `drop` only keeps the added ones.
+    /// A user other than root loses its permitted capabilities on execve, so the added
+    /// ones are raised in its ambient set as well
+    pub fn apply(&mut self, add: &[CapName], drop: &[CapName], uid: u32) {
+        if drop.contains(&CapName::All) {
+            *self = Self::empty();
+        }
+        for name in drop {
+            if let CapName::Cap(cap) = name {
+                for set in self.sets_mut() {
+                    set.drop(*cap);
+                }
+            }
+        }
+
+        let added = match add.contains(&CapName::All) {
+            true => Cap::probe_supported(),
+            false => add
+                .iter()
+                .filter_map(|name| match name {
+                    CapName::Cap(cap) => Some(*cap),
+                    CapName::All => None,
+                })
+                .collect(),
+        };
+        self.bounding |= added;
+        self.effective |= added;
+        self.permitted |= added;
+        if uid != 0 {
+            self.inheritable |= added;
+            self.ambient |= added;
+        }
+    }
+
+    fn sets_mut(&mut self) -> [&mut CapSet; 5] {
+        [
+            &mut self.bounding,
+            &mut self.effective,
+            &mut self.permitted,
+            &mut self.inheritable,
+            &mut self.ambient,
+        ]
+    }
+}
+
+/// Drop capabilities out of the bounding set while still privileged,
+/// the permitted ones are kept when switching to another user
+pub fn restrict_bounding_set(caps: &Capabilities) -> Result<(), ErrorCode> {
+    log::debug!("Restricting bounding set of capabilities ...");
+
+    for cap in Cap::probe_supported().iter() {
+        if !caps.bounding.has(cap) {
+            bounding::drop(cap).map_err(|e| {
+                log::error!("Cannot drop {} from bounding set: {}", cap, e);
+                ErrorCode::CapabilitiesError(1)
+            })?;
+        }
+    }
+    prctl::set_keepcaps(true).map_err(|e| {
+        log::error!("Cannot keep capabilities: {}", e);
+        ErrorCode::CapabilitiesError(2)
+    })
+}
+
+/// Apply the other capability sets, once switched to the user of the container
+pub fn set_capabilities(caps: &Capabilities) -> Result<(), ErrorCode> {
+    log::debug!("Setting capabilities ...");
+
+    let mut state = CapState::empty();
+    state.effective = caps.effective;
+    state.permitted = caps.permitted;
+    state.inheritable = caps.inheritable;
+    state.set_current().map_err(|e| {
+        log::error!("Cannot set capabilities: {}", e);
+        ErrorCode::CapabilitiesError(2)
+    })?;
+
+    let res = ambient::clear().and_then(|_| caps.ambient.iter().try_for_each(ambient::raise));
+    if let Err(e) = res {
+        log::error!("Cannot raise ambient capabilities: {}", e);
+        return Err(ErrorCode::CapabilitiesError(3));
+    }
+    let _ = prctl::set_keepcaps(false);
+
+    match FullCapState::get_current() {
+        Ok(state) => {
+            log::debug!("Bounding capabilities: {}", format_caps(state.bounding));
+            log::debug!("Effective capabilities: {}", format_caps(state.effective));
+            log::debug!("Permitted capabilities: {}", format_caps(state.permitted));
+            log::debug!(
+                "Inheritable capabilities: {}",
+                format_caps(state.inheritable)
+            );
+            log::debug!("Ambient capabilities: {}", format_caps(state.ambient));
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("Cannot get capabilities: {}", e);
+            Err(ErrorCode::CapabilitiesError(0))
+        }
+    }
+}
 
-    if let Ok(mut caps) = FullCapState::get_current() {
-        caps.bounding
-            .drop_all(CAPABILITIES_DROP.iter().map(|&cap| cap));
-        caps.inheritable
-            .drop_all(CAPABILITIES_DROP.iter().map(|&cap| cap));
-        Ok(())
-    } else {
-        Err(ErrorCode::CapabilitiesError(0))
+fn format_caps(caps: CapSet) -> String {
+    if caps.is_empty() {
+        return String::from("none");
     }
+    caps.iter()
+        .map(|cap| cap.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
 }
@@ -1,30 +1,424 @@
 use crate::error::ErrorCode;
-use cgroups_rs::{cgroup_builder::CgroupBuilder, hierarchies::V2, CgroupPid, MaxValue};
-use nix::unistd::Pid;
-use rlimit::{setrlimit, Resource};
-use std::fs::{canonicalize, remove_dir};
-
-const KMEM_LIMIT: i64 = 1024 * 1024 * 1024;
-const MEM_LIMIT: i64 = KMEM_LIMIT;
-const MAX_PID: MaxValue = MaxValue::Value(64);
+use cgroups_rs::{
+    cgroup_builder::CgroupBuilder,
+    hierarchies::V2,
+    memory::{MemController, SetMemory},
+    CgroupPid, MaxValue,
+};
+use nix::unistd::{getuid, Pid};
+use rlimit::{prlimit, Resource};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, canonicalize, remove_dir},
+    io,
+    path::Path,
+    str::FromStr,
+};
+
+const CGROUP_ROOT: &str = "/sys/fs/cgroup";
+const PROC_SELF_CGROUP: &str = "/proc/self/cgroup";
+const SYS_DEV_BLOCK: &str = "/sys/dev/block";
+// parent of rootless containers in the delegated subtree
+const ROOTLESS_CGROUP: &str = "curn";
+const MEM_LIMIT: i64 = 1024 * 1024 * 1024;
+const CPU_SHARES: u64 = 256;
+const MAX_PID: i64 = 64;
 const NOFILE_RLIMIT: u64 = 64;
 
-pub fn restrict_resources(hostname: &String, pid: Pid) -> Result<(), ErrorCode> {
-    log::debug!("Restricting resources for hostname {}", hostname);
+/// `-1` stands for `max` (unlimited) in all the limits below
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ResourceLimits {
+    pub memory_max: i64,
+    pub memory_high: Option<i64>,
+    pub memory_swap: Option<i64>,
+    pub cpu_shares: u64,
+    pub cpu_quota: Option<i64>,
+    pub cpu_period: Option<u64>,
+    pub cpuset_cpus: Option<String>,
+    pub cpuset_mems: Option<String>,
+    pub pids_max: i64,
+    /// `io.weight`, left to the kernel if not given
+    pub io_weight: Option<u16>,
+    pub io_max: Vec<IoMax>,
+    pub rlimits: Vec<Rlimit>,
+}
 
-    let cgs = CgroupBuilder::new(&hostname)
-        .cpu()
-        .shares(256)
-        .done()
-        .memory()
-        .kernel_memory_limit(KMEM_LIMIT)
-        .memory_hard_limit(MEM_LIMIT)
+/// Throttle of a block device, same format as `io.max`, e.g. `8:0 rbps=1048576 wiops=120`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct IoMax {
+    pub major: u64,
+    pub minor: u64,
+    pub rbps: Option<u64>,
+    pub wbps: Option<u64>,
+    pub riops: Option<u64>,
+    pub wiops: Option<u64>,
+}
+
+/// Resource limit of the container process, e.g. `NOFILE=1024:2048`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rlimit {
+    pub resource: String,
+    pub soft: u64,
+    pub hard: u64,
+}
+
+impl Default for ResourceLimits {
+    fn default() -> Self {
+        Self {
+            memory_max: MEM_LIMIT,
+            memory_high: None,
+            memory_swap: None,
+            cpu_shares: CPU_SHARES,
+            cpu_quota: None,
+            cpu_period: None,
+            cpuset_cpus: None,
+            cpuset_mems: None,
+            pids_max: MAX_PID,
+            io_weight: None,
+            io_max: Vec::new(),
+            rlimits: vec![Rlimit {
+                resource: "RLIMIT_NOFILE".to_string(),
+                soft: NOFILE_RLIMIT,
+                hard: NOFILE_RLIMIT,
+            }],
+        }
+    }
+}
+
+impl ResourceLimits {
+    /// Check values which the kernel would refuse before any cgroup is created
+    pub fn validate(&self) -> Result<(), ErrorCode> {
+        for (value, name) in [
+            (Some(self.memory_max), "memory"),
+            (self.memory_high, "memory-high"),
+            (self.cpu_quota, "cpu-quota"),
+            (Some(self.pids_max), "pids-max"),
+        ] {
+            if value.is_some_and(|v| v < -1 || v == 0) {
+                log::error!("Limit `{}` must be positive or `max`", name);
+                return Err(ErrorCode::ArgumentInvaild(name));
+            }
+        }
+        // memory.swap.max of 0 disables swap
+        if self.memory_swap.is_some_and(|v| v < -1) {
+            log::error!("Limit `memory-swap` must be positive, 0 or `max`");
+            return Err(ErrorCode::ArgumentInvaild("memory-swap"));
+        }
+        if let Some(high) = self.memory_high {
+            if self.memory_max != -1 && (high == -1 || high > self.memory_max) {
+                log::error!("memory.high must not be greater than memory.max");
+                return Err(ErrorCode::ArgumentInvaild("memory-high"));
+            }
+        }
+        if !(1..=10000).contains(&self.cpu_shares) {
+            return Err(ErrorCode::ArgumentInvaild("cpu-shares"));
+        }
+        // cpu.max accepts quota and period between 1ms and 1s
+        if self.cpu_quota.is_some_and(|q| q != -1 && q < 1000) {
+            return Err(ErrorCode::ArgumentInvaild("cpu-quota"));
+        }
+        if self
+            .cpu_period
+            .is_some_and(|p| !(1000..=1_000_000).contains(&p))
+        {
+            return Err(ErrorCode::ArgumentInvaild("cpu-period"));
+        }
+        for (value, name) in [
+            (&self.cpuset_cpus, "cpuset-cpus"),
+            (&self.cpuset_mems, "cpuset-mems"),
+        ] {
+            if value.as_ref().is_some_and(|v| !is_cpuset_list(v)) {
+                log::error!("Invalid list `{:?}` for {}", value, name);
+                return Err(ErrorCode::ArgumentInvaild(name));
+            }
+        }
+        if self.io_weight.is_some_and(|w| !(1..=10000).contains(&w)) {
+            return Err(ErrorCode::ArgumentInvaild("io-weight"));
+        }
+        for io_max in self.io_max.iter() {
+            let values = [io_max.rbps, io_max.wbps, io_max.riops, io_max.wiops];
+            // io.max refuses a limit of 0 and devices which are not block devices
+            let device = format!("{}/{}:{}", SYS_DEV_BLOCK, io_max.major, io_max.minor);
+            if values.iter().all(Option::is_none) || values.contains(&Some(0)) {
+                log::error!("Invalid io.max limit `{}`", io_max);
+                return Err(ErrorCode::ArgumentInvaild("io-max"));
+            }
+            if !Path::new(&device).exists() {
+                log::error!("No block device {}:{}", io_max.major, io_max.minor);
+                return Err(ErrorCode::ArgumentInvaild("io-max"));
+            }
+        }
+        for rlimit in self.rlimits.iter() {
+            if Resource::from_str(&rlimit.resource).is_err() || rlimit.soft > rlimit.hard {
+                log::error!("Invalid rlimit {:?}", rlimit);
+                return Err(ErrorCode::ArgumentInvaild("rlimit"));
+            }
+        }
+        Ok(())
+    }
+
+    /// Replace the limit of the same resource if already present
+    pub fn set_rlimit(&mut self, rlimit: Rlimit) {
+        self.rlimits.retain(|r| r.resource != rlimit.resource);
+        self.rlimits.push(rlimit);
+    }
+
+    /// Merge limits of the same device
+    pub fn set_io_max(&mut self, io_max: IoMax) {
+        match self
+            .io_max
+            .iter_mut()
+            .find(|d| d.major == io_max.major && d.minor == io_max.minor)
+        {
+            Some(device) => {
+                device.rbps = io_max.rbps.or(device.rbps);
+                device.wbps = io_max.wbps.or(device.wbps);
+                device.riops = io_max.riops.or(device.riops);
+                device.wiops = io_max.wiops.or(device.wiops);
+            }
+            None => self.io_max.push(io_max),
+        }
+    }
+}
+
+impl IoMax {
+    pub fn new(major: u64, minor: u64) -> Self {
+        Self {
+            major,
+            minor,
+            rbps: None,
+            wbps: None,
+            riops: None,
+            wiops: None,
+        }
+    }
+}
+
+impl fmt::Display for IoMax {
+    /// Line of `io.max`, e.g. `8:0 rbps=1048576 wiops=120`
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.major, self.minor)?;
+        for (key, value) in [
+            ("rbps", self.rbps),
+            ("wbps", self.wbps),
+            ("riops", self.riops),
+            ("wiops", self.wiops),
+        ] {
+            if let Some(value) = value {
+                write!(f, " {}={}", key, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for IoMax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut fields = s.split_ascii_whitespace();
+        let device = fields.next().ok_or("missing device")?;
+        let (major, minor) = device
+            .split_once(':')
+            .ok_or(format!("device `{}` must be MAJOR:MINOR", device))?;
+        let mut io_max = Self::new(
+            major
+                .parse()
+                .map_err(|_| format!("invalid major `{}`", major))?,
+            minor
+                .parse()
+                .map_err(|_| format!("invalid minor `{}`", minor))?,
+        );
+
+        for field in fields {
+            let (key, value) = field
+                .split_once('=')
+                .ok_or(format!("`{}` must be KEY=VALUE", field))?;
+            let value = value
+                .parse::<u64>()
+                .map_err(|_| format!("invalid value `{}`", value))?;
+            match key {
+                "rbps" => io_max.rbps = Some(value),
+                "wbps" => io_max.wbps = Some(value),
+                "riops" => io_max.riops = Some(value),
+                "wiops" => io_max.wiops = Some(value),
+                _ => return Err(format!("unknown key `{}`", key)),
+            }
+        }
+        Ok(io_max)
+    }
+}
+
+impl FromStr for Rlimit {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, limits) = s.split_once('=').ok_or("must be NAME=SOFT[:HARD]")?;
+        let name = name.to_uppercase();
+        let resource = if name.starts_with("RLIMIT_") {
+            name
+        } else {
+            format!("RLIMIT_{}", name)
+        };
+        if Resource::from_str(&resource).is_err() {
+            return Err(format!("unknown resource `{}`", resource));
+        }
+
+        let (soft, hard) = limits.split_once(':').unwrap_or((limits, limits));
+        Ok(Self {
+            resource,
+            soft: parse_rlimit_value(soft)?,
+            hard: parse_rlimit_value(hard)?,
+        })
+    }
+}
+
+/// Parse a size in bytes with an optional binary suffix (K, M, G, T), or `max` as -1
+pub fn parse_size(s: &str) -> Result<i64, String> {
+    if s == "max" {
+        return Ok(-1);
+    }
+
+    let (number, unit) = match s.find(|c: char| !c.is_ascii_digit()) {
+        Some(i) => s.split_at(i),
+        None => (s, ""),
+    };
+    let shift = match unit.to_uppercase().trim_end_matches('B') {
+        "" => 0,
+        "K" => 10,
+        "M" => 20,
+        "G" => 30,
+        "T" => 40,
+        _ => return Err(format!("unknown unit `{}`", unit)),
+    };
+    number
+        .parse::<i64>()
+        .ok()
+        .and_then(|n| n.checked_mul(1 << shift))
+        .ok_or(format!("invalid size `{}`", s))
+}
+
+/// Parse a positive number, or `max` as -1
+pub fn parse_max_value(s: &str) -> Result<i64, String> {
+    if s == "max" {
+        return Ok(-1);
+    }
+    s.parse::<i64>()
+        .map_err(|_| format!("invalid value `{}`", s))
+}
+
+fn parse_rlimit_value(s: &str) -> Result<u64, String> {
+    if s == "unlimited" {
+        return Ok(rlimit::INFINITY);
+    }
+    s.parse::<u64>()
+        .map_err(|_| format!("invalid limit `{}`", s))
+}
+
+/// e.g. `0-3,6`
+fn is_cpuset_list(s: &str) -> bool {
+    s.split(',').all(|range| {
+        let mut bounds = range.splitn(2, '-');
+        bounds.all(|b| !b.is_empty() && b.chars().all(|c| c.is_ascii_digit()))
+    })
+}
+
+fn to_max_value(value: i64) -> MaxValue {
+    match value {
+        -1 => MaxValue::Max,
+        v => MaxValue::Value(v),
+    }
+}
+
+/// Return the cgroup of a container relative to the cgroup root, rootless containers
+/// live in the subtree which systemd delegates to the user, e.g.
+/// user.slice/user-1000.slice/user@1000.service/curn/<hostname>
+pub fn cgroup_path(hostname: &str, rootless: bool) -> Option<String> {
+    if !rootless {
+        return Some(hostname.to_string());
+    }
+
+    let delegated = format!("user@{}.service", getuid());
+    let own = fs::read_to_string(PROC_SELF_CGROUP).unwrap_or_default();
+    let path = own
+        .lines()
+        .find_map(|line| line.strip_prefix("0::/"))
+        .and_then(|path| {
+            let components = path.split('/').collect::<Vec<_>>();
+            let n = components.iter().position(|c| *c == delegated)?;
+            Some(components[..=n].join("/"))
+        });
+    match path {
+        Some(path) => Some(format!("{}/{}/{}", path, ROOTLESS_CGROUP, hostname)),
+        None => {
+            log::warn!(
+                "No cgroup delegated to the current user ({} not found in {}), \
+                 resources are only limited by rlimits",
+                delegated,
+                PROC_SELF_CGROUP
+            );
+            None
+        }
+    }
+}
+
+/// Put the container into its cgroup, then set its rlimits
+pub fn restrict_resources(
+    cgroup: Option<&str>,
+    pid: Pid,
+    limits: &ResourceLimits,
+) -> Result<(), ErrorCode> {
+    if let Some(cgroup) = cgroup {
+        create_cgroup(cgroup, pid, limits)?;
+    }
+
+    // rlimits are set on the child process, not on curn itself
+    for rlimit in limits.rlimits.iter() {
+        let resource =
+            Resource::from_str(&rlimit.resource).map_err(|_| ErrorCode::CgroupError(2))?;
+        if let Err(e) = prlimit(
+            pid.as_raw(),
+            resource,
+            Some((rlimit.soft, rlimit.hard)),
+            None,
+        ) {
+            log::error!("Cannot set {}: {}", rlimit.resource, e);
+            return Err(ErrorCode::CgroupError(2));
+        }
+    }
+
+    Ok(())
+}
+
+fn create_cgroup(cgroup: &str, pid: Pid, limits: &ResourceLimits) -> Result<(), ErrorCode> {
+    log::debug!("Restricting resources by cgroup {}", cgroup);
+
+    let mut cpu = CgroupBuilder::new(cgroup).cpu().shares(limits.cpu_shares);
+    if let Some(quota) = limits.cpu_quota {
+        cpu = cpu.quota(quota);
+    }
+    if let Some(period) = limits.cpu_period {
+        cpu = cpu.period(period);
+    }
+    if let Some(cpus) = &limits.cpuset_cpus {
+        cpu = cpu.cpus(cpus.clone());
+    }
+    if let Some(mems) = &limits.cpuset_mems {
+        cpu = cpu.mems(mems.clone());
+    }
+
+    let mut memory = cpu.done().memory().memory_hard_limit(limits.memory_max);
+    if let Some(swap) = limits.memory_swap {
+        memory = memory.memory_swap_limit(swap);
+    }
+
+    let pids = memory
         .done()
         .pid()
-        .maximum_number_of_processes(MAX_PID)
-        .done()
-        .blkio()
-        .weight(50)
+        .maximum_number_of_processes(to_max_value(limits.pids_max));
+
+    let cgs = pids
         .done()
         // .devices()
         // .device(
@@ -40,7 +434,35 @@ pub fn restrict_resources(hostname: &String, pid: Pid) -> Result<(), ErrorCode>
         // )
         // .done()
         .build(Box::new(V2::new()))
-        .map_err(|_| ErrorCode::CgroupError(0))?;
+        .map_err(|e| {
+            // a delegated subtree may lack some controllers, e.g. cpu and io
+            log::error!("Cannot create cgroup {}: {}", cgroup, e);
+            ErrorCode::CgroupError(0)
+        })?;
+
+    // memory.high has no setter in the builder
+    if let Some(high) = limits.memory_high {
+        let memory: &MemController = cgs.controller_of().ok_or(ErrorCode::CgroupError(2))?;
+        let mem = SetMemory {
+            low: None,
+            high: Some(to_max_value(high)),
+            min: None,
+            max: None,
+        };
+        if let Err(e) = memory.set_mem(mem) {
+            log::error!("{}", e);
+            return Err(ErrorCode::CgroupError(2));
+        }
+    }
+
+    // the io controller of cgroups-rs ignores write errors, e.g. of an unknown device
+    let dir = Path::new(CGROUP_ROOT).join(cgroup);
+    if let Some(weight) = limits.io_weight {
+        write_cgroup_file(&dir.join("io.weight"), &format!("default {}", weight))?;
+    }
+    for device in limits.io_max.iter() {
+        write_cgroup_file(&dir.join("io.max"), &device.to_string())?;
+    }
 
     let pid: u64 = pid
         .as_raw()
@@ -51,25 +473,130 @@ pub fn restrict_resources(hostname: &String, pid: Pid) -> Result<(), ErrorCode>
         return Err(ErrorCode::CgroupError(1));
     }
 
-    if let Err(e) = setrlimit(Resource::NOFILE, NOFILE_RLIMIT, NOFILE_RLIMIT) {
-        log::error!("{}", e);
-        return Err(ErrorCode::CgroupError(2));
-    }
-
     Ok(())
 }
 
-pub fn clean_cgroups(hostname: &String) -> Result<(), ErrorCode> {
-    log::debug!("Cleaning cgruops: /sys/fs/cgroup/{}/", hostname);
+fn write_cgroup_file(path: &Path, value: &str) -> Result<(), ErrorCode> {
+    fs::write(path, value).map_err(|e| {
+        log::error!("Cannot write `{}` to {:?}: {}", value, path, e);
+        ErrorCode::CgroupError(2)
+    })
+}
 
-    match canonicalize(format!("/sys/fs/cgroup/{}/", hostname)) {
+pub fn clean_cgroups(cgroup: Option<&str>) -> Result<(), ErrorCode> {
+    let Some(cgroup) = cgroup else {
+        return Ok(());
+    };
+    log::debug!("Cleaning cgruops: {}/{}/", CGROUP_ROOT, cgroup);
+
+    match canonicalize(format!("{}/{}/", CGROUP_ROOT, cgroup)) {
         Ok(d) => match remove_dir(d) {
             Ok(_) => Ok(()),
             Err(_) => Err(ErrorCode::CgroupError(3)),
         },
+        // the container failed before its cgroup was created
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
         Err(e) => {
             log::error!("Error while canonicalize path: {}", e);
             Err(ErrorCode::CgroupError(4))
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn size_suffixes_are_binary() {
+        assert_eq!(parse_size("512"), Ok(512));
+        assert_eq!(parse_size("4k"), Ok(4096));
+        assert_eq!(parse_size("64M"), Ok(64 << 20));
+        assert_eq!(parse_size("2gb"), Ok(2 << 30));
+        assert_eq!(parse_size("1T"), Ok(1 << 40));
+        assert_eq!(parse_size("max"), Ok(-1));
+        assert!(parse_size("MAX").is_err());
+        assert!(parse_size("1.5G").is_err());
+        assert!(parse_size("-1").is_err());
+    }
+
+    #[test]
+    fn size_suffix_overflow() {
+        // 2^33 GiB is 2^63 bytes, one more than i64::MAX
+        assert_eq!(parse_size("8589934591G"), Ok(i64::MAX - (1 << 30) + 1));
+        assert!(parse_size("8589934592G").is_err());
+        assert!(parse_size("8388608T").is_err());
+        assert_eq!(parse_size(&i64::MAX.to_string()), Ok(i64::MAX));
+        assert!(parse_size("9223372036854775808").is_err());
+    }
+
+    #[test]
+    fn io_max_keys() {
+        let io_max = "8:16 wbps=1048576 riops=100".parse::<IoMax>().unwrap();
+        assert_eq!((io_max.major, io_max.minor), (8, 16));
+        assert_eq!((io_max.wbps, io_max.riops), (Some(1048576), Some(100)));
+        assert_eq!((io_max.rbps, io_max.wiops), (None, None));
+        // like the kernel, the last value of a key wins
+        let io_max = "8:0 rbps=1 rbps=2".parse::<IoMax>().unwrap();
+        assert_eq!(io_max.rbps, Some(2));
+    }
+
+    #[test]
+    fn io_max_unknown_key() {
+        assert_eq!(
+            "8:0 rbps=1 wbsp=2".parse::<IoMax>(),
+            Err("unknown key `wbsp`".to_string())
+        );
+        assert!("8:0 RBPS=1".parse::<IoMax>().is_err());
+        assert!("8:0 rbps".parse::<IoMax>().is_err());
+        assert!("8:0 rbps=max".parse::<IoMax>().is_err());
+        assert!("8 rbps=1".parse::<IoMax>().is_err());
+    }
+
+    #[test]
+    fn swap_may_be_disabled() {
+        let limits = |memory_swap| ResourceLimits {
+            memory_swap: Some(memory_swap),
+            ..ResourceLimits::default()
+        };
+        assert!(limits(0).validate().is_ok());
+        assert!(limits(-1).validate().is_ok());
+        assert!(limits(-2).validate().is_err());
+    }
+
+    #[test]
+    fn io_limits_are_validated() {
+        let limits = |io_max: &str| ResourceLimits {
+            io_max: vec![io_max.parse().unwrap()],
+            ..ResourceLimits::default()
+        };
+        assert!(limits("8:0").validate().is_err());
+        assert!(limits("8:0 rbps=0").validate().is_err());
+        // no block device has major 0
+        assert!(limits("0:0 rbps=1").validate().is_err());
+        let weight = |io_weight| ResourceLimits {
+            io_weight: Some(io_weight),
+            ..ResourceLimits::default()
+        };
+        assert!(weight(10000).validate().is_ok());
+        assert!(weight(0).validate().is_err());
+        assert_eq!(
+            "8:16 wbps=1 riops=2".parse::<IoMax>().unwrap().to_string(),
+            "8:16 wbps=1 riops=2"
+        );
+    }
+
+    #[test]
+    fn io_max_merged_by_device() {
+        let mut limits = ResourceLimits::default();
+        limits.set_io_max("8:0 rbps=1 wiops=5".parse().unwrap());
+        limits.set_io_max("8:16 rbps=3".parse().unwrap());
+        limits.set_io_max("8:0 rbps=2 wbps=4".parse().unwrap());
+        assert_eq!(limits.io_max.len(), 2);
+        let io_max = &limits.io_max[0];
+        assert_eq!(
+            (io_max.rbps, io_max.wbps, io_max.wiops),
+            (Some(2), Some(4), Some(5))
+        );
+    }
+}
@@ -1,31 +1,62 @@
-use std::ffi::CString;
+use std::{
+    ffi::{CStr, CString, OsStr},
+    os::fd::AsRawFd,
+    os::unix::ffi::{OsStrExt, OsStringExt},
+    path::Path,
+};
 
 use crate::{
-    capabilities::set_capabilities, config::ContainerOpts, error::ErrorCode,
-    hosthname::set_container_hostname, mount::set_mounts, syscall::set_syscalls,
-    user_namespace::set_user_namespace,
+    capabilities::{restrict_bounding_set, set_capabilities},
+    config::ContainerOpts,
+    error::ErrorCode,
+    hosthname::set_container_hostname,
+    init::run_init,
+    ipc::{recv_bool, send_fd},
+    mount::set_mounts,
+    network::set_container_network,
+    syscall::set_syscalls,
+    tty::set_controlling_terminal,
+    user_namespace::{set_user_namespace, switch_user},
 };
 use nix::{
+    errno::Errno,
     libc::c_int,
     sched::{clone, CloneFlags},
     sys::signal::Signal,
-    unistd::{close, execve, sleep, Pid},
+    unistd::{access, chdir, close, execve, AccessFlags, Pid},
 };
 
 const STACK_SIZE: usize = 1024 * 1024; // 1MB stack of child process
 
 fn setup_container_configuration(config: &ContainerOpts) -> Result<(), ErrorCode> {
+    // a rootless child is cloned into its user namespace, ids must be mapped before mounting
+    if config.rootless {
+        set_user_namespace(config.fd, true)?;
+    }
     set_container_hostname(&config.hostname)?;
-    set_mounts(
-        &config.container_id,
-        &config.mount_dir,
-        &config.root_path,
-        &config.add_paths,
-        config.tool_dir.as_ref(),
+    set_mounts(config)?;
+    set_container_network(config.fd, config.network.as_ref())?;
+    if !config.rootless {
+        set_user_namespace(config.fd, false)?;
+    }
+    restrict_bounding_set(&config.capabilities)?;
+    switch_user(
+        config.uid,
+        config.gid,
+        &config.groups,
+        config.id_mappings.self_map,
+    )?;
+    set_capabilities(&config.capabilities)?;
+    let listener = set_syscalls(
+        config.seccomp.as_ref(),
+        config.capabilities.bounding,
+        config.seccomp_notify.as_ref(),
+        config.fd,
     )?;
-    set_user_namespace(config.fd, config.uid)?;
-    set_capabilities()?;
-    set_syscalls()?;
+    // only curn may answer the notifications, the command never gets the listener
+    if let Some(listener) = listener {
+        send_fd(config.fd, listener.as_raw_fd())?;
+    }
 
     Ok(())
 }
@@ -37,11 +68,21 @@ fn child(config: ContainerOpts) -> isize {
         }
         Err(e) => {
             log::error!("Error while configuring container: {:?}", e);
-            return -1;
+            return e.get_retcode() as isize;
         }
     }
 
-    if let Err(_) = close(config.fd) {
+    // block until the container is started, a closed socket means it was deleted
+    log::debug!("Waiting for container to be started");
+    match recv_bool(config.fd) {
+        Ok(true) => {}
+        _ => {
+            log::info!("Container was not started, exiting");
+            return ErrorCode::ContainerError(4).get_retcode() as isize;
+        }
+    }
+
+    if close(config.fd).is_err() {
         log::error!("Error while closing socket...");
     }
 
@@ -51,19 +92,63 @@ fn child(config: ContainerOpts) -> isize {
         config.argv
     );
 
-    let environments = vec!["TERM=xterm"]
-        .iter()
-        .map(|&x| CString::new(x).expect("Must be valid"))
-        .collect::<Vec<_>>();
+    if let Some(pty) = &config.pty {
+        if let Err(e) = set_controlling_terminal(pty) {
+            return e.get_retcode() as isize;
+        }
+    }
+
+    if let Err(e) = chdir(&config.cwd) {
+        log::error!(
+            "Cannot change working directory to {:?}: {:?}",
+            config.cwd,
+            e
+        );
+        return ErrorCode::MountError(5).get_retcode() as isize;
+    }
 
-    sleep(1); // sleep 1s to make eBPF program to run first
-    match execve::<CString, CString>(&config.path, &config.argv, &environments) {
+    let path = find_command(&config.path, &config.env);
+    if config.init {
+        return run_init(config.pty.is_some(), || execute(&path, &config));
+    }
+    execute(&path, &config)
+}
+
+fn execute(path: &CStr, config: &ContainerOpts) -> isize {
+    match execve::<CString, CString>(path, &config.argv, &config.env) {
         Ok(_) => 0,
         Err(e) => {
             log::error!("Error while trying to perfoem execve: {:?}", e);
-            -1
+            // same convention as shells: 127 if command not found, 126 if not executable
+            match e {
+                Errno::ENOENT => 127,
+                _ => 126,
+            }
+        }
+    }
+}
+
+/// Search `PATH` of the container for a command without `/`, like execvp does
+fn find_command(command: &CStr, env: &[CString]) -> CString {
+    let name = command.to_bytes();
+    if name.is_empty() || name.contains(&b'/') {
+        return command.to_owned();
+    }
+
+    let path = env
+        .iter()
+        .find_map(|e| e.to_bytes().strip_prefix(b"PATH="))
+        .unwrap_or_default();
+    for dir in path.split(|&b| b == b':').filter(|d| !d.is_empty()) {
+        let candidate = Path::new(OsStr::from_bytes(dir)).join(OsStr::from_bytes(name));
+        if candidate.is_file() && access(&candidate, AccessFlags::X_OK).is_ok() {
+            log::debug!("Found command {:?} in PATH", candidate);
+            return CString::new(candidate.into_os_string().into_vec())
+                .unwrap_or(command.to_owned());
         }
     }
+
+    command.to_owned()
 }
 
 pub fn generate_child_process(config: &ContainerOpts) -> Result<Pid, ErrorCode> {
@@ -77,6 +162,10 @@ pub fn generate_child_process(config: &ContainerOpts) -> Result<Pid, ErrorCode>
     flags.insert(CloneFlags::CLONE_NEWIPC);
     flags.insert(CloneFlags::CLONE_NEWNET);
     flags.insert(CloneFlags::CLONE_NEWUTS);
+    // the other namespaces are then owned by the new user namespace
+    if config.rootless {
+        flags.insert(CloneFlags::CLONE_NEWUSER);
+    }
 
     unsafe {
         match clone(
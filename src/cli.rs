@@ -1,5 +1,22 @@
-use crate::error::ErrorCode;
-use std::path::PathBuf;
+use crate::{
+    audit::{AuditEvent, AuditRecord},
+    capabilities::CapName,
+    cgroup::{parse_max_value, parse_size, IoMax, ResourceLimits, Rlimit},
+    error::ErrorCode,
+    image::Image,
+    network::{NetworkMode, Subnet, DEFAULT_SUBNET},
+    seccomp::SeccompMode,
+    signals::DEFAULT_GRACE_PERIOD,
+    store::set_store_root,
+    user_namespace::IdMap,
+};
+use nix::sys::signal::Signal;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::{Duration, SystemTime},
+};
 use structopt::StructOpt;
 
 #[derive(Debug, StructOpt)]
@@ -9,31 +26,417 @@ use structopt::StructOpt;
 )]
 pub struct Args {
     /// Activate debug mode
-    #[structopt(short, long)]
+    #[structopt(short, long, global = true)]
     debug: bool,
 
-    /// Command to execute inside the container
-    #[structopt(short, long)]
-    pub command: String,
+    /// Directory keeping images, root filesystems of containers and audit logs,
+    /// /var/lib/curn by default
+    #[structopt(parse(from_os_str), long, global = true)]
+    root: Option<PathBuf>,
+
+    #[structopt(subcommand)]
+    pub subcommand: Command,
+}
+
+#[derive(Debug, StructOpt)]
+pub enum Command {
+    /// Create a container, the command is not executed until `start`
+    Create(CreateArgs),
+
+    /// Start the command of a created container
+    Start {
+        /// ID of the container
+        container_id: String,
+    },
+
+    /// Output the state of a container
+    State {
+        /// ID of the container
+        container_id: String,
+    },
+
+    /// Send a signal to the init process of a container
+    Kill {
+        /// ID of the container
+        container_id: String,
+
+        /// Signal to send, e.g. SIGKILL, KILL or 9
+        #[structopt(default_value = "SIGTERM", parse(try_from_str = parse_signal))]
+        signal: Signal,
+    },
+
+    /// Release all resources held by a container
+    Delete {
+        /// ID of the container
+        container_id: String,
+
+        /// Kill the container if it is still running
+        #[structopt(short, long)]
+        force: bool,
+    },
+
+    /// Create and start a container, then wait for it to exit
+    Run(CreateArgs),
+
+    /// Output the commands executed by a container, traced by eBPF,
+    /// and the syscalls caught by seccomp
+    Logs(LogsArgs),
+
+    /// Manage the local images run by `--image`, list them if no subcommand is given
+    #[structopt(alias = "images")]
+    Image {
+        #[structopt(subcommand)]
+        command: Option<ImageCommand>,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+pub enum ImageCommand {
+    /// Import a root filesystem tarball, an OCI image layout or a `docker save` archive,
+    /// gzip or zstd compressed archives are accepted as well
+    Import {
+        /// Archive or OCI layout directory to import
+        #[structopt(parse(from_os_str))]
+        path: PathBuf,
+
+        /// Name of the image, e.g. ubuntu or ubuntu:24.04
+        #[structopt(short, long)]
+        name: String,
+    },
+
+    /// List the images with their size and layers
+    #[structopt(alias = "ls")]
+    List,
+
+    /// Output an image, its layers and the config of its command
+    Inspect {
+        /// Name of the image
+        name: String,
+    },
+
+    /// Remove images, their layers are kept until `prune`
+    #[structopt(alias = "rm")]
+    Remove {
+        /// Name of the image
+        #[structopt(required = true)]
+        names: Vec<String>,
+    },
+
+    /// Remove the layers used neither by an image nor by a container
+    Prune,
+}
+
+#[derive(Debug, StructOpt)]
+pub struct CreateArgs {
+    /// ID of the container, generated randomly if not given
+    #[structopt(long = "id")]
+    pub container_id: Option<String>,
+
+    /// OCI bundle directory whose `config.json` defines the container
+    #[structopt(
+        parse(from_os_str),
+        short,
+        long,
+        conflicts_with_all = &["command", "uid", "mount-dir", "image"]
+    )]
+    pub bundle: Option<PathBuf>,
+
+    /// Imported image to run, its layers are stacked as an overlay and its config gives
+    /// the default command, environment, working directory and user
+    #[structopt(long, conflicts_with = "mount-dir")]
+    pub image: Option<String>,
+
+    /// Command and its arguments to execute inside the container, given after `--`
+    #[structopt(last = true, required_unless_one = &["bundle", "image"])]
+    pub command: Vec<String>,
+
+    /// Set an environment variable, `KEY` alone takes the value from the current environment
+    #[structopt(short, long = "env", parse(try_from_str = parse_env))]
+    pub env: Vec<String>,
+
+    /// Read environment variables from a file of `KEY=VAL` lines
+    #[structopt(parse(from_os_str), long)]
+    pub env_file: Vec<PathBuf>,
+
+    /// Working directory of the command inside the container
+    #[structopt(parse(from_os_str), short, long)]
+    pub workdir: Option<PathBuf>,
 
     /// User ID to create inside the container
-    #[structopt(short, long)]
-    pub uid: u32,
+    #[structopt(short, long, required_unless_one = &["bundle", "image"])]
+    pub uid: Option<u32>,
+
+    /// Group ID of the command inside the container, same as the user ID by default
+    #[structopt(short, long, conflicts_with = "bundle")]
+    pub gid: Option<u32>,
+
+    /// Supplementary group of the command inside the container
+    #[structopt(long = "group-add", conflicts_with = "bundle")]
+    pub groups: Vec<u32>,
+
+    /// UID range mapped into the container as CONTAINER_ID:HOST_ID:SIZE,
+    /// allocated from /etc/subuid if not given
+    #[structopt(long = "uidmap")]
+    pub uid_map: Vec<IdMap>,
+
+    /// GID range mapped into the container as CONTAINER_ID:HOST_ID:SIZE,
+    /// allocated from /etc/subgid if not given
+    #[structopt(long = "gidmap")]
+    pub gid_map: Vec<IdMap>,
+
+    /// Add a capability to the default ones, e.g. NET_ADMIN or ALL
+    #[structopt(long)]
+    pub cap_add: Vec<CapName>,
+
+    /// Drop a capability from the default ones, e.g. NET_RAW or ALL
+    #[structopt(long)]
+    pub cap_drop: Vec<CapName>,
+
+    /// Seccomp filter of the container, `default` is the built-in profile, `allowlist` and
+    /// `allowlist-kill` only allow syscalls of typical userland, `unconfined` filters nothing
+    #[structopt(long)]
+    pub seccomp: Option<SeccompMode>,
+
+    /// Seccomp profile in the JSON format of Docker or OCI
+    #[structopt(parse(from_os_str), long, conflicts_with = "seccomp")]
+    pub seccomp_profile: Option<PathBuf>,
+
+    /// Record the syscalls refused with an errno by seccomp in the audit log, see `curn logs`
+    #[structopt(long)]
+    pub seccomp_log: bool,
+
+    /// Training run: allow and record the syscalls seccomp would refuse, then write
+    /// a profile which allows them to the given file once the container exits
+    #[structopt(parse(from_os_str), long, conflicts_with = "seccomp-log")]
+    pub seccomp_learn: Option<PathBuf>,
+
+    /// Hide a path from the container, e.g. /proc/kcore, in addition to the default ones
+    #[structopt(parse(from_os_str), long = "mask-path")]
+    pub mask_paths: Vec<PathBuf>,
+
+    /// Make a path read-only in the container, e.g. /proc/sys, in addition to the default ones
+    #[structopt(parse(from_os_str), long = "readonly-path")]
+    pub readonly_paths: Vec<PathBuf>,
+
+    /// Expose a masked or read-only path again, ALL exposes every one of them
+    #[structopt(parse(from_os_str), long = "unmask-path")]
+    pub unmask_paths: Vec<PathBuf>,
 
     /// Directory to mount as root of the container
-    #[structopt(parse(from_os_str), short = "m", long = "mount")]
-    pub mount_dir: PathBuf,
+    #[structopt(
+        parse(from_os_str),
+        short = "m",
+        long = "mount",
+        required_unless_one = &["bundle", "image"]
+    )]
+    pub mount_dir: Option<PathBuf>,
+
+    /// Mount the root directory as read-only lower layer of an overlay
+    #[structopt(long)]
+    pub overlay: bool,
+
+    /// Read-only layer stacked on top of the root directory, the last one is topmost,
+    /// implies --overlay
+    #[structopt(parse(from_os_str), long = "layer")]
+    pub layers: Vec<PathBuf>,
+
+    /// Keep the upper layer of the overlay when the container is deleted
+    #[structopt(long)]
+    pub keep_upper: bool,
+
+    /// Mount the root filesystem read-only, volumes and tmpfs mounts stay writable,
+    /// e.g. add `--tmpfs /tmp` for scratch space
+    #[structopt(long)]
+    pub read_only: bool,
+
+    /// Run a minimal init as PID 1 which reaps zombies and forwards signals to the command
+    #[structopt(long)]
+    pub init: bool,
+
+    /// Allocate a pseudo-terminal for the command, only with `run`
+    #[structopt(long)]
+    pub tty: bool,
+
+    /// Time given to the container to exit after a stop signal is forwarded to it,
+    /// it is killed by SIGKILL afterwards, e.g. 10s or 1m
+    #[structopt(long, default_value = DEFAULT_GRACE_PERIOD, parse(try_from_str = humantime::parse_duration))]
+    pub grace_period: Duration,
+
+    #[structopt(flatten)]
+    pub resources: ResourceArgs,
+
+    /// Network of the container, `bridge` connects it to `curn0`, `none` only has loopback,
+    /// `bridge` by default, or `none` in rootless mode
+    #[structopt(long)]
+    pub network: Option<NetworkMode>,
+
+    /// Subnet of the bridge network which container addresses are allocated from
+    #[structopt(long, default_value = DEFAULT_SUBNET)]
+    pub subnet: Subnet,
+
+    /// Bind a host file or directory, `HOST:CONTAINER[:OPTIONS]` where options are
+    /// comma separated among ro, rw, rshared, rslave, rprivate, nosuid, nodev and noexec
+    #[structopt(short = "v", long = "volume", alias = "add")]
+    pub volumes: Vec<String>,
+
+    /// `-a` of the former `--add`, same as --volume
+    #[structopt(short = "a", hidden = true)]
+    pub add_volumes: Vec<String>,
 
-    /// Mount additional directories inside the container
-    #[structopt(parse(from_os_str), short = "a", long = "add")]
-    pub add_paths: Vec<PathBuf>,
+    /// Mount a tmpfs, `PATH[:OPTIONS]` where options are comma separated among size=,
+    /// mode=, nr_inodes=, uid=, gid= and the flags of volumes, e.g. /run:size=64m
+    #[structopt(long)]
+    pub tmpfs: Vec<String>,
 
     /// Mount the tool directory inside the container
     #[structopt(parse(from_os_str), short = "t", long = "tool")]
     pub tool_dir: Option<PathBuf>,
 }
 
-// e.g. curnc --debug --command /bin/bash --mount ../ubuntu-fs --uid 0
+#[derive(Debug, StructOpt)]
+pub struct ResourceArgs {
+    /// Memory limit (memory.max), e.g. 512M, 2G or max
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub memory: Option<i64>,
+
+    /// Memory usage throttle limit (memory.high), e.g. 256M or max
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub memory_high: Option<i64>,
+
+    /// Swap limit (memory.swap.max), e.g. 1G or max
+    #[structopt(long, parse(try_from_str = parse_size))]
+    pub memory_swap: Option<i64>,
+
+    /// CPU time in microseconds allowed in each period (cpu.max), or max
+    #[structopt(long, parse(try_from_str = parse_max_value))]
+    pub cpu_quota: Option<i64>,
+
+    /// Length of a CPU period in microseconds (cpu.max)
+    #[structopt(long)]
+    pub cpu_period: Option<u64>,
+
+    /// CPUs the container may run on (cpuset.cpus), e.g. 0-3,6
+    #[structopt(long)]
+    pub cpuset_cpus: Option<String>,
+
+    /// Memory nodes the container may use (cpuset.mems), e.g. 0
+    #[structopt(long)]
+    pub cpuset_mems: Option<String>,
+
+    /// Maximum number of processes (pids.max), or max
+    #[structopt(long, parse(try_from_str = parse_max_value))]
+    pub pids_max: Option<i64>,
+
+    /// Proportional IO weight between 1 and 10000 (io.weight)
+    #[structopt(long)]
+    pub io_weight: Option<u16>,
+
+    /// Throttle of a block device (io.max), e.g. "8:0 rbps=1048576 wiops=120"
+    #[structopt(long)]
+    pub io_max: Vec<IoMax>,
+
+    /// Resource limit of the container process, e.g. NOFILE=1024:2048 or CORE=0
+    #[structopt(long)]
+    pub rlimit: Vec<Rlimit>,
+}
+
+impl ResourceArgs {
+    /// Whether a limit only a cgroup can enforce is given, rlimits are set without one
+    pub fn needs_cgroup(&self) -> bool {
+        self.memory.is_some()
+            || self.memory_high.is_some()
+            || self.memory_swap.is_some()
+            || self.cpu_quota.is_some()
+            || self.cpu_period.is_some()
+            || self.cpuset_cpus.is_some()
+            || self.cpuset_mems.is_some()
+            || self.pids_max.is_some()
+            || self.io_weight.is_some()
+            || !self.io_max.is_empty()
+    }
+
+    /// Override given limits by the ones from command line
+    pub fn apply(self, limits: &mut ResourceLimits) {
+        if let Some(memory) = self.memory {
+            limits.memory_max = memory;
+        }
+        limits.memory_high = self.memory_high.or(limits.memory_high);
+        limits.memory_swap = self.memory_swap.or(limits.memory_swap);
+        limits.cpu_quota = self.cpu_quota.or(limits.cpu_quota);
+        limits.cpu_period = self.cpu_period.or(limits.cpu_period);
+        limits.cpuset_cpus = self.cpuset_cpus.or(limits.cpuset_cpus.take());
+        limits.cpuset_mems = self.cpuset_mems.or(limits.cpuset_mems.take());
+        if let Some(pids_max) = self.pids_max {
+            limits.pids_max = pids_max;
+        }
+        limits.io_weight = self.io_weight.or(limits.io_weight);
+        for io_max in self.io_max {
+            limits.set_io_max(io_max);
+        }
+        for rlimit in self.rlimit {
+            limits.set_rlimit(rlimit);
+        }
+    }
+}
+
+#[derive(Debug, StructOpt)]
+pub struct LogsArgs {
+    /// ID of the container
+    pub container_id: String,
+
+    /// Output the JSON Lines audit records instead of text
+    #[structopt(long)]
+    pub audit: bool,
+
+    /// Only records of the given process
+    #[structopt(long)]
+    pub pid: Option<i32>,
+
+    /// Only records of the given user, as seen by the host
+    #[structopt(long)]
+    pub uid: Option<i32>,
+
+    /// Only records whose executed path contains the given string
+    #[structopt(long)]
+    pub exec: Option<String>,
+
+    /// Only records of syscalls caught by seccomp
+    #[structopt(long, conflicts_with = "exec")]
+    pub seccomp: bool,
+
+    /// Only records since the given time, e.g. 2024-01-01T12:00:00Z
+    #[structopt(long, parse(try_from_str = humantime::parse_rfc3339_weak))]
+    pub since: Option<SystemTime>,
+
+    /// Only records until the given time, e.g. 2024-01-01T13:00:00Z
+    #[structopt(long, parse(try_from_str = humantime::parse_rfc3339_weak))]
+    pub until: Option<SystemTime>,
+
+    /// Only the last given number of records
+    #[structopt(short = "n", long)]
+    pub tail: Option<usize>,
+}
+
+impl LogsArgs {
+    pub fn matches(&self, record: &AuditRecord) -> bool {
+        let time = record.time();
+        self.pid.is_none_or(|pid| record.pid == pid)
+            && self.uid.is_none_or(|uid| record.uid == uid)
+            && self.exec.as_ref().is_none_or(|exec| match &record.event {
+                AuditEvent::Exec { path, .. } => path.contains(exec.as_str()),
+                AuditEvent::Seccomp { .. } => false,
+            })
+            && (!self.seccomp || matches!(record.event, AuditEvent::Seccomp { .. }))
+            && self
+                .since
+                .is_none_or(|since| time.is_some_and(|t| t >= since))
+            && self
+                .until
+                .is_none_or(|until| time.is_some_and(|t| t <= until))
+    }
+}
+
+// e.g. curnc --debug run --mount ../ubuntu-fs --uid 0 -- /bin/bash
 pub fn parse_args() -> Result<Args, ErrorCode> {
     let mut args = Args::from_args();
 
@@ -43,27 +446,213 @@ pub fn parse_args() -> Result<Args, ErrorCode> {
     } else {
         setup_log(log::LevelFilter::Info);
     }
+    set_store_root(args.root.take())?;
 
+    match &mut args.subcommand {
+        // nobody is attached to the terminal of a detached container
+        Command::Create(CreateArgs { tty: true, .. }) => {
+            return Err(ErrorCode::ArgumentInvaild("tty"))
+        }
+        Command::Create(create_args) | Command::Run(create_args) => {
+            validate_create_args(create_args)?
+        }
+        Command::Start { container_id }
+        | Command::State { container_id }
+        | Command::Kill { container_id, .. }
+        | Command::Delete { container_id, .. }
+        | Command::Logs(LogsArgs { container_id, .. }) => validate_container_id(container_id)?,
+        Command::Image {
+            command: Some(ImageCommand::Import { name, .. } | ImageCommand::Inspect { name }),
+        } => validate_image_name(name)?,
+        Command::Image {
+            command: Some(ImageCommand::Remove { names }),
+        } => names
+            .iter()
+            .try_for_each(|name| validate_image_name(name))?,
+        Command::Image { .. } => {}
+    }
+
+    Ok(args)
+}
+
+fn validate_create_args(args: &mut CreateArgs) -> Result<(), ErrorCode> {
     // validate arguments
-    if args.command.is_empty() {
+    if let Some(container_id) = &args.container_id {
+        validate_container_id(container_id)?;
+    }
+    for layer in args.layers.iter_mut() {
+        *layer = validate_layer(layer)?;
+    }
+    if args.workdir.as_ref().is_some_and(|w| !w.is_absolute()) {
+        return Err(ErrorCode::ArgumentInvaild("workdir"));
+    }
+    // variables of files come first so `--env` overrides them
+    let mut env = Vec::new();
+    for env_file in args.env_file.iter() {
+        env.append(&mut read_env_file(env_file)?);
+    }
+    env.append(&mut args.env);
+    args.env = env;
+    args.volumes.append(&mut args.add_volumes);
+    if let Some(bundle) = &args.bundle {
+        if !bundle.is_dir() {
+            return Err(ErrorCode::ArgumentInvaild("bundle"));
+        }
+        return Ok(());
+    }
+    if let Some(image) = &args.image {
+        validate_image_name(image)?;
+        apply_image(args)?;
+    }
+
+    let command = &mut args.command;
+    if command.is_empty() || command[0].is_empty() {
         return Err(ErrorCode::ArgumentInvaild("command"));
     }
-    if !args.mount_dir.exists() || !args.mount_dir.is_dir() {
+    let mount_dir = args.mount_dir.as_ref().expect("mount is required");
+    if !mount_dir.exists() || !mount_dir.is_dir() {
         return Err(ErrorCode::ArgumentInvaild("mount"));
     }
+    if args.overlay || !args.layers.is_empty() {
+        // root directory becomes the lowest layer
+        args.mount_dir =
+            Some(validate_layer(mount_dir).map_err(|_| ErrorCode::ArgumentInvaild("mount"))?);
+    }
 
-    // parse `ecurn` command if tool flag is given
+    // parse `ecurn` command if tool flag is given, e.g. `ecurn lasm` to `/curn/lasm`
     let ecmd = "ecurn";
     let epath = "/curn/";
-    if let Some(_) = args.tool_dir {
-        if let Some(i) = args.command.find(ecmd) {
-            if i == 0 {
-                args.command.replace_range(..ecmd.len() + 1, epath);
-            }
-        }
+    if args.tool_dir.is_some() && command.len() > 1 && command[0] == ecmd {
+        let tool = command.remove(1);
+        command[0] = format!("{}{}", epath, tool);
     }
 
-    Ok(args)
+    Ok(())
+}
+
+/// Layers are joined into overlay options, so they must not contain `:` or `,`
+fn validate_layer(layer: &Path) -> Result<PathBuf, ErrorCode> {
+    let layer = layer
+        .canonicalize()
+        .map_err(|_| ErrorCode::ArgumentInvaild("layer"))?;
+    let valid = layer.is_dir()
+        && layer
+            .to_str()
+            .is_some_and(|p| !p.contains(':') && !p.contains(','));
+    if !valid {
+        return Err(ErrorCode::ArgumentInvaild("layer"));
+    }
+    Ok(layer)
+}
+
+/// Run the layers of the image, its config only fills what the command line leaves out
+fn apply_image(args: &mut CreateArgs) -> Result<(), ErrorCode> {
+    let image = Image::load(args.image.as_deref().expect("image is given"))?;
+    let (mount_dir, mut layers) = image.rootfs()?;
+    // layers of the command line are stacked on top of the image
+    layers.append(&mut args.layers);
+    args.layers = layers;
+    args.mount_dir = Some(mount_dir);
+    // the store must never be written by a container
+    args.overlay = true;
+
+    if args.command.is_empty() {
+        args.command = image.command();
+    }
+    if let Some(env) = &image.config.env {
+        let mut env = env.clone();
+        env.append(&mut args.env);
+        args.env = env;
+    }
+    if args.workdir.is_none() {
+        args.workdir = image
+            .config
+            .working_dir
+            .as_ref()
+            .map(PathBuf::from)
+            .filter(|w| w.is_absolute());
+    }
+    if args.uid.is_none() {
+        let (uid, gid) = image.user()?.unwrap_or((0, None));
+        args.uid = Some(uid);
+        args.gid = args.gid.or(gid);
+    }
+    Ok(())
+}
+
+/// Image name is used as a file name, so restrict it to a safe charset
+fn validate_image_name(name: &str) -> Result<(), ErrorCode> {
+    let valid = !name.is_empty()
+        && !name.starts_with('.')
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | ':'));
+    if !valid {
+        return Err(ErrorCode::ArgumentInvaild("image"));
+    }
+    Ok(())
+}
+
+/// Container ID is used as a directory name, so restrict it to a safe charset
+fn validate_container_id(container_id: &str) -> Result<(), ErrorCode> {
+    let valid = !container_id.is_empty()
+        && !container_id.starts_with('.')
+        && container_id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+    if !valid {
+        return Err(ErrorCode::ArgumentInvaild("id"));
+    }
+    Ok(())
+}
+
+/// Accept `KEY=VAL`, or `KEY` whose value is taken from the current environment
+fn parse_env(env: &str) -> Result<String, String> {
+    let (key, value) = match env.split_once('=') {
+        Some((key, value)) => (key, value.to_string()),
+        None => (
+            env,
+            std::env::var(env).map_err(|_| format!("variable `{}` is not set", env))?,
+        ),
+    };
+    if key.is_empty() {
+        return Err(format!("`{}` has an empty name", env));
+    }
+    Ok(format!("{}={}", key, value))
+}
+
+/// Read `KEY=VAL` lines of an env file, blank lines and lines starting with `#` are skipped
+fn read_env_file(path: &Path) -> Result<Vec<String>, ErrorCode> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        log::error!("Cannot read env file {:?}: {}", path, e);
+        ErrorCode::ArgumentInvaild("env-file")
+    })?;
+
+    content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            parse_env(line).map_err(|e| {
+                log::error!("Invalid line in env file {:?}: {}", path, e);
+                ErrorCode::ArgumentInvaild("env-file")
+            })
+        })
+        .collect()
+}
+
+/// Accept signal as name (`SIGKILL` or `KILL`) or number (`9`)
+fn parse_signal(signal: &str) -> Result<Signal, String> {
+    if let Ok(signo) = signal.parse::<i32>() {
+        return Signal::try_from(signo).map_err(|e| e.to_string());
+    }
+
+    let name = signal.to_uppercase();
+    if name.starts_with("SIG") {
+        Signal::from_str(&name).map_err(|e| e.to_string())
+    } else {
+        Signal::from_str(&format!("SIG{}", name)).map_err(|e| e.to_string())
+    }
 }
 
 fn setup_log(level: log::LevelFilter) {
@@ -72,3 +661,25 @@ fn setup_log(level: log::LevelFilter) {
         .filter(None, level)
         .init();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bundle_conflicts_with_container_args() {
+        let parse = |args: &[&str]| Args::from_iter_safe(["curn", "run"].iter().chain(args));
+        assert!(parse(&["--bundle", "bundle"]).is_ok());
+        assert!(parse(&["--bundle", "bundle", "--mount", "rootfs"]).is_err());
+        assert!(parse(&["--bundle", "bundle", "--uid", "0"]).is_err());
+        assert!(parse(&["--bundle", "bundle", "--image", "ubuntu"]).is_err());
+        assert!(parse(&["--bundle", "bundle", "--", "/bin/sh"]).is_err());
+    }
+
+    #[test]
+    fn image_conflicts_with_mount() {
+        let parse = |args: &[&str]| Args::from_iter_safe(["curn", "run"].iter().chain(args));
+        assert!(parse(&["--image", "ubuntu"]).is_ok());
+        assert!(parse(&["--image", "ubuntu", "--mount", "rootfs"]).is_err());
+    }
+}
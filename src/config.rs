@@ -1,52 +1,339 @@
 use crate::{
-    container::generate_container_id, error::ErrorCode, hosthname::generate_hostname,
-    mount::generate_rootpath,
+    capabilities::Capabilities,
+    cgroup::{cgroup_path, ResourceLimits},
+    container::generate_container_id,
+    error::ErrorCode,
+    hosthname::generate_hostname,
+    mount::{generate_rootpath, Overlay, ProtectedPaths},
+    network::Network,
+    oci::load_spec,
+    seccomp::{SeccompNotify, SeccompProfile},
+    signals::DEFAULT_GRACE_PERIOD,
+    tty::Pty,
+    user_namespace::{is_rootless, IdMappings},
+    volume::{Tmpfs, Volume},
 };
-use std::{ffi::CString, os::fd::RawFd, path::PathBuf};
+use serde::{Deserialize, Serialize};
+use std::{
+    ffi::CString,
+    os::fd::RawFd,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+const DEFAULT_ENV: [&str; 2] = [
+    "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin",
+    "TERM=xterm",
+];
 
-#[derive(Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ContainerOpts {
     pub path: CString,
     pub argv: Vec<CString>,
+    pub env: Vec<CString>,
+    pub cwd: PathBuf,
+    pub init: bool,
     pub uid: u32,
+    pub gid: u32,
+    pub groups: Vec<u32>,
+    pub id_mappings: IdMappings,
+    pub capabilities: Capabilities,
+    /// `None` if seccomp is unconfined
+    pub seccomp: Option<SeccompProfile>,
+    pub seccomp_notify: Option<SeccompNotify>,
     pub mount_dir: PathBuf,
+    #[serde(skip, default = "invalid_fd")]
     pub fd: RawFd,
+    #[serde(skip)]
+    pub pty: Option<Pty>,
     pub hostname: String,
     pub container_id: String,
     pub root_path: String,
-    pub add_paths: Vec<(PathBuf, PathBuf)>,
+    pub overlay: Option<Overlay>,
+    /// Root is remounted read-only once pivoted, volumes and tmpfs mounts stay writable
+    pub readonly_root: bool,
+    pub protected_paths: ProtectedPaths,
+    pub resources: ResourceLimits,
+    /// Relative to the cgroup root, `None` if no cgroup can be created
+    pub cgroup: Option<String>,
+    /// curn runs as an unprivileged user
+    pub rootless: bool,
+    pub grace_period: Duration,
+    pub network: Option<Network>,
+    pub volumes: Vec<Volume>,
+    pub tmpfs: Vec<Tmpfs>,
     pub tool_dir: Option<PathBuf>,
+    pub bundle: Option<PathBuf>,
 }
 
 impl ContainerOpts {
     pub fn new(
-        command: String,
+        container_id: Option<String>,
+        command: Vec<String>,
         uid: u32,
         mount_dir: PathBuf,
         fd: RawFd,
-        add_paths: Vec<(PathBuf, PathBuf)>,
+        volumes: Vec<Volume>,
         tool_dir: Option<PathBuf>,
     ) -> Result<Self, ErrorCode> {
-        let argv: Vec<CString> = command
-            .split_ascii_whitespace()
-            .map(|s| CString::new(s).expect("Cannot read argument"))
-            .collect();
+        let argv = command
+            .iter()
+            .map(|s| CString::new(s.as_str()).map_err(|_| ErrorCode::ArgumentInvaild("command")))
+            .collect::<Result<Vec<_>, _>>()?;
         let path = argv[0].clone();
+        let env = DEFAULT_ENV
+            .iter()
+            .map(|&x| CString::new(x).expect("Must be valid"))
+            .collect();
         let hostname = generate_hostname()?;
-        let container_id = generate_container_id()?;
+        let container_id = match container_id {
+            Some(container_id) => container_id,
+            None => generate_container_id()?,
+        };
         let root_path = generate_rootpath(&container_id)?;
+        let rootless = is_rootless();
+        let cgroup = cgroup_path(&hostname, rootless);
 
         Ok(Self {
             path,
             argv,
+            env,
+            cwd: PathBuf::from("/"),
+            init: false,
             uid,
+            gid: uid,
+            groups: Vec::new(),
+            id_mappings: IdMappings::default(),
+            capabilities: Capabilities::default(),
+            seccomp: Some(SeccompProfile::default()),
+            seccomp_notify: None,
             mount_dir,
             fd,
+            pty: None,
             hostname,
             container_id,
             root_path,
-            add_paths,
+            overlay: None,
+            readonly_root: false,
+            protected_paths: ProtectedPaths::default(),
+            resources: ResourceLimits::default(),
+            cgroup,
+            rootless,
+            grace_period: default_grace_period(),
+            network: None,
+            volumes,
+            tmpfs: Vec::new(),
             tool_dir,
+            bundle: None,
         })
     }
+
+    /// Build options from `config.json` of an OCI bundle,
+    /// `volumes` and `tool_dir` from command line are mounted as well
+    pub fn from_bundle(
+        container_id: Option<String>,
+        bundle: &Path,
+        fd: RawFd,
+        mut volumes: Vec<Volume>,
+        tool_dir: Option<PathBuf>,
+    ) -> Result<Self, ErrorCode> {
+        let bundle = bundle.canonicalize().map_err(|e| {
+            log::error!("Cannot canonicalize bundle path {:?}: {}", bundle, e);
+            ErrorCode::ArgumentInvaild("bundle")
+        })?;
+        let spec = load_spec(&bundle)?;
+        let process = spec
+            .process
+            .as_ref()
+            .expect("process is checked by load_spec");
+        let root = spec.root.as_ref().expect("root is checked by load_spec");
+
+        let argv = to_cstrings(&process.args, "process.args")?;
+        let path = argv[0].clone();
+        let env = to_cstrings(&process.env, "process.env")?;
+        let mount_dir = bundle.join(&root.path);
+        if !mount_dir.is_dir() {
+            log::error!("Root path {:?} is not a directory", mount_dir);
+            return Err(ErrorCode::BundleError(2));
+        }
+        let hostname = match &spec.hostname {
+            Some(hostname) if !hostname.is_empty() => hostname.clone(),
+            _ => generate_hostname()?,
+        };
+        let container_id = match container_id {
+            Some(container_id) => container_id,
+            None => generate_container_id()?,
+        };
+        let root_path = generate_rootpath(&container_id)?;
+        let rootless = is_rootless();
+        let cgroup = cgroup_path(&hostname, rootless);
+        let mut bind_mounts = spec.bind_mounts(&bundle)?;
+        bind_mounts.append(&mut volumes);
+
+        Ok(Self {
+            path,
+            argv,
+            env,
+            cwd: process.cwd.clone(),
+            init: false,
+            uid: process.user.uid,
+            gid: process.user.gid,
+            groups: process.user.additional_gids.clone(),
+            id_mappings: spec.id_mappings(),
+            capabilities: spec.capabilities(),
+            seccomp: Some(spec.seccomp_profile()),
+            seccomp_notify: None,
+            mount_dir,
+            fd,
+            pty: None,
+            hostname,
+            container_id,
+            root_path,
+            overlay: None,
+            readonly_root: root.readonly,
+            protected_paths: spec.protected_paths(),
+            resources: spec.resource_limits(),
+            cgroup,
+            rootless,
+            grace_period: default_grace_period(),
+            network: None,
+            volumes: bind_mounts,
+            tmpfs: spec.tmpfs_mounts()?,
+            tool_dir,
+            bundle: Some(bundle),
+        })
+    }
+
+    /// Mount the root directory as lower layer of an overlay instead of binding it
+    pub fn with_overlay(mut self, overlay: Option<Overlay>) -> Self {
+        self.overlay = overlay;
+        self
+    }
+
+    /// A read-only root of a bundle stays read-only
+    pub fn with_readonly_root(mut self, readonly: bool) -> Self {
+        self.readonly_root |= readonly;
+        self
+    }
+
+    /// Masked and read-only paths, replacing the default ones or the ones of the bundle
+    pub fn with_protected_paths(mut self, protected_paths: ProtectedPaths) -> Self {
+        self.protected_paths = protected_paths;
+        self
+    }
+
+    /// Set each `KEY=VAL` of `env`, replacing a variable with the same key
+    pub fn with_env(mut self, env: &[String]) -> Result<Self, ErrorCode> {
+        for var in env.iter() {
+            let key = var.split('=').next().unwrap_or_default();
+            let var = CString::new(var.as_str()).map_err(|_| ErrorCode::ArgumentInvaild("env"))?;
+            self.env
+                .retain(|e| e.to_bytes().split(|&b| b == b'=').next() != Some(key.as_bytes()));
+            self.env.push(var);
+        }
+        Ok(self)
+    }
+
+    pub fn with_workdir(mut self, workdir: Option<PathBuf>) -> Self {
+        if let Some(workdir) = workdir {
+            self.cwd = workdir;
+        }
+        self
+    }
+
+    /// Connect the container to the bridge, `etc_files` are bind mounted into it
+    pub fn with_network(mut self, network: Option<Network>, mut etc_files: Vec<Volume>) -> Self {
+        self.network = network;
+        self.volumes.append(&mut etc_files);
+        self
+    }
+
+    /// Set the group and supplementary groups of the command, `gid` defaults to the user ID
+    pub fn with_groups(mut self, gid: Option<u32>, groups: Vec<u32>) -> Self {
+        self.gid = gid.unwrap_or(self.gid);
+        if !groups.is_empty() {
+            self.groups = groups;
+        }
+        self
+    }
+
+    pub fn with_id_mappings(mut self, id_mappings: IdMappings) -> Self {
+        self.id_mappings = id_mappings;
+        self
+    }
+
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
+    }
+
+    pub fn with_seccomp(mut self, seccomp: Option<SeccompProfile>) -> Self {
+        self.seccomp = seccomp;
+        self
+    }
+
+    /// Report the syscalls caught by the seccomp profile set before
+    pub fn with_seccomp_notify(mut self, notify: Option<SeccompNotify>) -> Result<Self, ErrorCode> {
+        match (&notify, &self.seccomp) {
+            (None, _) => {}
+            (Some(_), None) => {
+                log::error!("Seccomp is unconfined, no syscall can be caught");
+                return Err(ErrorCode::ArgumentInvaild("seccomp"));
+            }
+            (Some(_), Some(profile)) if profile.uses_trace() => {
+                log::error!("SCMP_ACT_TRACE cannot be used while catching syscalls");
+                return Err(ErrorCode::ArgumentInvaild("seccomp-profile"));
+            }
+            (Some(_), Some(_)) => {}
+        }
+        self.seccomp_notify = notify;
+        Ok(self)
+    }
+
+    /// Mount `tmpfs` after the ones of the bundle
+    pub fn with_tmpfs(mut self, mut tmpfs: Vec<Tmpfs>) -> Self {
+        self.tmpfs.append(&mut tmpfs);
+        self
+    }
+
+    pub fn with_init(mut self, init: bool) -> Self {
+        self.init = init;
+        self
+    }
+
+    pub fn with_pty(mut self, pty: Option<Pty>) -> Self {
+        self.pty = pty;
+        self
+    }
+
+    pub fn with_grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    pub fn with_resources(mut self, resources: ResourceLimits) -> Self {
+        self.resources = resources;
+        self
+    }
+}
+
+fn to_cstrings(values: &[String], field: &str) -> Result<Vec<CString>, ErrorCode> {
+    values
+        .iter()
+        .map(|s| {
+            CString::new(s.as_str()).map_err(|_| {
+                log::error!("Field `{}` contains a nul byte: {:?}", field, s);
+                ErrorCode::BundleError(2)
+            })
+        })
+        .collect()
+}
+
+fn default_grace_period() -> Duration {
+    humantime::parse_duration(DEFAULT_GRACE_PERIOD).expect("Must be valid")
+}
+
+/// Socket of the child is only meaningful inside the process which created it
+fn invalid_fd() -> RawFd {
+    -1
 }
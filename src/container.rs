@@ -1,111 +1,578 @@
 use crate::{
+    audit::read_audit,
     cgroup::{clean_cgroups, restrict_resources},
     child::generate_child_process,
-    cli::Args,
+    cli::{Command, CreateArgs, LogsArgs},
     config::ContainerOpts,
     ebpf::{clean_ebpf_program, generate_ebpf_program},
     error::ErrorCode,
-    ipc::generate_socketpair,
-    mount::clean_mounts,
-    user_namespace::handle_child_uid_gid_map,
+    image,
+    ipc::{
+        generate_fifo, generate_socketpair, recv_bool, recv_fifo_bool, send_bool, send_fifo_bool,
+    },
+    mount::{clean_mounts, Overlay},
+    network::{clean_network, prepare_network, setup_host_network},
+    seccomp::{SeccompNotify, SeccompProfile},
+    seccomp_notify::SeccompNotifier,
+    signals::SignalForwarder,
+    state::{
+        create_state_dir, monitor_log, remove_state_dir, start_fifo, state_dir, ContainerState,
+        Status,
+    },
+    tty::{open_pty, proxy_tty},
+    user_namespace::{handle_child_uid_gid_map, IdMappings},
+    volume::{Tmpfs, Volume},
 };
+use libc::{STDERR_FILENO, STDIN_FILENO, STDOUT_FILENO};
 use nix::{
-    sys::{utsname::uname, wait::waitpid},
-    unistd::{close, Pid},
+    sys::{
+        signal::{kill, Signal},
+        utsname::uname,
+        wait::{waitpid, WaitStatus},
+    },
+    unistd::{close, dup2, fork, setsid, ForkResult, Pid},
 };
 use rand::Rng;
-use std::{os::fd::RawFd, path::PathBuf};
+use std::{
+    fs::{File, OpenOptions},
+    os::fd::{AsRawFd, RawFd},
+    thread::sleep,
+    time::Duration,
+};
 
 const MINIMAL_KERNEL_VERSION: f64 = 5.4; // kernel version of Ubuntu 20.04 LTS
+const DELETE_WAIT_RETRIES: u32 = 50; // wait at most 5s for the container to exit
 
 pub struct Container {
     config: ContainerOpts,
-    sockets: (RawFd, RawFd),
+    sockets: Option<(RawFd, RawFd)>,
     child_pid: Option<Pid>,
     ebpf_pid: Option<Pid>,
+    notifier: Option<SeccompNotifier>,
+    exit_code: Option<i32>,
 }
 
 impl Container {
-    pub fn new(args: Args) -> Result<Self, ErrorCode> {
-        let mut add_paths = Vec::new();
-        for x in args.add_paths.iter() {
-            let pair = x.to_str().unwrap().split(":").collect::<Vec<_>>();
-            let from_path = PathBuf::from(pair[0])
-                .canonicalize()
-                .expect("Cannot canonicalize path");
-            let mnt_path = PathBuf::from(
-                pair[1]
-                    .to_string()
-                    .strip_prefix("/")
-                    .expect("Cannot canonicalize path"),
+    pub fn new(args: CreateArgs) -> Result<Self, ErrorCode> {
+        let volumes = args
+            .volumes
+            .iter()
+            .map(|spec| Volume::parse(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+        let tmpfs = args
+            .tmpfs
+            .iter()
+            .map(|spec| Tmpfs::parse(spec))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let sockets = generate_socketpair()?;
+        let config = match args.bundle {
+            Some(bundle) => ContainerOpts::from_bundle(
+                args.container_id,
+                &bundle,
+                sockets.1,
+                volumes,
+                args.tool_dir,
+            )?,
+            None => ContainerOpts::new(
+                args.container_id,
+                args.command,
+                args.uid.expect("uid is required without bundle"),
+                args.mount_dir.expect("mount is required without bundle"),
+                sockets.1,
+                volumes,
+                args.tool_dir,
+            )?,
+        };
+        let overlay = if args.overlay || !args.layers.is_empty() {
+            Some(Overlay {
+                layers: args.layers,
+                keep_upper: args.keep_upper,
+            })
+        } else {
+            None
+        };
+        // default limits fall back to rlimits, explicit ones are never silently dropped
+        if config.cgroup.is_none() && args.resources.needs_cgroup() {
+            log::error!(
+                "Resource limits need a cgroup delegated by systemd, e.g. run curn with \
+                 `systemd-run --user --scope`"
             );
-            add_paths.push((from_path, mnt_path));
+            return Err(ErrorCode::RootlessUnsupported("cgroup"));
         }
+        let mut resources = config.resources.clone();
+        args.resources.apply(&mut resources);
+        resources.validate()?;
+        let mut protected_paths = config.protected_paths.clone();
+        protected_paths.apply(&args.mask_paths, &args.readonly_paths, &args.unmask_paths)?;
+        let mut capabilities = config.capabilities.clone();
+        capabilities.apply(&args.cap_add, &args.cap_drop, config.uid);
+        // profile of a bundle is overridden by the command line
+        let seccomp = match (args.seccomp, args.seccomp_profile) {
+            (Some(mode), _) => mode.profile(),
+            (None, Some(profile)) => Some(SeccompProfile::load(&profile)?),
+            (None, None) => config.seccomp.clone(),
+        };
+        let seccomp_notify = match (args.seccomp_log, args.seccomp_learn) {
+            (true, _) => Some(SeccompNotify::Log),
+            (false, Some(path)) => match std::path::absolute(&path) {
+                Ok(path) => Some(SeccompNotify::Learn(path)),
+                Err(_) => return Err(ErrorCode::ArgumentInvaild("seccomp-learn")),
+            },
+            (false, None) => None,
+        };
+        let pty = if args.tty { Some(open_pty()?) } else { None };
+        let config = config
+            .with_env(&args.env)?
+            .with_workdir(args.workdir)
+            .with_overlay(overlay)
+            .with_readonly_root(args.read_only)
+            .with_protected_paths(protected_paths)
+            .with_tmpfs(tmpfs)
+            .with_init(args.init)
+            .with_pty(pty)
+            .with_grace_period(args.grace_period)
+            .with_resources(resources)
+            .with_groups(args.gid, args.groups)
+            .with_capabilities(capabilities)
+            .with_seccomp(seccomp)
+            .with_seccomp_notify(seccomp_notify)?;
 
-        let sockets = generate_socketpair()?;
-        let config = ContainerOpts::new(
-            args.command,
-            args.uid,
-            args.mount_dir,
-            sockets.1,
-            add_paths,
-            args.tool_dir,
-        )?;
+        // mappings of a bundle are overridden by the command line ones
+        let uid_map = match args.uid_map.is_empty() {
+            true => config.id_mappings.uid.clone(),
+            false => args.uid_map,
+        };
+        let gid_map = match args.gid_map.is_empty() {
+            true => config.id_mappings.gid.clone(),
+            false => args.gid_map,
+        };
+        let id_mappings =
+            IdMappings::allocate(uid_map, gid_map, &config.container_id, config.rootless)?;
+        id_mappings
+            .validate(config.uid, config.gid, &config.groups)
+            .and_then(|_| create_state_dir(&config.container_id))
+            .inspect_err(|_| id_mappings.release())?;
+
+        let container_id = config.container_id.clone();
+        let config = config.with_id_mappings(id_mappings.clone());
+        let config = prepare_network(config, args.network, args.subnet).inspect_err(|_| {
+            let _ = remove_state_dir(&container_id);
+            id_mappings.release();
+        })?;
 
         Ok(Self {
             config,
-            sockets,
+            sockets: Some(sockets),
             child_pid: None,
             ebpf_pid: None,
+            notifier: None,
+            exit_code: None,
         })
     }
 
+    /// Rebuild a container from its persisted state, it has no socket to the child
+    pub fn load(state: &ContainerState) -> Self {
+        Self {
+            config: state.config.clone(),
+            sockets: None,
+            child_pid: state.pid.map(Pid::from_raw),
+            ebpf_pid: state.ebpf_pid.map(Pid::from_raw),
+            notifier: None,
+            exit_code: state.exit_code,
+        }
+    }
+
     pub fn create(&mut self) -> Result<(), ErrorCode> {
+        let sockets = self.sockets.ok_or(ErrorCode::ContainerError(4))?;
         let pid = generate_child_process(&self.config)?;
-        let ebpf_pid = generate_ebpf_program(self.config.container_id.clone(), pid.as_raw())?;
-        restrict_resources(&self.config.hostname, pid)?;
-        handle_child_uid_gid_map(pid, self.sockets.0)?;
         self.child_pid = Some(pid);
-        self.ebpf_pid = Some(ebpf_pid);
+        // only the child uses the slave side of the terminal
+        if let Some(pty) = &self.config.pty {
+            let _ = close(pty.slave);
+        }
+        self.ebpf_pid = generate_ebpf_program(&self.config, pid.as_raw())?;
+        restrict_resources(self.config.cgroup.as_deref(), pid, &self.config.resources)?;
+        if let Some(network) = &self.config.network {
+            setup_host_network(pid, network, sockets.0)?;
+        }
+        handle_child_uid_gid_map(
+            pid,
+            sockets.0,
+            &self.config.id_mappings,
+            self.config.rootless,
+        )?;
+        // the child sends the listener once its filter is loaded
+        if let Some(notify) = &self.config.seccomp_notify {
+            self.notifier = Some(SeccompNotifier::start(
+                &self.config,
+                notify.clone(),
+                pid,
+                sockets.0,
+            )?);
+        }
 
         log::debug!("Creation finished");
         Ok(())
     }
 
+    /// Let the child blocked on the socketpair execute the command
+    pub fn start(&mut self) -> Result<(), ErrorCode> {
+        let sockets = self.sockets.ok_or(ErrorCode::ContainerError(4))?;
+        log::debug!("Starting container {}", self.config.container_id);
+        send_bool(sockets.0, true)
+    }
+
+    /// Close the socketpair so the child blocked on it exits without executing the command
+    pub fn abort(&mut self) -> Result<(), ErrorCode> {
+        let sockets = self.sockets.ok_or(ErrorCode::ContainerError(4))?;
+        log::debug!("Aborting container {}", self.config.container_id);
+        send_bool(sockets.0, false)
+    }
+
+    /// Signals sent to curn are forwarded to the container from now on,
+    /// instead of killing curn before it cleans up
+    pub fn forward_signals(&self) -> Result<SignalForwarder, ErrorCode> {
+        let pid = self.child_pid.ok_or(ErrorCode::ContainerError(1))?;
+        let extra = match self.config.pty {
+            Some(_) => vec![Signal::SIGWINCH],
+            None => Vec::new(),
+        };
+        SignalForwarder::new(pid, self.config.grace_period, &extra)
+    }
+
+    /// Proxy the terminal of the container if it has one, then wait for it to exit
+    pub fn wait(&mut self, forwarder: &mut SignalForwarder) -> Result<i32, ErrorCode> {
+        log::debug!("Container child process PID: {:?}", self.child_pid);
+        if let (Some(pty), Some(pid)) = (&self.config.pty, self.child_pid) {
+            // the container cannot be used without its terminal
+            if proxy_tty(pty.master, forwarder).is_err() {
+                let _ = kill(pid, Signal::SIGKILL);
+            }
+        }
+        forwarder.wait()?;
+        let exit_code = wait_child(self.child_pid)?;
+        self.exit_code = Some(exit_code);
+        if let Some(notifier) = self.notifier.take() {
+            notifier.finish()?;
+        }
+        Ok(exit_code)
+    }
+
+    pub fn save_state(&self, status: Status) -> Result<(), ErrorCode> {
+        let mut state = ContainerState::new(&self.config, self.child_pid, self.ebpf_pid);
+        state.status = status;
+        state.exit_code = self.exit_code;
+        state.save()
+    }
+
+    /// Kill the child unless it has been waited for already, e.g. after an error
+    fn kill_child(&self) {
+        if let (Some(pid), None) = (self.child_pid, self.exit_code) {
+            let _ = kill(pid, Signal::SIGKILL);
+            let _ = waitpid(pid, None);
+        }
+    }
+
+    /// Kill the child of a container which failed to be created, then clean up after it
+    /// like after a normal exit
+    fn discard(&mut self) {
+        self.kill_child();
+        let _ = self.clean_exit();
+    }
+
     pub fn clean_exit(&mut self) -> Result<(), ErrorCode> {
         log::debug!("Cleaning container");
 
-        if let Err(e) = close(self.sockets.0) {
-            log::error!("Unable to close write socket of parent: {:?}", e);
-            return Err(ErrorCode::SocketError(3));
+        // every step runs even if one fails, the first error is returned
+        let mut results = Vec::new();
+        if let Some(sockets) = self.sockets.take() {
+            results.push(close(sockets.0).map_err(|e| {
+                log::error!("Unable to close write socket of parent: {:?}", e);
+                ErrorCode::SocketError(3)
+            }));
+            results.push(close(sockets.1).map_err(|e| {
+                log::error!("Unable to close read socket of child: {:?}", e);
+                ErrorCode::SocketError(4)
+            }));
+        }
+        if let Some(pty) = self.config.pty.take() {
+            let _ = close(pty.master);
+        }
+        results.push(clean_mounts(
+            &self.config.root_path,
+            self.config.overlay.as_ref(),
+        ));
+        results.push(clean_cgroups(self.config.cgroup.as_deref()));
+        if let Some(network) = &self.config.network {
+            results.push(clean_network(network));
         }
-        if let Err(e) = close(self.sockets.1) {
-            log::error!("Unable to close read socket of child: {:?}", e);
-            return Err(ErrorCode::SocketError(4));
+        self.config.id_mappings.release();
+        if let Some(ebpf_pid) = self.ebpf_pid {
+            results.push(clean_ebpf_program(ebpf_pid));
         }
-        clean_mounts(&self.config.root_path)?;
-        clean_cgroups(&self.config.hostname)?;
-        clean_ebpf_program(self.ebpf_pid.expect("eBPF program pid must be valid"))?;
 
-        log::debug!("Clean finished");
-        Ok(())
+        let errors = results
+            .into_iter()
+            .filter_map(Result::err)
+            .collect::<Vec<_>>();
+        for e in errors.iter() {
+            log::error!("Error while cleaning container: {:?}", e);
+        }
+        match errors.into_iter().next() {
+            Some(e) => Err(e),
+            None => {
+                log::debug!("Clean finished");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Return exit code of the container for `run`, otherwise 0
+pub fn execute(command: Command) -> Result<i32, ErrorCode> {
+    match command {
+        Command::Create(args) => create(args).map(|_| 0),
+        Command::Start { container_id } => start(&container_id).map(|_| 0),
+        Command::State { container_id } => state(&container_id).map(|_| 0),
+        Command::Kill {
+            container_id,
+            signal,
+        } => kill_container(&container_id, signal).map(|_| 0),
+        Command::Delete {
+            container_id,
+            force,
+        } => delete(&container_id, force).map(|_| 0),
+        Command::Run(args) => run(args),
+        Command::Logs(args) => logs(args).map(|_| 0),
+        Command::Image { command } => image::execute(command).map(|_| 0),
     }
 }
 
-pub fn start(args: Args) -> Result<(), ErrorCode> {
+fn run(args: CreateArgs) -> Result<i32, ErrorCode> {
     check_linux_version()?;
 
     let mut container = Container::new(args)?;
 
     if let Err(e) = container.create() {
         log::error!("Error while creating container: {:?}", e);
+        container.discard();
+        let _ = remove_state_dir(&container.config.container_id);
+        return Err(e);
+    }
+
+    // kept until curn exits so it is not killed while cleaning up
+    let mut forwarder = None;
+    let exit_code = container
+        .save_state(Status::Created)
+        .and_then(|_| container.forward_signals())
+        .and_then(|f| {
+            let forwarder = forwarder.insert(f);
+            container.start()?;
+            container.save_state(Status::Running)?;
+            container.wait(forwarder)
+        });
+    match &exit_code {
+        Ok(code) => log::debug!(
+            "Execution finished with code {}, now cleaning and exit",
+            code
+        ),
+        Err(e) => {
+            log::error!("Error while running container: {:?}", e);
+            container.kill_child();
+        }
+    }
+
+    // every step of the cleanup runs, the first error is returned
+    let cleaned = container.clean_exit();
+    let removed = remove_state_dir(&container.config.container_id);
+    exit_code.and_then(|code| cleaned.and(removed).map(|_| code))
+}
+
+/// Fork a monitor process which owns the container until it exits,
+/// return as soon as the container is created
+fn create(args: CreateArgs) -> Result<(), ErrorCode> {
+    check_linux_version()?;
+
+    let container = Container::new(args)?;
+    let (parent_fd, monitor_fd) = generate_socketpair()?;
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Parent { .. }) => {
+            let _ = close(monitor_fd);
+            let created = recv_bool(parent_fd)?;
+            let _ = close(parent_fd);
+            if !created {
+                return Err(ErrorCode::ContainerError(4));
+            }
+            println!("{}", container.config.container_id);
+            Ok(())
+        }
+        Ok(ForkResult::Child) => {
+            let _ = close(parent_fd);
+            detach(&container.config.container_id)?;
+            monitor(container, monitor_fd)
+        }
+        Err(e) => {
+            log::error!("Cannot fork monitor process: {:?}", e);
+            Err(ErrorCode::ContainerError(3))
+        }
+    }
+}
+
+/// Leave the session of the terminal so its signals never reach the monitor, and release
+/// the stdio of `create` so callers reading its output are not blocked until the container
+/// exits. The monitor and the command log into the state directory instead
+fn detach(container_id: &str) -> Result<(), ErrorCode> {
+    let path = monitor_log(container_id);
+    let stdio = File::open("/dev/null").and_then(|null| {
+        let log = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok((null, log))
+    });
+    let (null, log) = stdio.map_err(|e| {
+        log::error!("Cannot open log of the monitor {:?}: {}", path, e);
+        ErrorCode::ContainerError(3)
+    })?;
+    let redirected = setsid()
+        .and_then(|_| dup2(null.as_raw_fd(), STDIN_FILENO))
+        .and_then(|_| dup2(log.as_raw_fd(), STDOUT_FILENO))
+        .and_then(|_| dup2(log.as_raw_fd(), STDERR_FILENO));
+    if let Err(e) = redirected {
+        log::error!("Cannot detach the monitor: {:?}", e);
+        return Err(ErrorCode::ContainerError(3));
+    }
+    Ok(())
+}
+
+fn monitor(mut container: Container, fd: RawFd) -> Result<(), ErrorCode> {
+    let container_id = container.config.container_id.clone();
+    let fifo = start_fifo(&container_id);
+
+    let created = generate_fifo(&fifo)
+        .and_then(|_| container.create())
+        .and_then(|_| container.save_state(Status::Created));
+    send_bool(fd, created.is_ok())?;
+    let _ = close(fd);
+    if let Err(e) = created {
+        log::error!("Error while creating container: {:?}", e);
+        container.discard();
+        let _ = remove_state_dir(&container_id);
         return Err(e);
     }
-    log::debug!("Container child process PID: {:?}", container.child_pid);
-    wait_child(container.child_pid)?;
 
-    log::debug!("Execution finished, now cleaning and exit");
-    container.clean_exit()
+    // block until `start` or `delete` is issued
+    let mut forwarder = container.forward_signals()?;
+    if recv_fifo_bool(&fifo)? {
+        container.start()?;
+        container.save_state(Status::Running)?;
+    } else {
+        container.abort()?;
+    }
+    container.wait(&mut forwarder)?;
+
+    // state is already gone if the container has been deleted meanwhile
+    if state_dir(&container_id).exists() {
+        container.save_state(Status::Stopped)?;
+    }
+    Ok(())
+}
+
+fn start(container_id: &str) -> Result<(), ErrorCode> {
+    let state = ContainerState::load(container_id)?;
+    if state.status != Status::Created {
+        log::error!("Cannot start a container in status {:?}", state.status);
+        return Err(ErrorCode::StateError(5));
+    }
+
+    send_fifo_bool(&start_fifo(container_id), true)
+}
+
+fn state(container_id: &str) -> Result<(), ErrorCode> {
+    let state = ContainerState::load(container_id)?;
+    match serde_json::to_string_pretty(&state) {
+        Ok(content) => {
+            println!("{}", content);
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("Cannot serialize state: {}", e);
+            Err(ErrorCode::StateError(2))
+        }
+    }
+}
+
+fn kill_container(container_id: &str, signal: Signal) -> Result<(), ErrorCode> {
+    let state = ContainerState::load(container_id)?;
+    let pid = match (state.status, state.pid) {
+        (Status::Created | Status::Running, Some(pid)) => Pid::from_raw(pid),
+        _ => {
+            log::error!("Cannot kill a container in status {:?}", state.status);
+            return Err(ErrorCode::StateError(5));
+        }
+    };
+
+    log::debug!("Sending {} to container process (pid {})", signal, pid);
+    kill(pid, signal).map_err(|_| ErrorCode::ContainerError(2))
+}
+
+fn delete(container_id: &str, force: bool) -> Result<(), ErrorCode> {
+    let state = ContainerState::load(container_id)?;
+    match state.status {
+        Status::Stopped => {}
+        Status::Created => {
+            // wake up the monitor to abort the container, kill it if nobody is waiting
+            if send_fifo_bool(&start_fifo(container_id), false).is_err() {
+                if let Some(pid) = state.pid {
+                    let _ = kill(Pid::from_raw(pid), Signal::SIGKILL);
+                }
+            }
+        }
+        Status::Running if force => {
+            if let Some(pid) = state.pid {
+                kill(Pid::from_raw(pid), Signal::SIGKILL)
+                    .map_err(|_| ErrorCode::ContainerError(2))?;
+            }
+        }
+        _ => {
+            log::error!("Cannot delete a container in status {:?}", state.status);
+            return Err(ErrorCode::StateError(5));
+        }
+    }
+
+    for _ in 0..DELETE_WAIT_RETRIES {
+        if !state.is_alive() {
+            break;
+        }
+        sleep(Duration::from_millis(100));
+    }
+
+    Container::load(&state).clean_exit()?;
+    remove_state_dir(container_id)
+}
+
+fn logs(args: LogsArgs) -> Result<(), ErrorCode> {
+    let records = read_audit(&args.container_id)?
+        .into_iter()
+        .filter(|r| args.matches(r))
+        .collect::<Vec<_>>();
+    let skip = args
+        .tail
+        .map_or(0, |tail| records.len().saturating_sub(tail));
+
+    for record in records.iter().skip(skip) {
+        if args.audit {
+            match serde_json::to_string(record) {
+                Ok(line) => println!("{}", line),
+                Err(e) => {
+                    log::error!("Cannot serialize audit record: {}", e);
+                    return Err(ErrorCode::AuditError(2));
+                }
+            }
+        } else {
+            println!("{}", record);
+        }
+    }
+    Ok(())
 }
 
 fn check_linux_version() -> Result<(), ErrorCode> {
@@ -130,15 +597,26 @@ fn check_linux_version() -> Result<(), ErrorCode> {
     Ok(())
 }
 
-fn wait_child(pid: Option<Pid>) -> Result<(), ErrorCode> {
+/// Return exit code of the child, or `128 + signo` if it was killed by a signal
+fn wait_child(pid: Option<Pid>) -> Result<i32, ErrorCode> {
     match pid {
         Some(pid) => {
             log::debug!("Waiting for child process (pid {}) to finish", pid);
-            if let Err(e) = waitpid(pid, None) {
-                log::error!("Error while waiting for pid to finish: {:?}", e);
-                return Err(ErrorCode::ContainerError(1));
+            match waitpid(pid, None) {
+                Ok(WaitStatus::Exited(_, code)) => Ok(code),
+                Ok(WaitStatus::Signaled(_, signal, _)) => {
+                    log::info!("Child process was killed by {}", signal);
+                    Ok(128 + signal as i32)
+                }
+                Ok(status) => {
+                    log::error!("Unexpected wait status of child process: {:?}", status);
+                    Err(ErrorCode::ContainerError(1))
+                }
+                Err(e) => {
+                    log::error!("Error while waiting for pid to finish: {:?}", e);
+                    Err(ErrorCode::ContainerError(1))
+                }
             }
-            Ok(())
         }
         None => {
             log::error!("Invalid pid of waiting process");
@@ -152,7 +630,7 @@ pub fn generate_container_id() -> Result<String, ErrorCode> {
 }
 
 /// Generate a n-char String
-fn random_string(n: usize) -> String {
+pub fn random_string(n: usize) -> String {
     const CHARSET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
     let mut rng = rand::thread_rng();
 
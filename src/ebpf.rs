@@ -1,80 +1,328 @@
-use crate::error::ErrorCode;
+use crate::{
+    audit::{AuditLog, AuditRecord},
+    config::ContainerOpts,
+    error::ErrorCode,
+    ipc::{generate_socketpair, recv_bool, send_bool},
+};
+use aya::{
+    maps::{
+        perf::{PerfEvent, PerfEventArrayBuffer},
+        HashMap, MapData, PerfEventArray,
+    },
+    programs::{uprobe::UProbeScope, TracePoint, UProbe},
+    util::online_cpus,
+    Ebpf,
+};
 use libc::c_int;
 use nix::{
-    fcntl::{open, OFlag},
+    poll::{poll, PollFd, PollFlags, PollTimeout},
     sched::{clone, CloneFlags},
     sys::{
         signal::{kill, Signal},
-        stat::Mode,
+        wait::waitpid,
     },
-    unistd::{dup2, execve, Pid},
+    unistd::{close, Pid},
+};
+use std::{
+    env, fmt,
+    mem::size_of,
+    os::fd::{AsFd, RawFd},
+    path::{Path, PathBuf},
+    ptr,
 };
-use std::{ffi::CString, fs, path::PathBuf};
 
 const STACK_SIZE: usize = 1024 * 1024;
+const BPF_OBJECT: &str = "snoop.bpf.o";
+// searched after the directory of curn executable
+const BPF_OBJECT_DIRS: [&str; 2] = ["/usr/local/lib/curn", "/usr/lib/curn"];
+// (program, category, tracepoint), fork and exit keep the tracked pids up to date
+const TRACEPOINTS: [(&str, &str, &str); 3] = [
+    (
+        "tracepoint__sched__sched_process_fork",
+        "sched",
+        "sched_process_fork",
+    ),
+    (
+        "tracepoint__sched__sched_process_exit",
+        "sched",
+        "sched_process_exit",
+    ),
+    (
+        "tracepoint__syscalls__sys_enter_execve",
+        "syscalls",
+        "sys_enter_execve",
+    ),
+];
+const READLINE_PROGRAM: &str = "printret";
+const EVENTS_MAP: &str = "events";
+const TRACKED_MAP: &str = "tracked";
+const PERF_PAGES: usize = 16;
+const TASK_COMM_LEN: usize = 16;
+const MAX_PATH_LEN: usize = 256;
+const FULL_MAX_ARGS_ARR: usize = 20 * 128;
 
-fn ebpf_program(conatiner_id: String, pid: i32) -> isize {
-    log::info!(
-        "Starting container with command `{}` and args {:?}",
-        "./ecli",
-        vec!["run", "package.json"]
-    );
+/// Same layout as `struct event` in snoop.bpf.h
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Event {
+    pub pid: i32,
+    pub ppid: i32,
+    pub uid: i32,
+    pub retval: i32,
+    pub is_exit: u8,
+    pub comm: [u8; TASK_COMM_LEN],
+    pub filename: [u8; MAX_PATH_LEN],
+    pub args_count: i32,
+    pub args_size: u32,
+    pub args: [u8; FULL_MAX_ARGS_ARR],
+}
 
-    let path = PathBuf::from(format!("./logs/{}", conatiner_id));
-    if let Err(e) = fs::File::create(&path) {
-        log::error!("Error while executing eBPF program: {:?}", e);
-        return -1;
+impl Event {
+    /// Decode a perf sample, only the used part of `args` is sent by the kernel side
+    fn from_bytes(data: &[u8]) -> Option<Self> {
+        let header = size_of::<Self>() - FULL_MAX_ARGS_ARR;
+        if data.len() < header {
+            return None;
+        }
+
+        let mut bytes = vec![0u8; size_of::<Self>()];
+        let len = data.len().min(bytes.len());
+        bytes[..len].copy_from_slice(&data[..len]);
+        // every bit pattern is a valid `Event`, and the buffer has no alignment guarantee
+        let event = unsafe { ptr::read_unaligned(bytes.as_ptr() as *const Self) };
+        if event.args_size as usize > len - header {
+            return None;
+        }
+        Some(event)
+    }
+
+    /// Name of the process calling execve
+    pub fn comm(&self) -> String {
+        c_string(&self.comm)
+    }
+
+    /// Executed path as given to execve
+    pub fn filename(&self) -> String {
+        c_string(&self.filename)
     }
 
-    match open(&path, OFlag::O_WRONLY, Mode::empty()) {
-        Ok(fd) => {
-            let _ = dup2(fd, 1);
-            let _ = dup2(fd, 2);
+    /// Arguments of execve, the kernel side keeps at most 20 of them
+    pub fn argv(&self) -> Vec<String> {
+        self.args[..self.args_size as usize]
+            .split(|&c| c == 0)
+            .take(self.args_count.max(0) as usize)
+            .map(|a| String::from_utf8_lossy(a).into_owned())
+            .collect()
+    }
+}
+
+fn c_string(bytes: &[u8]) -> String {
+    let len = bytes.iter().position(|&c| c == 0).unwrap_or(bytes.len());
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "pid {} ppid {} uid {} exec {} {:?}",
+            self.pid,
+            self.ppid,
+            self.uid,
+            self.filename(),
+            self.argv()
+        )
+    }
+}
+
+/// Look for the compiled eBPF object next to curn, then in the system directories
+fn find_bpf_object() -> Option<PathBuf> {
+    let exe_dir = env::current_exe()
+        .ok()
+        .and_then(|p| p.parent().map(Path::to_path_buf));
+    exe_dir
+        .into_iter()
+        .chain(BPF_OBJECT_DIRS.iter().map(PathBuf::from))
+        .map(|dir| dir.join(BPF_OBJECT))
+        .find(|p| p.is_file())
+}
+
+/// `readline` of the container's bash, the topmost layer wins for an overlay root
+fn find_bash(config: &ContainerOpts) -> Option<PathBuf> {
+    let layers = config
+        .overlay
+        .iter()
+        .flat_map(|o| o.layers.iter().rev())
+        .chain(std::iter::once(&config.mount_dir));
+    layers.map(|l| l.join("bin/bash")).find(|p| p.is_file())
+}
+
+fn load_program(config: &ContainerOpts, pid: i32) -> Result<Ebpf, String> {
+    let object = find_bpf_object().ok_or(format!("cannot find {}", BPF_OBJECT))?;
+    log::debug!("Loading eBPF object {:?} for pid {}", object, pid);
+
+    let mut bpf =
+        Ebpf::load_file(&object).map_err(|e| format!("cannot load {:?}: {}", object, e))?;
+
+    // descendants of the init process are added by the fork tracepoint
+    let mut tracked: HashMap<_, i32, u8> = bpf
+        .map_mut(TRACKED_MAP)
+        .ok_or(format!("missing map {}", TRACKED_MAP))?
+        .try_into()
+        .map_err(|e| format!("{}: {}", TRACKED_MAP, e))?;
+    tracked
+        .insert(pid, 1, 0)
+        .map_err(|e| format!("cannot track pid {}: {}", pid, e))?;
+
+    for (name, category, tracepoint) in TRACEPOINTS {
+        let program: &mut TracePoint = bpf
+            .program_mut(name)
+            .ok_or(format!("missing program {}", name))?
+            .try_into()
+            .map_err(|e| format!("{}: {}", name, e))?;
+        program
+            .load()
+            .and_then(|_| program.attach(category, tracepoint))
+            .map_err(|e| format!("cannot attach {}: {}", name, e))?;
+    }
+
+    match find_bash(config) {
+        Some(bash) => {
+            let readline: &mut UProbe = bpf
+                .program_mut(READLINE_PROGRAM)
+                .ok_or(format!("missing program {}", READLINE_PROGRAM))?
+                .try_into()
+                .map_err(|e| format!("{}: {}", READLINE_PROGRAM, e))?;
+            readline
+                .load()
+                .and_then(|_| readline.attach("readline", &bash, UProbeScope::AllProcesses))
+                .map_err(|e| format!("cannot attach {} to {:?}: {}", READLINE_PROGRAM, bash, e))?;
         }
-        Err(e) => {
-            log::error!("Error while trying to perfoem execve: {:?}", e);
-            return -1;
+        None => log::debug!("No bash in the container, skip attaching readline"),
+    }
+
+    Ok(bpf)
+}
+
+fn open_buffers(bpf: &mut Ebpf) -> Result<Vec<PerfEventArrayBuffer<MapData>>, String> {
+    let map = bpf
+        .take_map(EVENTS_MAP)
+        .ok_or(format!("missing map {}", EVENTS_MAP))?;
+    let mut events = PerfEventArray::try_from(map).map_err(|e| e.to_string())?;
+
+    online_cpus()
+        .map_err(|(path, e)| format!("cannot read {}: {}", path, e))?
+        .into_iter()
+        .map(|cpu| {
+            events
+                .open(cpu, Some(PERF_PAGES))
+                .map_err(|e| format!("cannot open perf buffer of cpu {}: {}", cpu, e))
+        })
+        .collect()
+}
+
+fn read_events(
+    buffers: &mut [PerfEventArrayBuffer<MapData>],
+    mut handle: impl FnMut(Event),
+) -> Result<(), String> {
+    loop {
+        {
+            let mut fds = buffers
+                .iter()
+                .map(|b| PollFd::new(b.as_fd(), PollFlags::POLLIN))
+                .collect::<Vec<_>>();
+            poll(&mut fds, PollTimeout::NONE).map_err(|e| format!("cannot poll: {}", e))?;
+        }
+
+        for buffer in buffers.iter_mut().filter(|b| b.readable()) {
+            buffer.for_each(|event| match event {
+                PerfEvent::Sample { head, tail } => {
+                    let data = [head, tail].concat();
+                    match Event::from_bytes(&data) {
+                        Some(event) => handle(event),
+                        None => log::error!("Truncated eBPF event of {} bytes", data.len()),
+                    }
+                }
+                PerfEvent::Lost { count } => log::error!("Lost {} eBPF events", count),
+            });
         }
     }
+}
 
-    match execve::<CString, CString>(
-        &CString::new("./ecli").unwrap(),
-        &[
-            CString::new("run").unwrap(),
-            CString::new("package.json").unwrap(),
-            CString::new("--ppid_target").unwrap(),
-            CString::new(format!("{}", pid)).unwrap(),
-        ],
-        &[],
-    ) {
-        Ok(_) => 0,
+fn ebpf_program(config: &ContainerOpts, pid: i32, fd: RawFd) -> isize {
+    let setup = AuditLog::create(&config.container_id)
+        .map_err(|e| e.to_string())
+        .and_then(|audit| {
+            let mut bpf = load_program(config, pid)?;
+            let buffers = open_buffers(&mut bpf)?;
+            Ok((bpf, buffers, audit))
+        });
+
+    let (_bpf, mut buffers, mut audit) = match setup {
+        Ok(setup) => setup,
         Err(e) => {
-            log::error!("Error while trying to perfoem execve: {:?}", e);
-            -1
+            log::error!("Error while loading eBPF program: {}", e);
+            let _ = send_bool(fd, false);
+            return -1;
         }
+    };
+    if send_bool(fd, true).is_err() {
+        return -1;
     }
+    let _ = close(fd);
+
+    let res = read_events(&mut buffers, |event| {
+        log::debug!("eBPF event: {}", event);
+        let _ = audit.append(&AuditRecord::new(&event, config));
+    });
+    if let Err(e) = res {
+        log::error!("Error while reading eBPF events: {}", e);
+        return -1;
+    }
+    0
 }
 
-pub fn generate_ebpf_program(conatiner_id: String, pid: i32) -> Result<Pid, ErrorCode> {
+/// Start a process which traces the container, return `None` if the eBPF program
+/// cannot be loaded so the container runs without being traced
+pub fn generate_ebpf_program(config: &ContainerOpts, pid: i32) -> Result<Option<Pid>, ErrorCode> {
+    if config.rootless {
+        log::warn!("eBPF tracing needs root, the container will not be traced");
+        return Ok(None);
+    }
     log::debug!("Cloning eBPF user process");
 
+    let (parent_fd, ebpf_fd) = generate_socketpair()?;
     let mut tmp_stack: [u8; STACK_SIZE] = [0; STACK_SIZE];
-    unsafe {
+    let ebpf_pid = unsafe {
         match clone(
-            Box::new(|| ebpf_program(conatiner_id.clone(), pid)),
+            Box::new(|| ebpf_program(config, pid, ebpf_fd)),
             &mut tmp_stack,
             CloneFlags::empty(),
             Some(Signal::SIGCHLD as c_int),
         ) {
-            Ok(pid) => Ok(pid),
-            Err(_) => Err(ErrorCode::ChildProcessError(0)),
+            Ok(pid) => pid,
+            Err(_) => return Err(ErrorCode::ChildProcessError(0)),
         }
+    };
+    let _ = close(ebpf_fd);
+
+    // the program must be attached before the container executes anything
+    let loaded = recv_bool(parent_fd).unwrap_or(false);
+    let _ = close(parent_fd);
+    if !loaded {
+        log::error!("eBPF program is not loaded, the container will not be traced");
+        let _ = waitpid(ebpf_pid, None);
+        return Ok(None);
     }
+
+    Ok(Some(ebpf_pid))
 }
 
 pub fn clean_ebpf_program(pid: Pid) -> Result<(), ErrorCode> {
     log::debug!("Cleaning eBPF program (pid {})", pid);
 
     kill(pid, Signal::SIGTERM).map_err(|_| ErrorCode::ContainerError(2))?;
+    // only the process which started it can reap it
+    let _ = waitpid(pid, None);
     Ok(())
 }
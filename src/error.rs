@@ -14,11 +14,43 @@ pub enum ErrorCode {
     CapabilitiesError(u8),
     SyscallError(u8),
     CgroupError(u8),
+    StateError(u8),
+    BundleError(u8),
+    BundleUnsupported(&'static str),
+    NetworkError(u8),
+    AuditError(u8),
+    TtyError(u8),
+    RootlessUnsupported(&'static str),
+    ImageError(u8),
 }
 
+/// Exit codes of curn itself, chosen above `128 + signo` (at most 192)
+/// so they never collide with a workload killed by a signal.
+/// A workload which exits normally returns its own code (0 to 255).
 impl ErrorCode {
     pub fn get_retcode(&self) -> i32 {
-        1
+        match self {
+            ErrorCode::ArgumentInvaild(_) => 200,
+            ErrorCode::NotSupported(_) => 201,
+            ErrorCode::ContainerError(_) => 202,
+            ErrorCode::SocketError(_) => 203,
+            ErrorCode::ChildProcessError(_) => 204,
+            ErrorCode::RngError => 205,
+            ErrorCode::HostnameError(_) => 206,
+            ErrorCode::MountError(_) => 207,
+            ErrorCode::NamespacesError(_) => 208,
+            ErrorCode::CapabilitiesError(_) => 209,
+            ErrorCode::SyscallError(_) => 210,
+            ErrorCode::CgroupError(_) => 211,
+            ErrorCode::StateError(_) => 212,
+            ErrorCode::BundleError(_) => 213,
+            ErrorCode::BundleUnsupported(_) => 214,
+            ErrorCode::NetworkError(_) => 215,
+            ErrorCode::AuditError(_) => 216,
+            ErrorCode::TtyError(_) => 217,
+            ErrorCode::RootlessUnsupported(_) => 218,
+            ErrorCode::ImageError(_) => 219,
+        }
     }
 }
 
@@ -40,6 +72,9 @@ impl fmt::Display for ErrorCode {
                     0 => "Hardware and OS donot support container",
                     1 => "Error while waiting for pid to finish",
                     2 => "Error while killing a process",
+                    3 => "Failed to fork monitor process",
+                    4 => "Container was not created",
+                    5 => "Failed to forward signals to container",
                     _ => "Unknown reason",
                 };
                 write!(f, "Container Error by: {}", reason)
@@ -51,13 +86,25 @@ impl fmt::Display for ErrorCode {
                     2 => "Cannot receive value through socket",
                     3 => "Cannot close write socket of parent",
                     4 => "Cannot close read socket of child",
+                    5 => "Cannot create start fifo",
+                    6 => "Cannot open start fifo",
+                    7 => "Cannot write to start fifo",
+                    8 => "Cannot read from start fifo",
                     _ => "Unknown reason",
                 };
                 write!(f, "Socket Error: {}", reason)
             }
             ErrorCode::RngError => write!(f, "Failed to random choose"),
             ErrorCode::HostnameError(_element) => write!(f, "Cannot set up hostname for container"),
-            ErrorCode::ChildProcessError(_element) => write!(f, "Clone child process failed"),
+            ErrorCode::ChildProcessError(element) => {
+                let reason = match element {
+                    0 => "Clone child process failed",
+                    1 => "Init failed to fork the command",
+                    2 => "Init failed to handle signals",
+                    _ => "Unknown reason",
+                };
+                write!(f, "Child Process Error: {}", reason)
+            }
             ErrorCode::MountError(element) => {
                 let reason = match element {
                     0 => "Failed to mount file system",
@@ -66,6 +113,8 @@ impl fmt::Display for ErrorCode {
                     3 => "Failed to delete empty directory",
                     4 => "Failed to pivot root",
                     5 => "Failed to change working directory to root",
+                    6 => "Failed to delete directory recursively",
+                    7 => "Failed to create device or symbolic link",
                     _ => "Unknown reason",
                 };
                 write!(f, "Mount Error: {}", reason)
@@ -80,17 +129,30 @@ impl fmt::Display for ErrorCode {
                     5 => "Failed to create uid_map file",
                     6 => "Failed to write gid_map file",
                     7 => "Failed to create gid_map file",
+                    8 => "No subordinate id range left",
+                    9 => "Failed to lease subordinate ids",
                     _ => "Unknown reason",
                 };
                 write!(f, "Namespace Error: {}", reason)
             }
-            ErrorCode::CapabilitiesError(_element) => write!(f, "Failed to restrict capabilities"),
+            ErrorCode::CapabilitiesError(element) => {
+                let reason = match element {
+                    0 => "Failed to get capabilities",
+                    1 => "Failed to restrict bounding set",
+                    2 => "Failed to set capabilities",
+                    3 => "Failed to raise ambient capabilities",
+                    _ => "Unknown reason",
+                };
+                write!(f, "Capabilities Error: {}", reason)
+            }
             ErrorCode::SyscallError(element) => {
                 let reason = match element {
                     0 => "Failed to load seccomp policy",
                     1 => "Failed to create seccomp context",
                     2 => "Failed to set action for syscall",
                     3 => "Failed to set rule for syscall",
+                    4 => "Failed to receive seccomp notifications",
+                    5 => "Failed to write learned seccomp profile",
                     _ => "Unknown reason",
                 };
                 write!(f, "Syscall Error: {}", reason)
@@ -106,16 +168,86 @@ impl fmt::Display for ErrorCode {
                 };
                 write!(f, "Cgroup Error: {}", reason)
             }
+            ErrorCode::StateError(element) => {
+                let reason = match element {
+                    0 => "Failed to create state directory",
+                    1 => "Failed to write state file",
+                    2 => "Failed to read state file",
+                    3 => "Container does not exist",
+                    4 => "Container already exists",
+                    5 => "Operation not allowed in current container status",
+                    6 => "Failed to remove state directory",
+                    _ => "Unknown reason",
+                };
+                write!(f, "State Error: {}", reason)
+            }
+            ErrorCode::BundleError(element) => {
+                let reason = match element {
+                    0 => "Failed to read config.json of bundle",
+                    1 => "Failed to parse config.json of bundle",
+                    2 => "Missing or invalid field in config.json",
+                    _ => "Unknown reason",
+                };
+                write!(f, "Bundle Error: {}", reason)
+            }
+            ErrorCode::BundleUnsupported(element) => {
+                write!(f, "Bundle field not supported by curn: {}", element)
+            }
+            ErrorCode::NetworkError(element) => {
+                let reason = match element {
+                    0 => "Failed to set up bridge or veth pair on host",
+                    1 => "Failed to configure network inside container",
+                    2 => "Failed to allocate an address",
+                    3 => "Failed to write network configuration files",
+                    4 => "Failed to tear down network",
+                    _ => "Unknown reason",
+                };
+                write!(f, "Network Error: {}", reason)
+            }
+            ErrorCode::AuditError(element) => {
+                let reason = match element {
+                    0 => "Failed to write audit file",
+                    1 => "Failed to read audit file",
+                    2 => "Invalid record in audit file",
+                    _ => "Unknown reason",
+                };
+                write!(f, "Audit Error: {}", reason)
+            }
+            ErrorCode::TtyError(element) => {
+                let reason = match element {
+                    0 => "Failed to allocate a pseudo-terminal",
+                    1 => "Failed to set controlling terminal of container",
+                    2 => "Failed to set terminal to raw mode",
+                    3 => "Failed to proxy terminal I/O",
+                    _ => "Unknown reason",
+                };
+                write!(f, "Tty Error: {}", reason)
+            }
+            ErrorCode::RootlessUnsupported(element) => {
+                write!(f, "Not supported in rootless mode: {}", element)
+            }
+            ErrorCode::ImageError(element) => {
+                let reason = match element {
+                    0 => "Failed to read image archive",
+                    1 => "Invalid manifest or config of image",
+                    2 => "Failed to unpack layer",
+                    3 => "Image or layer does not exist",
+                    4 => "Failed to write image store",
+                    5 => "Digest of a blob or layer does not match",
+                    _ => "Unknown reason",
+                };
+                write!(f, "Image Error: {}", reason)
+            }
             _ => write!(f, "Unknown Error: {:?}", self),
         }
     }
 }
 
-pub fn exit_with_retcode(res: Result<(), ErrorCode>) {
+pub fn exit_with_retcode(res: Result<i32, ErrorCode>) {
     match res {
-        Ok(_) => {
-            log::debug!("Exit without any error, return 0");
-            exit(0);
+        Ok(retcode) => {
+            log::debug!("Exit without any error, return {}", retcode);
+            exit(retcode);
         }
         Err(e) => {
             let retcode = e.get_retcode();
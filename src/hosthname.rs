@@ -2,11 +2,11 @@ use crate::error::ErrorCode;
 use nix::unistd::sethostname;
 use rand::{seq::SliceRandom, Rng};
 
-const HOSTNAME_NOUN: [&'static str; 8] = [
+const HOSTNAME_NOUN: [&str; 8] = [
     "cat", "world", "coffee", "girl", "man", "book", "pinguin", "moon",
 ];
 
-const HOSTNAME_ADJ: [&'static str; 16] = [
+const HOSTNAME_ADJ: [&str; 16] = [
     "blue",
     "red",
     "green",
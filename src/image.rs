@@ -0,0 +1,1178 @@
+use crate::{
+    cli::ImageCommand, container::random_string, error::ErrorCode, seccomp::is_native_arch,
+    state::list_states, store::store_root, user_namespace::is_rootless,
+};
+use flate2::read::MultiGzDecoder;
+use nix::sys::stat::{makedev, mknod, Mode, SFlag};
+use ruzstd::decoding::StreamingDecoder;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashSet,
+    ffi::CString,
+    fs::{self, create_dir_all, remove_dir_all, File},
+    io::{self, BufRead, BufReader, Read},
+    os::unix::{ffi::OsStrExt, fs::MetadataExt},
+    path::{Component, Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+use tar::Archive;
+
+const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+// https://github.com/opencontainers/image-spec/blob/main/layer.md#whiteouts
+const WHITEOUT_PREFIX: &str = ".wh.";
+const OPAQUE_WHITEOUT: &str = ".wh..wh..opq";
+const OVERLAY_OPAQUE_XATTR: &str = "trusted.overlay.opaque";
+// indexes may point to further indexes, e.g. a manifest list inside an OCI layout
+const MAX_INDEX_DEPTH: usize = 4;
+
+/// Defaults of the command, `config` of an image config in the format of Docker and OCI
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default, rename_all = "PascalCase")]
+pub struct ImageConfig {
+    pub entrypoint: Option<Vec<String>>,
+    pub cmd: Option<Vec<String>>,
+    pub env: Option<Vec<String>>,
+    pub working_dir: Option<String>,
+    /// `UID[:GID]` or a user name
+    pub user: Option<String>,
+}
+
+/// Image config blob of a `docker save` archive or an OCI layout
+#[derive(Debug, Deserialize)]
+struct ImageSpec {
+    #[serde(default)]
+    config: Option<ImageConfig>,
+    rootfs: RootFs,
+}
+
+#[derive(Debug, Deserialize)]
+struct RootFs {
+    diff_ids: Vec<String>,
+}
+
+/// Entry of `manifest.json` of a `docker save` archive, paths are relative to the archive
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DockerManifest {
+    config: PathBuf,
+    layers: Vec<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Descriptor {
+    #[serde(default)]
+    media_type: String,
+    digest: String,
+    platform: Option<Platform>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Platform {
+    architecture: String,
+    os: String,
+}
+
+/// `index.json` of an OCI layout, or an image index blob
+#[derive(Debug, Deserialize)]
+struct OciIndex {
+    manifests: Vec<Descriptor>,
+}
+
+#[derive(Debug, Deserialize)]
+struct OciManifest {
+    config: Descriptor,
+    layers: Vec<Descriptor>,
+}
+
+/// Image kept in the local store, its layers are shared with other images
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Image {
+    pub name: String,
+    /// Digests of the uncompressed layers from bottom to top, e.g. sha256:xxx...
+    pub layers: Vec<String>,
+    pub config: ImageConfig,
+    pub created: u64,
+}
+
+impl Image {
+    fn new(name: &str, layers: Vec<String>, config: ImageConfig) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            name: name.to_string(),
+            layers,
+            config,
+            created,
+        }
+    }
+
+    pub fn load(name: &str) -> Result<Self, ErrorCode> {
+        let path = image_path(name);
+        if !path.exists() {
+            log::error!("Image `{}` does not exist, see `curn image import`", name);
+            return Err(ErrorCode::ImageError(3));
+        }
+        read_json(&path)
+    }
+
+    fn save(&self) -> Result<(), ErrorCode> {
+        let path = image_path(&self.name);
+        let tmp_path = path.with_extension("tmp");
+        log::debug!("Saving image `{}` to {:?}", self.name, path);
+
+        let content = serde_json::to_string_pretty(self).map_err(|e| {
+            log::error!("Cannot serialize image: {}", e);
+            ErrorCode::ImageError(4)
+        })?;
+        if let Err(e) = fs::write(&tmp_path, content).and_then(|_| fs::rename(&tmp_path, &path)) {
+            log::error!("Cannot write image file {:?}: {}", path, e);
+            return Err(ErrorCode::ImageError(4));
+        }
+        Ok(())
+    }
+
+    /// Return the bottom layer, used as root directory, and the layers stacked on it
+    pub fn rootfs(&self) -> Result<(PathBuf, Vec<PathBuf>), ErrorCode> {
+        let mut layers = self
+            .layers
+            .iter()
+            .map(|digest| layer_dir(digest))
+            .collect::<Result<Vec<_>, _>>()?;
+        if let Some(layer) = layers.iter().find(|l| !l.is_dir()) {
+            log::error!("Layer {:?} of image `{}` is missing", layer, self.name);
+            return Err(ErrorCode::ImageError(3));
+        }
+        if layers.is_empty() {
+            log::error!("Image `{}` has no layer", self.name);
+            return Err(ErrorCode::ImageError(1));
+        }
+        let bottom = layers.remove(0);
+        Ok((bottom, layers))
+    }
+
+    /// Entrypoint followed by the arguments of cmd
+    pub fn command(&self) -> Vec<String> {
+        let config = &self.config;
+        config
+            .entrypoint
+            .iter()
+            .chain(config.cmd.iter())
+            .flatten()
+            .cloned()
+            .collect()
+    }
+
+    /// Parse `UID[:GID]` of the config, only numeric IDs and `root` are understood
+    pub fn user(&self) -> Result<Option<(u32, Option<u32>)>, ErrorCode> {
+        let user = match self.config.user.as_deref() {
+            Some(user) if !user.is_empty() => user,
+            _ => return Ok(None),
+        };
+        let parse = |id: &str| match id {
+            "root" => Some(0),
+            id => id.parse::<u32>().ok(),
+        };
+        let (uid, gid) = match user.split_once(':') {
+            Some((uid, gid)) => (parse(uid), parse(gid).map(Some)),
+            None => (parse(user), Some(None)),
+        };
+        match (uid, gid) {
+            (Some(uid), Some(gid)) => Ok(Some((uid, gid))),
+            _ => {
+                log::error!(
+                    "User `{}` of image `{}` is not numeric, give it by --uid",
+                    user,
+                    self.name
+                );
+                Err(ErrorCode::ArgumentInvaild("uid"))
+            }
+        }
+    }
+}
+
+/// Run a subcommand of `curn image`, list the images if none is given
+pub fn execute(command: Option<ImageCommand>) -> Result<(), ErrorCode> {
+    match command.unwrap_or(ImageCommand::List) {
+        ImageCommand::Import { path, name } => {
+            let image = import(&path, &name)?;
+            log::info!(
+                "Imported image `{}` with {} layers",
+                image.name,
+                image.layers.len()
+            );
+            Ok(())
+        }
+        ImageCommand::List => list(),
+        ImageCommand::Inspect { name } => inspect(&name),
+        ImageCommand::Remove { names } => names.iter().try_for_each(|name| remove(name)),
+        ImageCommand::Prune => prune(),
+    }
+}
+
+fn list() -> Result<(), ErrorCode> {
+    println!("{:<24} {:>10}  {:<20}  LAYERS", "NAME", "SIZE", "CREATED");
+    for image in load_images()? {
+        // layers shared with other images are counted by each of them
+        let size = image
+            .layers
+            .iter()
+            .filter_map(|layer| layer_dir(layer).ok())
+            .map(|dir| dir_size(&dir))
+            .sum();
+        let layers = image
+            .layers
+            .iter()
+            .map(|layer| short_digest(layer))
+            .collect::<Vec<_>>()
+            .join(",");
+        let created = UNIX_EPOCH + Duration::from_secs(image.created);
+        println!(
+            "{:<24} {:>10}  {:<20}  {}",
+            image.name,
+            format_size(size),
+            humantime::format_rfc3339_seconds(created),
+            layers
+        );
+    }
+    Ok(())
+}
+
+fn inspect(name: &str) -> Result<(), ErrorCode> {
+    let image = Image::load(name)?;
+    match serde_json::to_string_pretty(&image) {
+        Ok(content) => {
+            println!("{}", content);
+            Ok(())
+        }
+        Err(e) => {
+            log::error!("Cannot serialize image: {}", e);
+            Err(ErrorCode::ImageError(1))
+        }
+    }
+}
+
+/// Remove the record of an image, its layers are only removed by `prune`
+fn remove(name: &str) -> Result<(), ErrorCode> {
+    let path = image_path(name);
+    if !path.exists() {
+        log::error!("Image `{}` does not exist", name);
+        return Err(ErrorCode::ImageError(3));
+    }
+    if let Err(e) = fs::remove_file(&path) {
+        log::error!("Cannot remove image file {:?}: {}", path, e);
+        return Err(ErrorCode::ImageError(4));
+    }
+    log::info!("Removed image `{}`", name);
+    Ok(())
+}
+
+/// Remove the layers used neither by an image nor by the root of an existing container
+fn prune() -> Result<(), ErrorCode> {
+    let mut used = HashSet::new();
+    for image in load_images()? {
+        for layer in image.layers.iter() {
+            used.extend(layer_dir(layer)?.canonicalize().ok());
+        }
+    }
+    // layers of a removed image may still be stacked by a container
+    for state in list_states() {
+        let config = &state.config;
+        let layers = config.overlay.iter().flat_map(|o| o.layers.iter());
+        for layer in std::iter::once(&config.mount_dir).chain(layers) {
+            used.extend(layer.canonicalize().ok());
+        }
+    }
+
+    let dir = layers_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => {
+            log::error!("Cannot read layer directory {:?}: {}", dir, e);
+            return Err(ErrorCode::ImageError(0));
+        }
+    };
+    let (mut removed, mut freed) = (0, 0);
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.canonicalize().is_ok_and(|p| used.contains(&p)) {
+            continue;
+        }
+        let size = dir_size(&path);
+        if let Err(e) = remove_dir_all(&path) {
+            log::error!("Cannot remove layer {:?}: {}", path, e);
+            return Err(ErrorCode::ImageError(4));
+        }
+        log::info!("Removed layer {}", entry.file_name().to_string_lossy());
+        removed += 1;
+        freed += size;
+    }
+    log::info!("Removed {} layers, {} freed", removed, format_size(freed));
+    Ok(())
+}
+
+/// Load every image of the store, sorted by name
+fn load_images() -> Result<Vec<Image>, ErrorCode> {
+    let dir = images_dir();
+    let entries = match fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => {
+            log::error!("Cannot read image directory {:?}: {}", dir, e);
+            return Err(ErrorCode::ImageError(0));
+        }
+    };
+
+    let mut images = entries
+        .flatten()
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .map(|path| read_json::<Image>(&path))
+        .collect::<Result<Vec<_>, _>>()?;
+    images.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(images)
+}
+
+/// Return the size of the regular files under `path`, hard links are counted once
+fn dir_size(path: &Path) -> u64 {
+    let mut size = 0;
+    let mut inodes = HashSet::new();
+    let mut dirs = vec![path.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.path().symlink_metadata() else {
+                continue;
+            };
+            if metadata.is_dir() {
+                dirs.push(entry.path());
+            } else if metadata.is_file() && inodes.insert((metadata.dev(), metadata.ino())) {
+                size += metadata.len();
+            }
+        }
+    }
+    size
+}
+
+/// Format a size with decimal units like Docker, e.g. 77.9MB
+fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "kB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1000.0 && unit < UNITS.len() - 1 {
+        size /= 1000.0;
+        unit += 1;
+    }
+    match unit {
+        0 => format!("{}B", bytes),
+        _ => format!("{:.1}{}", size, UNITS[unit]),
+    }
+}
+
+/// First 12 hex digits of a digest, enough to tell layers apart
+fn short_digest(digest: &str) -> &str {
+    let hex = digest.strip_prefix("sha256:").unwrap_or(digest);
+    &hex[..hex.len().min(12)]
+}
+
+/// Import a root filesystem tarball, a `docker save` archive or an OCI layout, given as
+/// directory or archive, into the store under `name`
+pub fn import(path: &Path, name: &str) -> Result<Image, ErrorCode> {
+    for dir in [images_dir(), layers_dir(), tmp_dir()] {
+        if let Err(e) = create_dir_all(&dir) {
+            log::error!("Cannot create image store directory {:?}: {}", dir, e);
+            return Err(ErrorCode::ImageError(4));
+        }
+    }
+
+    if path.is_dir() {
+        return import_layout(path, name);
+    }
+    if !is_image_archive(path)? {
+        log::info!("Importing {:?} as a root filesystem", path);
+        let layer = store_layer(open(path)?, None)?;
+        let image = Image::new(name, vec![layer], ImageConfig::default());
+        image.save()?;
+        return Ok(image);
+    }
+
+    // blobs are read by path, so the archive is unpacked first
+    let dir = tmp_dir().join(random_string(12));
+    let result = open(path)
+        .and_then(decompress)
+        .and_then(|reader| {
+            Archive::new(reader).unpack(&dir).map_err(|e| {
+                log::error!("Cannot unpack image archive {:?}: {}", path, e);
+                ErrorCode::ImageError(0)
+            })
+        })
+        .and_then(|_| import_layout(&dir, name));
+    if let Err(e) = remove_dir_all(&dir) {
+        log::warn!("Cannot remove temporary directory {:?}: {}", dir, e);
+    }
+    result
+}
+
+/// Import an unpacked `docker save` archive or OCI layout
+fn import_layout(dir: &Path, name: &str) -> Result<Image, ErrorCode> {
+    let (config, layers) = if dir.join("manifest.json").exists() {
+        log::info!("Importing {:?} as a docker save archive", dir);
+        docker_manifest(dir)?
+    } else if dir.join("index.json").exists() {
+        log::info!("Importing {:?} as an OCI image layout", dir);
+        oci_manifest(dir)?
+    } else {
+        log::error!("Neither manifest.json nor index.json is found in {:?}", dir);
+        return Err(ErrorCode::ImageError(1));
+    };
+
+    let spec: ImageSpec = read_json(&config)?;
+    if spec.rootfs.diff_ids.len() != layers.len() {
+        log::error!(
+            "Image config lists {} layers while the manifest has {}",
+            spec.rootfs.diff_ids.len(),
+            layers.len()
+        );
+        return Err(ErrorCode::ImageError(1));
+    }
+    for (layer, diff_id) in layers.iter().zip(spec.rootfs.diff_ids.iter()) {
+        if layer_dir(diff_id)?.is_dir() {
+            log::info!("Layer {} already exists", diff_id);
+            continue;
+        }
+        log::info!("Unpacking layer {}", diff_id);
+        store_layer(open(layer)?, Some(diff_id))?;
+    }
+
+    let image = Image::new(name, spec.rootfs.diff_ids, spec.config.unwrap_or_default());
+    image.save()?;
+    Ok(image)
+}
+
+/// Return the config and layer paths of the first image of `manifest.json`
+fn docker_manifest(dir: &Path) -> Result<(PathBuf, Vec<PathBuf>), ErrorCode> {
+    let manifests: Vec<DockerManifest> = read_json(&dir.join("manifest.json"))?;
+    if manifests.len() > 1 {
+        log::warn!(
+            "Archive holds {} images, only the first one is imported",
+            manifests.len()
+        );
+    }
+    let manifest = manifests.into_iter().next().ok_or_else(|| {
+        log::error!("manifest.json of {:?} is empty", dir);
+        ErrorCode::ImageError(1)
+    })?;
+
+    let config = archive_path(dir, &manifest.config)?;
+    // the config is named by its digest, e.g. xxx....json, layers are checked by diff ID
+    let stem = config.file_stem().and_then(|s| s.to_str());
+    if let Some(digest) = stem.map(|hex| format!("sha256:{}", hex)) {
+        if digest_hex(&digest).is_ok() {
+            verify_blob(&config, &digest)?;
+        }
+    }
+    let layers = manifest
+        .layers
+        .iter()
+        .map(|layer| archive_path(dir, layer))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok((config, layers))
+}
+
+/// Return the config and layer blobs of the manifest of an OCI layout, the one of the
+/// native platform if the layout holds several platforms
+fn oci_manifest(dir: &Path) -> Result<(PathBuf, Vec<PathBuf>), ErrorCode> {
+    let mut index: OciIndex = read_json(&dir.join("index.json"))?;
+    for _ in 0..MAX_INDEX_DEPTH {
+        let descriptor = select_manifest(index.manifests).ok_or_else(|| {
+            log::error!("No manifest is found in {:?}", dir);
+            ErrorCode::ImageError(1)
+        })?;
+        let blob = verified_blob(dir, &descriptor.digest)?;
+        if is_index(&descriptor.media_type) {
+            index = read_json(&blob)?;
+            continue;
+        }
+
+        let manifest: OciManifest = read_json(&blob)?;
+        let config = verified_blob(dir, &manifest.config.digest)?;
+        let layers = manifest
+            .layers
+            .iter()
+            .map(|layer| verified_blob(dir, &layer.digest))
+            .collect::<Result<Vec<_>, _>>()?;
+        return Ok((config, layers));
+    }
+
+    log::error!("Indexes of {:?} are nested too deeply", dir);
+    Err(ErrorCode::ImageError(1))
+}
+
+/// Pick the manifest of the native platform, or the first one if there is none
+fn select_manifest(manifests: Vec<Descriptor>) -> Option<Descriptor> {
+    let native = manifests.iter().position(|m| {
+        m.platform
+            .as_ref()
+            .is_some_and(|p| p.os == "linux" && is_native_arch(&p.architecture))
+    });
+    manifests.into_iter().nth(native.unwrap_or(0))
+}
+
+/// Image indexes of OCI and manifest lists of Docker
+fn is_index(media_type: &str) -> bool {
+    media_type.ends_with("image.index.v1+json") || media_type.ends_with("manifest.list.v2+json")
+}
+
+/// Unpack a layer into the store, return the digest of its uncompressed content
+fn store_layer(reader: impl Read, expected: Option<&str>) -> Result<String, ErrorCode> {
+    let tmp = tmp_dir().join(random_string(12));
+    let digest = create_dir_all(&tmp)
+        .map_err(|e| {
+            log::error!("Cannot create layer directory {:?}: {}", tmp, e);
+            ErrorCode::ImageError(4)
+        })
+        .and_then(|_| unpack_layer(reader, &tmp))
+        .and_then(|digest| match expected {
+            Some(expected) if expected != digest => {
+                log::error!("Layer has digest {}, expected {}", digest, expected);
+                Err(ErrorCode::ImageError(5))
+            }
+            _ => Ok(digest),
+        })
+        .and_then(|digest| {
+            let dir = layer_dir(&digest)?;
+            if dir.is_dir() {
+                log::info!("Layer {} already exists", digest);
+                return Ok(digest);
+            }
+            fs::rename(&tmp, &dir).map_err(|e| {
+                log::error!("Cannot move layer to {:?}: {}", dir, e);
+                ErrorCode::ImageError(4)
+            })?;
+            Ok(digest)
+        });
+    if tmp.exists() {
+        let _ = remove_dir_all(&tmp);
+    }
+    digest
+}
+
+/// Unpack a tar layer, gzip or zstd compressed or not, into `dir` and turn its whiteouts
+/// into the ones of overlayfs, return the digest of the uncompressed tar
+fn unpack_layer(reader: impl Read, dir: &Path) -> Result<String, ErrorCode> {
+    let unpack_error = |e: io::Error| {
+        log::error!("Cannot unpack layer into {:?}: {}", dir, e);
+        ErrorCode::ImageError(2)
+    };
+    let mut reader = DigestReader::new(decompress(reader)?);
+
+    let mut archive = Archive::new(&mut reader);
+    archive.set_preserve_permissions(true);
+    // files keep the owners of the image unless nobody else may own them
+    archive.set_preserve_ownerships(!is_rootless());
+    archive.set_unpack_xattrs(true);
+    for entry in archive.entries().map_err(unpack_error)? {
+        let mut entry = entry.map_err(unpack_error)?;
+        let path = entry.path().map_err(unpack_error)?.into_owned();
+        let name = path.file_name().map(|n| n.to_string_lossy().into_owned());
+        let whiteout = match name.as_deref() {
+            Some(OPAQUE_WHITEOUT) => Some(None),
+            Some(name) => name.strip_prefix(WHITEOUT_PREFIX).map(Some),
+            None => None,
+        };
+        let Some(whiteout) = whiteout else {
+            entry.unpack_in(dir).map_err(unpack_error)?;
+            continue;
+        };
+
+        let parent = path.parent().and_then(relative_path);
+        let (Some(parent), true) = (parent, whiteout.is_none_or(is_file_name)) else {
+            log::error!("Whiteout {:?} escapes the layer", path);
+            return Err(ErrorCode::ImageError(2));
+        };
+        let parent = whiteout_parent(dir, &parent)?;
+        // both markers of overlayfs need root, the character device and the trusted xattr
+        if is_rootless() {
+            log::error!("Cannot import whiteout {:?} without root", path);
+            return Err(ErrorCode::RootlessUnsupported("whiteouts of image layers"));
+        }
+        match whiteout {
+            Some(name) => create_whiteout(&parent.join(name))?,
+            None => set_opaque(&parent)?,
+        }
+    }
+
+    // blocks padding the end of the archive are part of the digest
+    io::copy(&mut reader, &mut io::sink()).map_err(unpack_error)?;
+    Ok(reader.digest())
+}
+
+/// Return the directory of a whiteout inside the layer, created if missing, refusing
+/// symbolic links on the way like `unpack_in` does for the other entries
+fn whiteout_parent(dir: &Path, parent: &Path) -> Result<PathBuf, ErrorCode> {
+    let mut path = dir.to_path_buf();
+    for component in parent.components() {
+        path.push(component);
+        match path.symlink_metadata() {
+            Ok(metadata) if metadata.is_dir() => {}
+            Ok(_) => {
+                log::error!("Parent {:?} of a whiteout is not a directory", path);
+                return Err(ErrorCode::ImageError(2));
+            }
+            Err(_) => fs::create_dir(&path).map_err(|e| {
+                log::error!("Cannot create directory {:?}: {}", path, e);
+                ErrorCode::ImageError(2)
+            })?,
+        }
+    }
+    Ok(path)
+}
+
+/// Whether a whiteout names a single entry of its directory, e.g. not `.wh..`
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains('/')
+}
+
+/// Hide a file of the lower layers by a 0/0 character device
+fn create_whiteout(path: &Path) -> Result<(), ErrorCode> {
+    if path.symlink_metadata().is_ok() {
+        let _ = fs::remove_file(path).or_else(|_| remove_dir_all(path));
+    }
+    mknod(path, SFlag::S_IFCHR, Mode::empty(), makedev(0, 0)).map_err(|e| {
+        log::error!("Cannot create whiteout {:?}: {}", path, e);
+        ErrorCode::ImageError(2)
+    })
+}
+
+/// Hide the content of the lower layers under a directory
+fn set_opaque(path: &Path) -> Result<(), ErrorCode> {
+    let c_path = CString::new(path.as_os_str().as_bytes()).expect("Path has no nul byte");
+    let c_name = CString::new(OVERLAY_OPAQUE_XATTR).expect("Must be valid");
+    let res = unsafe {
+        libc::lsetxattr(
+            c_path.as_ptr(),
+            c_name.as_ptr(),
+            b"y".as_ptr() as *const libc::c_void,
+            1,
+            0,
+        )
+    };
+    if res != 0 {
+        log::error!(
+            "Cannot mark {:?} as opaque: {}",
+            path,
+            io::Error::last_os_error()
+        );
+        return Err(ErrorCode::ImageError(2));
+    }
+    Ok(())
+}
+
+/// Whether the tar at `path` is an image archive rather than a root filesystem
+fn is_image_archive(path: &Path) -> Result<bool, ErrorCode> {
+    let mut archive = Archive::new(decompress(open(path)?)?);
+    let entries = archive.entries().map_err(|e| {
+        log::error!("Cannot read archive {:?}: {}", path, e);
+        ErrorCode::ImageError(0)
+    })?;
+    for entry in entries {
+        let entry = entry.map_err(|e| {
+            log::error!("Cannot read archive {:?}: {}", path, e);
+            ErrorCode::ImageError(0)
+        })?;
+        let is_manifest = entry
+            .path()
+            .ok()
+            .and_then(|p| relative_path(&p))
+            .is_some_and(|p| p == Path::new("manifest.json") || p == Path::new("oci-layout"));
+        if is_manifest {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Detect gzip and zstd by their magic numbers, anything else is read as is
+fn decompress<'a>(reader: impl Read + 'a) -> Result<Box<dyn Read + 'a>, ErrorCode> {
+    let mut reader = BufReader::new(reader);
+    let magic = reader.fill_buf().map_err(|e| {
+        log::error!("Cannot read archive: {}", e);
+        ErrorCode::ImageError(0)
+    })?;
+
+    if magic.starts_with(&GZIP_MAGIC) {
+        Ok(Box::new(MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(&ZSTD_MAGIC) {
+        let decoder = StreamingDecoder::new(reader).map_err(|e| {
+            log::error!("Cannot read zstd archive: {}", e);
+            ErrorCode::ImageError(0)
+        })?;
+        Ok(Box::new(decoder))
+    } else {
+        Ok(Box::new(reader))
+    }
+}
+
+/// Compute sha256 of everything read through it
+struct DigestReader<R> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> DigestReader<R> {
+    fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    fn digest(self) -> String {
+        format!("sha256:{:x}", self.hasher.finalize())
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+fn open(path: &Path) -> Result<File, ErrorCode> {
+    File::open(path).map_err(|e| {
+        log::error!("Cannot open {:?}: {}", path, e);
+        ErrorCode::ImageError(0)
+    })
+}
+
+fn read_json<T: DeserializeOwned>(path: &Path) -> Result<T, ErrorCode> {
+    let content = fs::read_to_string(path).map_err(|e| {
+        log::error!("Cannot read {:?}: {}", path, e);
+        ErrorCode::ImageError(0)
+    })?;
+    serde_json::from_str(&content).map_err(|e| {
+        log::error!("Cannot parse {:?}: {}", path, e);
+        ErrorCode::ImageError(1)
+    })
+}
+
+/// Return `path` without `.` components, `None` if it is absolute or escapes by `..`
+fn relative_path(path: &Path) -> Option<PathBuf> {
+    let mut relative = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => relative.push(name),
+            Component::CurDir => {}
+            _ => return None,
+        }
+    }
+    Some(relative)
+}
+
+/// Resolve a path given by `manifest.json` inside the archive directory
+fn archive_path(dir: &Path, path: &Path) -> Result<PathBuf, ErrorCode> {
+    match relative_path(path) {
+        Some(path) if !path.as_os_str().is_empty() => Ok(dir.join(path)),
+        _ => {
+            log::error!("Path {:?} of manifest.json escapes the archive", path);
+            Err(ErrorCode::ImageError(1))
+        }
+    }
+}
+
+/// Return the hex part of a `sha256:xxx...` digest
+fn digest_hex(digest: &str) -> Result<&str, ErrorCode> {
+    match digest.strip_prefix("sha256:") {
+        Some(hex) if hex.len() == 64 && hex.chars().all(|c| c.is_ascii_hexdigit()) => Ok(hex),
+        _ => {
+            log::error!("Unsupported digest `{}`", digest);
+            Err(ErrorCode::ImageError(1))
+        }
+    }
+}
+
+/// Return a blob of an OCI layout, e.g. blobs/sha256/xxx...
+fn blob_path(dir: &Path, digest: &str) -> Result<PathBuf, ErrorCode> {
+    Ok(dir.join("blobs/sha256").join(digest_hex(digest)?))
+}
+
+/// Return a blob of an OCI layout once its content is checked against its digest
+fn verified_blob(dir: &Path, digest: &str) -> Result<PathBuf, ErrorCode> {
+    let path = blob_path(dir, digest)?;
+    verify_blob(&path, digest)?;
+    Ok(path)
+}
+
+fn verify_blob(path: &Path, digest: &str) -> Result<(), ErrorCode> {
+    let mut reader = DigestReader::new(open(path)?);
+    io::copy(&mut reader, &mut io::sink()).map_err(|e| {
+        log::error!("Cannot read {:?}: {}", path, e);
+        ErrorCode::ImageError(0)
+    })?;
+    let actual = reader.digest();
+    if actual != digest {
+        log::error!("Blob {:?} has digest {}, expected {}", path, actual, digest);
+        return Err(ErrorCode::ImageError(5));
+    }
+    Ok(())
+}
+
+fn images_dir() -> PathBuf {
+    store_root().join("images")
+}
+
+fn layers_dir() -> PathBuf {
+    store_root().join("layers")
+}
+
+/// Archives and layers being unpacked, on the same filesystem as the layers
+fn tmp_dir() -> PathBuf {
+    store_root().join("tmp")
+}
+
+/// Return the file of an image, e.g. /var/lib/curn/images/ubuntu.json
+fn image_path(name: &str) -> PathBuf {
+    images_dir().join(format!("{}.json", name))
+}
+
+/// Return the unpacked layer of a digest, e.g. /var/lib/curn/layers/xxx...
+fn layer_dir(digest: &str) -> Result<PathBuf, ErrorCode> {
+    Ok(layers_dir().join(digest_hex(digest)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{store::set_store_root, testing::TempDir};
+    use flate2::{write::GzEncoder, Compression};
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+    use std::{env, os::unix::fs::FileTypeExt, process};
+    use tar::{Builder, EntryType, Header};
+
+    fn layer_tar(files: &[(&str, &str)]) -> Vec<u8> {
+        let mut builder = Builder::new(Vec::new());
+        for (path, content) in files {
+            let mut header = Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            header.set_cksum();
+            builder
+                .append_data(&mut header, path, content.as_bytes())
+                .unwrap();
+        }
+        builder.into_inner().unwrap()
+    }
+
+    /// Create the directories of the store of the tests, never the one of the host
+    fn test_store() {
+        let root = env::temp_dir().join(format!("curn-store-{}", process::id()));
+        set_store_root(Some(root.clone())).unwrap();
+        assert_eq!(store_root(), root);
+        for dir in [images_dir(), layers_dir(), tmp_dir()] {
+            create_dir_all(dir).unwrap();
+        }
+    }
+
+    /// Write a blob of an OCI layout, return its digest
+    fn write_blob(dir: &Path, content: &[u8]) -> String {
+        let digest = format!("sha256:{:x}", Sha256::digest(content));
+        fs::write(blob_path(dir, &digest).unwrap(), content).unwrap();
+        digest
+    }
+
+    /// Value of the opaque marker of overlayfs on a directory
+    fn opaque_of(path: &Path) -> Option<Vec<u8>> {
+        let path = CString::new(path.as_os_str().as_bytes()).unwrap();
+        let name = CString::new(OVERLAY_OPAQUE_XATTR).unwrap();
+        let mut value = [0u8; 8];
+        let size = unsafe {
+            libc::lgetxattr(
+                path.as_ptr(),
+                name.as_ptr(),
+                value.as_mut_ptr() as *mut libc::c_void,
+                value.len(),
+            )
+        };
+        (size >= 0).then(|| value[..size as usize].to_vec())
+    }
+
+    #[test]
+    fn unpack_compressed_layer() {
+        let tar = layer_tar(&[("etc/os-release", "ID=test\n"), ("./bin/sh", "#!")]);
+        let expected = format!("sha256:{:x}", Sha256::digest(&tar));
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        io::copy(&mut tar.as_slice(), &mut gzip).unwrap();
+        let gzip = gzip.finish().unwrap();
+        let zstd = compress_to_vec(tar.as_slice(), CompressionLevel::Fastest);
+
+        for (i, layer) in [gzip, zstd].iter().enumerate() {
+            let dir = TempDir::new(&format!("layer-{}", i));
+            let digest = unpack_layer(layer.as_slice(), dir.path());
+            let content = fs::read_to_string(dir.join("etc/os-release"));
+
+            // digest is the diff ID, the one of the uncompressed tar
+            assert_eq!(digest.unwrap(), expected);
+            assert_eq!(content.unwrap(), "ID=test\n");
+        }
+    }
+
+    #[test]
+    fn whiteouts_of_overlayfs() {
+        if is_rootless() {
+            return;
+        }
+        let dir = TempDir::new("overlay");
+        let tar = layer_tar(&[
+            ("etc/hosts", "127.0.0.1 localhost\n"),
+            ("etc/.wh.hosts", ""),
+            ("etc/.wh.passwd", ""),
+            ("usr/.wh..wh..opq", ""),
+        ]);
+        let digest = unpack_layer(tar.as_slice(), dir.path());
+        let hosts = fs::symlink_metadata(dir.join("etc/hosts"));
+        let passwd = fs::symlink_metadata(dir.join("etc/passwd"));
+        let opaque = opaque_of(&dir.join("usr"));
+        let marker = dir.join("usr").join(OPAQUE_WHITEOUT).exists();
+
+        assert!(digest.is_ok());
+        for whiteout in [hosts.unwrap(), passwd.unwrap()] {
+            assert!(whiteout.file_type().is_char_device());
+            assert_eq!(whiteout.rdev(), 0);
+        }
+        assert_eq!(opaque.as_deref(), Some(&b"y"[..]));
+        assert!(!marker);
+    }
+
+    #[test]
+    fn whiteouts_never_follow_symlinks() {
+        let dir = TempDir::new("whiteout");
+        let host = dir.join("host");
+        let layer = dir.join("layer");
+        create_dir_all(&host).unwrap();
+        create_dir_all(&layer).unwrap();
+        fs::write(host.join("passwd"), "root:x:0:0::/root:/bin/sh\n").unwrap();
+
+        let malicious = |whiteout: &str| {
+            let mut builder = Builder::new(Vec::new());
+            let mut header = Header::new_gnu();
+            header.set_entry_type(EntryType::Symlink);
+            header.set_size(0);
+            header.set_mode(0o777);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            builder.append_link(&mut header, "etc", &host).unwrap();
+            let mut header = Header::new_gnu();
+            header.set_size(0);
+            header.set_mode(0o644);
+            header.set_uid(0);
+            header.set_gid(0);
+            header.set_mtime(0);
+            builder
+                .append_data(&mut header, whiteout, io::empty())
+                .unwrap();
+            builder.into_inner().unwrap()
+        };
+        let whiteout = unpack_layer(malicious("etc/.wh.passwd").as_slice(), &layer);
+        let _ = remove_dir_all(layer.join("etc"));
+        let opaque = unpack_layer(malicious("etc/.wh..wh..opq").as_slice(), &layer);
+        let escape = unpack_layer(layer_tar(&[("etc2/.wh...", "")]).as_slice(), &layer);
+        let passwd = fs::symlink_metadata(host.join("passwd"));
+        let opaque_host = opaque_of(&host);
+
+        assert!(matches!(whiteout, Err(ErrorCode::ImageError(2))));
+        assert!(matches!(opaque, Err(ErrorCode::ImageError(2))));
+        assert!(matches!(escape, Err(ErrorCode::ImageError(2))));
+        assert!(passwd.unwrap().is_file());
+        assert!(opaque_host.is_none());
+    }
+
+    #[test]
+    fn native_manifest_is_selected() {
+        let native = match env::consts::ARCH {
+            "x86_64" => "amd64",
+            "aarch64" => "arm64",
+            arch => arch,
+        };
+        let foreign = if native == "s390x" {
+            "riscv64"
+        } else {
+            "s390x"
+        };
+        let index = |arches: &[&str]| {
+            let manifests = arches
+                .iter()
+                .map(|arch| {
+                    format!(
+                        r#"{{ "digest": "sha256:{0}", "platform": {{ "architecture": "{0}", "os": "linux" }} }}"#,
+                        arch
+                    )
+                })
+                .collect::<Vec<_>>();
+            let index = format!(r#"{{ "manifests": [{}] }}"#, manifests.join(","));
+            serde_json::from_str::<OciIndex>(&index).unwrap().manifests
+        };
+        assert_eq!(
+            select_manifest(index(&[foreign, native])).unwrap().digest,
+            format!("sha256:{}", native)
+        );
+        // an image without a native manifest falls back to its first one
+        assert_eq!(
+            select_manifest(index(&[foreign, "mips64"])).unwrap().digest,
+            format!("sha256:{}", foreign)
+        );
+        assert!(select_manifest(Vec::new()).is_none());
+    }
+
+    #[test]
+    fn layer_digest_must_match_diff_id() {
+        test_store();
+        let tar = layer_tar(&[("etc/mismatch", "")]);
+        let digest = format!("sha256:{:x}", Sha256::digest(&tar));
+        let result = store_layer(tar.as_slice(), Some(&format!("sha256:{}", "0".repeat(64))));
+
+        assert!(matches!(result, Err(ErrorCode::ImageError(5))));
+        assert!(!layer_dir(&digest).unwrap().exists());
+    }
+
+    #[test]
+    fn import_docker_save_archive() {
+        test_store();
+        let dir = TempDir::new("docker-save");
+        create_dir_all(dir.join("abc")).unwrap();
+        let tar = layer_tar(&[("etc/docker", "save\n")]);
+        let diff_id = format!("sha256:{:x}", Sha256::digest(&tar));
+        fs::write(dir.join("abc/layer.tar"), &tar).unwrap();
+        fs::write(
+            dir.join("config.json"),
+            format!(
+                r#"{{ "config": {{ "Cmd": ["/bin/sh"] }}, "rootfs": {{ "diff_ids": ["{}"] }} }}"#,
+                diff_id
+            ),
+        )
+        .unwrap();
+        fs::write(
+            dir.join("manifest.json"),
+            r#"[{ "Config": "config.json", "Layers": ["abc/layer.tar"] }]"#,
+        )
+        .unwrap();
+
+        let name = format!("docker-{}", process::id());
+        let image = import_layout(dir.path(), &name);
+        let content = fs::read_to_string(layer_dir(&diff_id).unwrap().join("etc/docker"));
+        let loaded = Image::load(&name);
+        let _ = remove_dir_all(layer_dir(&diff_id).unwrap());
+        let _ = fs::remove_file(image_path(&name));
+
+        let image = image.unwrap();
+        assert_eq!(image.layers, vec![diff_id]);
+        assert_eq!(image.command(), vec!["/bin/sh"]);
+        assert_eq!(content.unwrap(), "save\n");
+        assert_eq!(loaded.unwrap().name, name);
+    }
+
+    #[test]
+    fn import_oci_layout() {
+        test_store();
+        let dir = TempDir::new("oci-layout");
+        create_dir_all(dir.join("blobs/sha256")).unwrap();
+        let tar = layer_tar(&[("etc/oci", "layout\n")]);
+        let diff_id = format!("sha256:{:x}", Sha256::digest(&tar));
+        let mut gzip = GzEncoder::new(Vec::new(), Compression::default());
+        io::copy(&mut tar.as_slice(), &mut gzip).unwrap();
+        let layer = write_blob(dir.path(), &gzip.finish().unwrap());
+        let config = write_blob(
+            dir.path(),
+            format!(
+                r#"{{ "config": {{ "Entrypoint": ["/init"], "User": "1000" }}, "rootfs": {{ "diff_ids": ["{}"] }} }}"#,
+                diff_id
+            )
+            .as_bytes(),
+        );
+        let manifest = write_blob(
+            dir.path(),
+            format!(
+                r#"{{ "config": {{ "digest": "{}" }}, "layers": [{{ "digest": "{}" }}] }}"#,
+                config, layer
+            )
+            .as_bytes(),
+        );
+        fs::write(
+            dir.join("index.json"),
+            format!(r#"{{ "manifests": [{{ "digest": "{}" }}] }}"#, manifest),
+        )
+        .unwrap();
+
+        // a blob changed after its manifest was written
+        let blob = blob_path(dir.path(), &config).unwrap();
+        let original = fs::read(&blob).unwrap();
+        fs::write(&blob, b"{}").unwrap();
+        let tampered = import_layout(dir.path(), "tampered");
+        fs::write(&blob, original).unwrap();
+
+        let name = format!("oci-{}", process::id());
+        let image = import_layout(dir.path(), &name);
+        let content = fs::read_to_string(layer_dir(&diff_id).unwrap().join("etc/oci"));
+        let _ = remove_dir_all(layer_dir(&diff_id).unwrap());
+        let _ = fs::remove_file(image_path(&name));
+
+        assert!(matches!(tampered, Err(ErrorCode::ImageError(5))));
+        let image = image.unwrap();
+        assert_eq!(image.layers, vec![diff_id]);
+        assert_eq!(image.command(), vec!["/init"]);
+        assert_eq!(image.user().unwrap(), Some((1000, None)));
+        assert_eq!(content.unwrap(), "layout\n");
+    }
+
+    #[test]
+    fn paths_stay_inside_the_archive() {
+        let dir = Path::new("/archive");
+        assert_eq!(
+            archive_path(dir, Path::new("./abc/layer.tar")).unwrap(),
+            dir.join("abc/layer.tar")
+        );
+        assert!(archive_path(dir, Path::new("../layer.tar")).is_err());
+        assert!(archive_path(dir, Path::new("/layer.tar")).is_err());
+        assert!(blob_path(dir, "sha256:../../etc/passwd").is_err());
+        assert!(digest_hex(&format!("sha512:{}", "0".repeat(64))).is_err());
+    }
+
+    #[test]
+    fn sizes_and_digests_are_shortened() {
+        assert_eq!(format_size(999), "999B");
+        assert_eq!(format_size(77_850_000), "77.8MB");
+        assert_eq!(format_size(2_000_000_000), "2.0GB");
+        assert_eq!(
+            short_digest(&format!("sha256:818ae1848ea9{}", "0".repeat(52))),
+            "818ae1848ea9"
+        );
+    }
+
+    #[test]
+    fn user_of_config() {
+        let image = |user: &str| {
+            let config = ImageConfig {
+                user: Some(user.to_string()),
+                ..ImageConfig::default()
+            };
+            Image::new("test", Vec::new(), config)
+        };
+        assert_eq!(image("").user().unwrap(), None);
+        assert_eq!(image("1000").user().unwrap(), Some((1000, None)));
+        assert_eq!(image("root:10").user().unwrap(), Some((0, Some(10))));
+        assert!(image("nobody").user().is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use crate::{error::ErrorCode, signals::FORWARDED_SIGNALS};
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::{
+        signal::{kill, SigSet, SigmaskHow, Signal},
+        signalfd::{SfdFlags, SignalFd},
+        wait::{waitpid, WaitPidFlag, WaitStatus},
+    },
+    unistd::{fork, getpid, setpgid, tcsetpgrp, ForkResult, Pid},
+};
+use std::{io::stdin, os::fd::AsFd};
+
+/// Stay PID 1 of the container: fork the command, forward signals to it and reap
+/// every zombie, then exit with the status of the command once it exits
+pub fn run_init(tty: bool, exec: impl FnOnce() -> isize) -> isize {
+    log::debug!("Running as init of the container");
+
+    let mut mask = SigSet::empty();
+    for signal in FORWARDED_SIGNALS {
+        mask.add(signal);
+    }
+    mask.add(Signal::SIGCHLD);
+    // the command takes the terminal from the background, see below
+    mask.add(Signal::SIGTTOU);
+
+    let old_mask = match mask.thread_swap_mask(SigmaskHow::SIG_BLOCK) {
+        Ok(old_mask) => old_mask,
+        Err(e) => {
+            log::error!("Init cannot block signals: {:?}", e);
+            return ErrorCode::ChildProcessError(2).get_retcode() as isize;
+        }
+    };
+    let signals = match SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+    {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::error!("Init cannot create signalfd: {:?}", e);
+            return ErrorCode::ChildProcessError(2).get_retcode() as isize;
+        }
+    };
+
+    match unsafe { fork() } {
+        Ok(ForkResult::Child) => {
+            drop(signals);
+            // a process group of its own in the foreground of the terminal receives Ctrl-C
+            if tty {
+                let _ = setpgid(Pid::from_raw(0), Pid::from_raw(0));
+                let _ = tcsetpgrp(stdin().as_fd(), getpid());
+            }
+            let _ = old_mask.thread_set_mask();
+            exec()
+        }
+        Ok(ForkResult::Parent { child }) => {
+            log::debug!("Init forked the command (pid {})", child);
+            match reap(child, &signals) {
+                Ok(code) => code as isize,
+                Err(e) => {
+                    log::error!("Init cannot wait for the command: {:?}", e);
+                    let _ = kill(child, Signal::SIGKILL);
+                    ErrorCode::ChildProcessError(2).get_retcode() as isize
+                }
+            }
+        }
+        Err(e) => {
+            log::error!("Init cannot fork the command: {:?}", e);
+            ErrorCode::ChildProcessError(1).get_retcode() as isize
+        }
+    }
+}
+
+/// Return exit code of the command, or `128 + signo` if it was killed by a signal
+fn reap(command: Pid, signals: &SignalFd) -> Result<i32, Errno> {
+    loop {
+        let mut fds = [PollFd::new(signals.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::NONE) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(e) => return Err(e),
+        }
+
+        while let Ok(Some(info)) = signals.read_signal() {
+            match Signal::try_from(info.ssi_signo as i32) {
+                Ok(signal) if FORWARDED_SIGNALS.contains(&signal) => {
+                    log::debug!("Init forwarding {} to the command", signal);
+                    let _ = kill(command, signal);
+                }
+                _ => {}
+            }
+        }
+
+        // one SIGCHLD may stand for several exited children
+        let mut status = None;
+        loop {
+            match waitpid(None, Some(WaitPidFlag::WNOHANG)) {
+                Ok(WaitStatus::Exited(pid, code)) if pid == command => status = Some(code),
+                Ok(WaitStatus::Signaled(pid, signal, _)) if pid == command => {
+                    status = Some(128 + signal as i32)
+                }
+                Ok(WaitStatus::StillAlive) | Err(Errno::ECHILD) => break,
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(e) => return Err(e),
+            }
+        }
+        // remaining processes are killed with the PID namespace when init exits
+        if let Some(code) = status {
+            log::debug!("Command of init exited with {}", code);
+            return Ok(code);
+        }
+    }
+}
@@ -1,6 +1,23 @@
 use crate::error::ErrorCode;
-use nix::sys::socket::{recv, send, socketpair, AddressFamily, MsgFlags, SockFlag, SockType};
-use std::os::fd::{IntoRawFd, RawFd};
+use nix::{
+    sys::{
+        socket::{
+            recv, recvmsg, send, sendmsg, socketpair, AddressFamily, ControlMessage,
+            ControlMessageOwned, MsgFlags, SockFlag, SockType,
+        },
+        stat::Mode,
+    },
+    unistd::mkfifo,
+};
+use std::{
+    fs::{File, OpenOptions},
+    io::{IoSlice, IoSliceMut, Read, Write},
+    os::{
+        fd::{FromRawFd, IntoRawFd, OwnedFd, RawFd},
+        unix::fs::OpenOptionsExt,
+    },
+    path::Path,
+};
 
 pub fn generate_socketpair() -> Result<(RawFd, RawFd), ErrorCode> {
     log::debug!("Generating socket pair");
@@ -44,7 +61,7 @@ pub fn send_str(fd: RawFd, value: &str) -> Result<(), ErrorCode> {
     log::debug!("Sending string `{}`", value);
 
     let buf = value.as_bytes();
-    if let Err(e) = send(fd, &buf, MsgFlags::empty()) {
+    if let Err(e) = send(fd, buf, MsgFlags::empty()) {
         log::error!("Cannot send string through socket: {:?}", e);
         return Err(ErrorCode::SocketError(1));
     }
@@ -67,3 +84,108 @@ pub fn recv_str(fd: RawFd) -> Result<String, ErrorCode> {
     log::debug!("Received string `{}`", value);
     Ok(value)
 }
+
+/// Pass a file descriptor to the other process, it is duplicated into it
+pub fn send_fd(fd: RawFd, value: RawFd) -> Result<(), ErrorCode> {
+    // nothing is logged before sending, the seccomp listener may be the one sent
+    let buf = [0u8];
+    let fds = [value];
+    if let Err(e) = sendmsg::<()>(
+        fd,
+        &[IoSlice::new(&buf)],
+        &[ControlMessage::ScmRights(&fds)],
+        MsgFlags::empty(),
+        None,
+    ) {
+        log::error!("Cannot send file descriptor through socket: {:?}", e);
+        return Err(ErrorCode::SocketError(1));
+    }
+    log::debug!("Sent file descriptor {}", value);
+
+    Ok(())
+}
+
+pub fn recv_fd(fd: RawFd) -> Result<OwnedFd, ErrorCode> {
+    let mut buf = [0u8];
+    let mut iov = [IoSliceMut::new(&mut buf)];
+    let mut cmsg = nix::cmsg_space!(RawFd);
+    let msg = match recvmsg::<()>(fd, &mut iov, Some(&mut cmsg), MsgFlags::MSG_CMSG_CLOEXEC) {
+        Ok(msg) => msg,
+        Err(e) => {
+            log::error!("Cannot receive file descriptor through socket: {:?}", e);
+            return Err(ErrorCode::SocketError(2));
+        }
+    };
+
+    let value = msg.cmsgs().ok().and_then(|mut cmsgs| {
+        cmsgs.find_map(|c| match c {
+            ControlMessageOwned::ScmRights(fds) => fds.first().copied(),
+            _ => None,
+        })
+    });
+    match value {
+        Some(value) => {
+            log::debug!("Received file descriptor {}", value);
+            Ok(unsafe { OwnedFd::from_raw_fd(value) })
+        }
+        None => {
+            log::error!("No file descriptor received through socket");
+            Err(ErrorCode::SocketError(2))
+        }
+    }
+}
+
+pub fn generate_fifo(path: &Path) -> Result<(), ErrorCode> {
+    log::debug!("Generating fifo {:?}", path);
+
+    if let Err(e) = mkfifo(path, Mode::S_IRUSR | Mode::S_IWUSR) {
+        log::error!("Cannot create fifo: {:?}", e);
+        return Err(ErrorCode::SocketError(5));
+    }
+
+    Ok(())
+}
+
+/// Block until the other side opens the fifo and sends a bool value
+pub fn recv_fifo_bool(path: &Path) -> Result<bool, ErrorCode> {
+    let mut fifo = match File::open(path) {
+        Ok(fifo) => fifo,
+        Err(e) => {
+            log::error!("Cannot open fifo for reading: {:?}", e);
+            return Err(ErrorCode::SocketError(6));
+        }
+    };
+
+    let mut buf: [u8; 1] = [0];
+    if let Err(e) = fifo.read_exact(&mut buf) {
+        log::error!("Cannot receive bool value through fifo: {:?}", e);
+        return Err(ErrorCode::SocketError(8));
+    }
+    let value = buf[0] == 1;
+    log::debug!("Received a bool value `{}` from fifo", value);
+    Ok(value)
+}
+
+/// Fail instead of blocking if nobody is waiting on the other side of the fifo
+pub fn send_fifo_bool(path: &Path, value: bool) -> Result<(), ErrorCode> {
+    log::debug!("Sending a bool value `{}` to fifo", value);
+
+    let mut fifo = match OpenOptions::new()
+        .write(true)
+        .custom_flags(libc::O_NONBLOCK)
+        .open(path)
+    {
+        Ok(fifo) => fifo,
+        Err(e) => {
+            log::error!("Cannot open fifo for writing: {:?}", e);
+            return Err(ErrorCode::SocketError(6));
+        }
+    };
+
+    if let Err(e) = fifo.write_all(&[value as u8]) {
+        log::error!("Cannot send bool value through fifo: {:?}", e);
+        return Err(ErrorCode::SocketError(7));
+    }
+
+    Ok(())
+}
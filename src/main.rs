@@ -1,6 +1,7 @@
 #[macro_use]
 extern crate scan_fmt;
 
+mod audit;
 mod capabilities;
 mod cgroup;
 mod child;
@@ -10,10 +11,23 @@ mod container;
 mod ebpf;
 mod error;
 mod hosthname;
+mod image;
+mod init;
 mod ipc;
 mod mount;
+mod network;
+mod oci;
+mod seccomp;
+mod seccomp_notify;
+mod signals;
+mod state;
+mod store;
 mod syscall;
+#[cfg(test)]
+mod testing;
+mod tty;
 mod user_namespace;
+mod volume;
 
 use error::exit_with_retcode;
 
@@ -21,7 +35,7 @@ fn main() {
     match cli::parse_args() {
         Ok(args) => {
             log::info!("{:?}", args);
-            exit_with_retcode(container::start(args));
+            exit_with_retcode(container::execute(args.subcommand));
         }
         Err(e) => {
             log::error!("Error while parsing arguments:\n\t{}", e);
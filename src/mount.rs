@@ -1,93 +1,226 @@
-use crate::error::ErrorCode;
+use crate::{config::ContainerOpts, error::ErrorCode, store::container_dir, volume::Volume};
 use nix::{
+    errno::Errno,
     mount::{mount, umount2, MntFlags, MsFlags},
+    sys::{
+        stat::{makedev, mknod, Mode, SFlag},
+        statvfs::{statvfs, FsFlags},
+    },
     unistd::{chdir, pivot_root},
 };
+use serde::{Deserialize, Serialize};
 use std::{
-    fs::{create_dir_all, remove_dir},
-    path::PathBuf,
+    fs::{create_dir_all, remove_dir, remove_dir_all, set_permissions, OpenOptions, Permissions},
+    io,
+    os::unix::fs::{symlink, PermissionsExt},
+    path::{Path, PathBuf},
 };
 
-/// Return mounted path, e.g. /tmp/cunrc.xxx...
+// name, major and minor of the device nodes every container gets
+const DEVICES: [(&str, u64, u64); 6] = [
+    ("null", 1, 3),
+    ("zero", 1, 5),
+    ("full", 1, 7),
+    ("random", 1, 8),
+    ("urandom", 1, 9),
+    ("tty", 5, 0),
+];
+
+const DEV_SYMLINKS: [(&str, &str); 5] = [
+    ("fd", "/proc/self/fd"),
+    ("stdin", "/proc/self/fd/0"),
+    ("stdout", "/proc/self/fd/1"),
+    ("stderr", "/proc/self/fd/2"),
+    ("ptmx", "pts/ptmx"),
+];
+
+// https://github.com/moby/moby/blob/master/oci/defaults.go
+const MASKED_PATHS_DEFAULT: [&str; 12] = [
+    "/proc/asound",
+    "/proc/acpi",
+    "/proc/interrupts",
+    "/proc/kcore",
+    "/proc/keys",
+    "/proc/latency_stats",
+    "/proc/timer_list",
+    "/proc/timer_stats",
+    "/proc/sched_debug",
+    "/proc/scsi",
+    "/sys/firmware",
+    "/sys/devices/virtual/powercap",
+];
+
+const READONLY_PATHS_DEFAULT: [&str; 5] = [
+    "/proc/bus",
+    "/proc/fs",
+    "/proc/irq",
+    "/proc/sys",
+    "/proc/sysrq-trigger",
+];
+
+/// Paths hidden from the container or only readable by it, `maskedPaths` and
+/// `readonlyPaths` of OCI
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ProtectedPaths {
+    /// Covered by /dev/null, or an empty tmpfs if a directory
+    pub masked: Vec<PathBuf>,
+    pub readonly: Vec<PathBuf>,
+}
+
+impl Default for ProtectedPaths {
+    fn default() -> Self {
+        Self {
+            masked: MASKED_PATHS_DEFAULT.iter().map(PathBuf::from).collect(),
+            readonly: READONLY_PATHS_DEFAULT.iter().map(PathBuf::from).collect(),
+        }
+    }
+}
+
+impl ProtectedPaths {
+    /// Remove `--unmask-path` from the paths, `ALL` of which exposes every one,
+    /// then add `--mask-path` and `--readonly-path`
+    pub fn apply(
+        &mut self,
+        mask: &[PathBuf],
+        readonly: &[PathBuf],
+        unmask: &[PathBuf],
+    ) -> Result<(), ErrorCode> {
+        if let Some(path) = mask.iter().chain(readonly).find(|p| !p.is_absolute()) {
+            log::error!("Protected path {:?} is not absolute", path);
+            return Err(ErrorCode::ArgumentInvaild("mask-path"));
+        }
+        if unmask.iter().any(|p| p == Path::new("ALL")) {
+            self.masked.clear();
+            self.readonly.clear();
+        }
+        self.masked.retain(|p| !unmask.contains(p));
+        self.readonly.retain(|p| !unmask.contains(p));
+
+        self.masked.extend_from_slice(mask);
+        self.readonly.extend_from_slice(readonly);
+        Ok(())
+    }
+}
+
+/// Copy-on-write root, `--mount` directory is the lowest read-only layer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Overlay {
+    /// Read-only layers stacked on top of `--mount`, the last one is topmost
+    pub layers: Vec<PathBuf>,
+    /// Keep the upper layer when the container is deleted
+    pub keep_upper: bool,
+}
+
+/// Return mounted path, e.g. /var/lib/curn/containers/cunrc.xxx.../rootfs
 pub fn generate_rootpath(container_id: &str) -> Result<String, ErrorCode> {
-    Ok(format!("/tmp/{}", container_id))
+    let path = container_dir(container_id).join("rootfs");
+    path.to_str()
+        .map(String::from)
+        .ok_or(ErrorCode::ArgumentInvaild("root"))
 }
 
-pub fn set_mounts(
-    container_id: &str,
-    mount_dir: &PathBuf,
-    root_path: &String,
-    add_paths: &Vec<(PathBuf, PathBuf)>,
-    tool_dir: Option<&PathBuf>,
-) -> Result<(), ErrorCode> {
+/// Return path holding upperdir and workdir of overlay, next to the mounted path,
+/// e.g. /var/lib/curn/containers/cunrc.xxx.../overlay
+fn overlay_path(root_path: &str) -> PathBuf {
+    Path::new(root_path).with_file_name("overlay")
+}
+
+pub fn set_mounts(config: &ContainerOpts) -> Result<(), ErrorCode> {
     log::debug!("Setting mount points ...");
 
-    // remount root `/` by private mount namespace
+    // remount root `/` as slave of the host, mounts of the container never reach the
+    // host while volumes may still receive the ones of the host
     mount_directory(
         None,
         &PathBuf::from("/"),
         None,
-        vec![MsFlags::MS_REC, MsFlags::MS_PRIVATE],
+        vec![MsFlags::MS_REC, MsFlags::MS_SLAVE],
     )?;
 
     // create new root directory and mount root to it
     log::debug!(
         "Mounting container's root to temp directory `{}`",
-        root_path
+        config.root_path
     );
 
-    let new_root = PathBuf::from(root_path);
+    let new_root = PathBuf::from(&config.root_path);
     create_directory(&new_root)?;
-    mount_directory(
-        Some(&mount_dir),
-        &new_root,
-        None,
-        vec![MsFlags::MS_BIND, MsFlags::MS_PRIVATE],
-    )?;
+    match &config.overlay {
+        Some(overlay) => mount_overlay(&config.mount_dir, overlay, &new_root)?,
+        None => mount_directory(
+            Some(&config.mount_dir),
+            &new_root,
+            None,
+            vec![MsFlags::MS_BIND],
+        )?,
+    }
+    mount_directory(None, &new_root, None, vec![MsFlags::MS_PRIVATE])?;
+
+    // volumes may be mounted into /dev as well
+    mount_dev(&new_root)?;
 
-    // mount additional volumes
+    // parents are mounted before the volumes and tmpfs mounts nested in them
     log::debug!("Mounting additional volumes");
-    for (from_path, mnt_path) in add_paths.iter() {
-        log::debug!(
-            "Mount host's {} to container's /{}",
-            from_path.to_str().unwrap(),
-            mnt_path.to_str().unwrap()
-        );
-
-        let mnt_path = new_root.join(mnt_path);
-        create_directory(&mnt_path)?;
-        mount_directory(
-            Some(from_path),
-            &mnt_path,
-            None,
-            vec![MsFlags::MS_PRIVATE, MsFlags::MS_BIND],
-        )?;
+    let mut mounts = config
+        .volumes
+        .iter()
+        .map(|v| (&v.destination, Some(v), None))
+        .chain(config.tmpfs.iter().map(|t| (&t.destination, None, Some(t))))
+        .collect::<Vec<_>>();
+    mounts.sort_by_key(|(destination, _, _)| destination.components().count());
+    for (destination, volume, tmpfs) in mounts {
+        let mnt_path = mount_point(&new_root, destination)?;
+        if let Some(volume) = volume {
+            mount_volume(volume, &mnt_path)?;
+        }
+        if let Some(tmpfs) = tmpfs {
+            log::debug!("Mount tmpfs to container's /{}", destination.display());
+            create_directory(&mnt_path)?;
+            mount_filesystem(
+                "tmpfs",
+                &mnt_path,
+                tmpfs.options.flags(),
+                &tmpfs.data.join(","),
+            )?;
+        }
     }
 
-    if let Some(tool_dir) = tool_dir {
+    if let Some(tool_dir) = &config.tool_dir {
         // mount tool volume
-        let tool_mnt_point = new_root.join("curn");
+        let tool_mnt_point = mount_point(&new_root, Path::new("curn"))?;
         create_directory(&tool_mnt_point)?;
         mount_directory(
-            Some(&tool_dir),
+            Some(tool_dir),
             &tool_mnt_point,
             None,
             vec![MsFlags::MS_BIND, MsFlags::MS_PRIVATE],
         )?;
     }
 
+    // mount proc and sys filesystems of container, before the host's ones are unmounted with
+    // the old root as a user namespace may only mount them while other ones are visible
+    let proc_mnt_point = mount_point(&new_root, Path::new("proc"))?;
+    create_directory(&proc_mnt_point)?;
+    mount_filesystem(
+        "proc",
+        &proc_mnt_point,
+        MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC,
+        "",
+    )?;
+    mount_sys(&new_root)?;
+
     // pivot and change working path to the new root
     log::debug!("Pivoting root");
 
-    let old_root_tail = format!("oldroot.{}", container_id);
+    let old_root_tail = format!("oldroot.{}", config.container_id);
     let put_old = new_root.join(&old_root_tail);
     create_directory(&put_old)?;
 
-    if let Err(_) = pivot_root(&new_root, &put_old) {
+    if pivot_root(&new_root, &put_old).is_err() {
         return Err(ErrorCode::MountError(4));
     }
 
-    if let Err(_) = chdir(&PathBuf::from("/")) {
+    if chdir(&PathBuf::from("/")).is_err() {
         return Err(ErrorCode::MountError(5));
     }
 
@@ -98,26 +231,304 @@ pub fn set_mounts(
     unmount_directory(&old_root)?;
     delete_directory(&old_root)?;
 
-    // mount proc filesystem of container
+    // only the root mount itself, the mounts on top of it keep their flags
+    if config.readonly_root {
+        log::debug!("Remounting root read-only");
+        remount_readonly(Path::new("/"))?;
+    }
+
+    // symbolic links are now resolved inside the container
+    protect_paths(&config.protected_paths)
+}
+
+/// Return where `destination` is mounted under the new root, refusing any symbolic
+/// link on the way as it would be resolved against the host before pivoting
+fn mount_point(new_root: &Path, destination: &Path) -> Result<PathBuf, ErrorCode> {
+    let mut path = new_root.to_path_buf();
+    for component in destination.components() {
+        path.push(component);
+        if path.is_symlink() {
+            log::error!(
+                "Cannot mount to container's /{}, {:?} is a symbolic link",
+                destination.display(),
+                path
+            );
+            return Err(ErrorCode::MountError(0));
+        }
+    }
+    Ok(path)
+}
+
+fn mount_volume(volume: &Volume, mnt_path: &PathBuf) -> Result<(), ErrorCode> {
+    log::debug!(
+        "Mount host's {} to container's /{}",
+        volume.source.display(),
+        volume.destination.display()
+    );
+
+    if volume.source.is_dir() {
+        create_directory(mnt_path)?;
+    } else {
+        create_file(mnt_path)?;
+    }
     mount_directory(
-        Some(&PathBuf::from("proc")),
-        &PathBuf::from("/proc"),
-        Some(&PathBuf::from("proc")),
-        vec![],
+        Some(&volume.source),
+        mnt_path,
+        None,
+        vec![MsFlags::MS_BIND, MsFlags::MS_REC],
     )?;
+    if let Err(e) = mount(
+        None::<&str>,
+        mnt_path,
+        None::<&str>,
+        volume.propagation.flags(),
+        None::<&str>,
+    ) {
+        log::error!("Cannot set propagation of {:?}: {}", mnt_path, e);
+        return Err(ErrorCode::MountError(0));
+    }
+
+    // flags given with MS_BIND are ignored, they only apply on remount
+    let flags = volume.options.flags();
+    match flags.is_empty() {
+        true => Ok(()),
+        false => remount_bind(mnt_path, flags),
+    }
+}
+
+/// Mount a read-only sysfs, or bind the one of the host if the network
+/// namespace of the container is not owned by its user namespace
+fn mount_sys(new_root: &Path) -> Result<(), ErrorCode> {
+    let sys = mount_point(new_root, Path::new("sys"))?;
+    create_directory(&sys)?;
+    let flags = MsFlags::MS_RDONLY | MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    if mount(Some("sysfs"), &sys, Some("sysfs"), flags, None::<&str>).is_ok() {
+        return Ok(());
+    }
 
+    log::debug!("Cannot mount sysfs, binding the one of the host");
+    mount_directory(
+        Some(&PathBuf::from("/sys")),
+        &sys,
+        None,
+        vec![MsFlags::MS_BIND, MsFlags::MS_REC],
+    )?;
+    remount_readonly(&sys)
+}
+
+fn protect_paths(paths: &ProtectedPaths) -> Result<(), ErrorCode> {
+    log::debug!("Masking and protecting paths");
+
+    for path in paths.readonly.iter().filter(|p| exists(p)) {
+        mount_directory(
+            Some(path),
+            path,
+            None,
+            vec![MsFlags::MS_BIND, MsFlags::MS_REC],
+        )?;
+        remount_readonly(path)?;
+    }
+    for path in paths.masked.iter().filter(|p| exists(p)) {
+        match path.is_dir() {
+            true => mount_filesystem("tmpfs", path, MsFlags::MS_RDONLY, "size=0")?,
+            false => mount_directory(
+                Some(&PathBuf::from("/dev/null")),
+                path,
+                None,
+                vec![MsFlags::MS_BIND],
+            )?,
+        }
+    }
     Ok(())
 }
 
-pub fn clean_mounts(path: &String) -> Result<(), ErrorCode> {
+/// Paths missing from the kernel or the root directory are not protected
+fn exists(path: &Path) -> bool {
+    let exists = path.symlink_metadata().is_ok();
+    if !exists {
+        log::debug!("Skip protecting missing path {:?}", path);
+    }
+    exists
+}
+
+fn remount_readonly(path: &Path) -> Result<(), ErrorCode> {
+    remount_bind(path, MsFlags::MS_RDONLY)
+}
+
+/// Add flags to a bind mount, the ones locked by a user namespace are kept
+fn remount_bind(path: &Path, flags: MsFlags) -> Result<(), ErrorCode> {
+    let mut flags = flags | MsFlags::MS_BIND | MsFlags::MS_REMOUNT;
+    if let Ok(stat) = statvfs(path) {
+        for (fs_flag, ms_flag) in [
+            (FsFlags::ST_NOSUID, MsFlags::MS_NOSUID),
+            (FsFlags::ST_NODEV, MsFlags::MS_NODEV),
+            (FsFlags::ST_NOEXEC, MsFlags::MS_NOEXEC),
+            (FsFlags::ST_NOATIME, MsFlags::MS_NOATIME),
+            (FsFlags::ST_NODIRATIME, MsFlags::MS_NODIRATIME),
+            (FsFlags::ST_RELATIME, MsFlags::MS_RELATIME),
+        ] {
+            if stat.flags().contains(fs_flag) {
+                flags.insert(ms_flag);
+            }
+        }
+    }
+
+    match mount(None::<&str>, path, None::<&str>, flags, None::<&str>) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("Cannot remount {:?} with {:?}: {}", path, flags, e);
+            Err(ErrorCode::MountError(0))
+        }
+    }
+}
+
+/// Replace /dev of the root directory by a tmpfs holding only the standard devices,
+/// a devpts instance of its own, /dev/shm and /dev/mqueue
+fn mount_dev(new_root: &Path) -> Result<(), ErrorCode> {
+    log::debug!("Mounting /dev");
+
+    let dev = mount_point(new_root, Path::new("dev"))?;
+    create_directory(&dev)?;
+    mount_filesystem(
+        "tmpfs",
+        &dev,
+        MsFlags::MS_NOSUID | MsFlags::MS_STRICTATIME,
+        "mode=755,size=65536k",
+    )?;
+
+    for (name, major, minor) in DEVICES {
+        create_device(&dev.join(name), major, minor)?;
+    }
+    for (name, target) in DEV_SYMLINKS {
+        if let Err(e) = symlink(target, dev.join(name)) {
+            log::error!("Cannot create symbolic link /dev/{}: {}", name, e);
+            return Err(ErrorCode::MountError(7));
+        }
+    }
+
+    let pts = dev.join("pts");
+    create_directory(&pts)?;
+    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NOEXEC;
+    let options = "newinstance,ptmxmode=0666,mode=0620";
+    // group tty may not be mapped into a user namespace, ptys then belong to the user's group
+    let tty_group = format!("{},gid=5", options);
+    if mount(
+        Some("devpts"),
+        &pts,
+        Some("devpts"),
+        flags,
+        Some(tty_group.as_str()),
+    )
+    .is_err()
+    {
+        mount_filesystem("devpts", &pts, flags, options)?;
+    }
+
+    let flags = MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC;
+    let shm = dev.join("shm");
+    create_directory(&shm)?;
+    mount_filesystem("tmpfs", &shm, flags, "mode=1777,size=65536k")?;
+    let mqueue = dev.join("mqueue");
+    create_directory(&mqueue)?;
+    mount_filesystem("mqueue", &mqueue, flags, "")
+}
+
+/// Create a character device, or bind the one of the host as a user namespace cannot
+fn create_device(path: &Path, major: u64, minor: u64) -> Result<(), ErrorCode> {
+    let mode = Mode::from_bits_truncate(0o666);
+    match mknod(path, SFlag::S_IFCHR, mode, makedev(major, minor)) {
+        // the mode of a new node is masked by umask
+        Ok(_) => set_permissions(path, Permissions::from_mode(0o666)).map_err(|e| {
+            log::error!("Cannot set permissions of {:?}: {}", path, e);
+            ErrorCode::MountError(7)
+        }),
+        Err(Errno::EPERM) => {
+            let host_path = Path::new("/dev").join(path.file_name().expect("Device has a name"));
+            create_file(&path.to_path_buf())?;
+            mount_directory(
+                Some(&host_path),
+                &path.to_path_buf(),
+                None,
+                vec![MsFlags::MS_BIND],
+            )
+        }
+        Err(e) => {
+            log::error!("Cannot create device {:?}: {}", path, e);
+            Err(ErrorCode::MountError(7))
+        }
+    }
+}
+
+pub fn clean_mounts(path: &String, overlay: Option<&Overlay>) -> Result<(), ErrorCode> {
     log::debug!("Cleaning mount points: {}", path);
 
     let root_mnt_point = PathBuf::from(&path);
     delete_directory(&root_mnt_point)?;
 
+    if let Some(overlay) = overlay {
+        let overlay_dir = overlay_path(path);
+        if overlay.keep_upper {
+            delete_directory_all(&overlay_dir.join("work"))?;
+            log::info!("Upper layer kept at {:?}", overlay_dir.join("upper"));
+        } else {
+            delete_directory_all(&overlay_dir)?;
+        }
+    }
+
+    // the directory of the container is left while it keeps an upper layer
+    if let Some(container_dir) = root_mnt_point.parent() {
+        let _ = remove_dir(container_dir);
+    }
     Ok(())
 }
 
+fn mount_overlay(
+    mount_dir: &PathBuf,
+    overlay: &Overlay,
+    mount_point: &PathBuf,
+) -> Result<(), ErrorCode> {
+    let overlay_dir = overlay_path(mount_point.to_str().expect("Mount point must be valid"));
+    let upper_dir = overlay_dir.join("upper");
+    let work_dir = overlay_dir.join("work");
+    create_directory(&upper_dir)?;
+    create_directory(&work_dir)?;
+
+    // overlayfs lists lower directories from top to bottom
+    let lower_dirs = overlay
+        .layers
+        .iter()
+        .rev()
+        .chain(std::iter::once(mount_dir))
+        .map(|p| p.to_str().expect("Layer path must be valid"))
+        .collect::<Vec<_>>()
+        .join(":");
+    let options = format!(
+        "lowerdir={},upperdir={},workdir={}",
+        lower_dirs,
+        upper_dir.to_str().expect("Upper directory must be valid"),
+        work_dir.to_str().expect("Work directory must be valid")
+    );
+    log::debug!("Mounting overlay with {}", options);
+
+    match mount(
+        Some("overlay"),
+        mount_point,
+        Some("overlay"),
+        MsFlags::empty(),
+        Some(options.as_str()),
+    ) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!(
+                "Cannot mount overlay to `{}`: {}",
+                mount_point.to_str().expect("Mount point must be valid"),
+                e
+            );
+            Err(ErrorCode::MountError(0))
+        }
+    }
+}
+
 fn mount_directory(
     path: Option<&PathBuf>,
     mount_point: &PathBuf,
@@ -151,6 +562,22 @@ fn mount_directory(
     }
 }
 
+fn mount_filesystem(
+    fstype: &str,
+    mount_point: &Path,
+    flags: MsFlags,
+    options: &str,
+) -> Result<(), ErrorCode> {
+    let data = Some(options).filter(|o| !o.is_empty());
+    match mount(Some(fstype), mount_point, Some(fstype), flags, data) {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!("Cannot mount {} to {:?}: {}", fstype, mount_point, e);
+            Err(ErrorCode::MountError(0))
+        }
+    }
+}
+
 fn unmount_directory(path: &PathBuf) -> Result<(), ErrorCode> {
     match umount2(path, MntFlags::MNT_DETACH) {
         Ok(_) => Ok(()),
@@ -179,10 +606,33 @@ fn create_directory(path: &PathBuf) -> Result<(), ErrorCode> {
     }
 }
 
+/// Create an empty file as mount point of a bind mounted file, e.g. /etc/hosts
+fn create_file(path: &PathBuf) -> Result<(), ErrorCode> {
+    let res = match path.parent() {
+        Some(parent) => create_dir_all(parent),
+        None => Ok(()),
+    }
+    .and_then(|_| OpenOptions::new().create(true).append(true).open(path));
+
+    match res {
+        Ok(_) => Ok(()),
+        Err(e) => {
+            log::error!(
+                "Cannot create file `{}`: {}",
+                path.to_str().expect("Path to be created must be valid"),
+                e
+            );
+            Err(ErrorCode::MountError(2))
+        }
+    }
+}
+
 /// Remove an empty directory since must protect old root while it not be unmounted
 fn delete_directory(path: &PathBuf) -> Result<(), ErrorCode> {
     match remove_dir(path) {
         Ok(_) => Ok(()),
+        // nothing to clean if the container failed before creating it
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
         Err(e) => {
             log::error!(
                 "Unable to delete empty directory `{}`: {}",
@@ -193,3 +643,42 @@ fn delete_directory(path: &PathBuf) -> Result<(), ErrorCode> {
         }
     }
 }
+
+/// Remove a directory with all its content, used for the discarded overlay layers
+fn delete_directory_all(path: &PathBuf) -> Result<(), ErrorCode> {
+    match remove_dir_all(path) {
+        Ok(_) => Ok(()),
+        // nothing to clean if the container failed before creating it
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+        Err(e) => {
+            log::error!(
+                "Unable to delete directory `{}`: {}",
+                path.to_str().expect("Path to be deleted must be valid"),
+                e
+            );
+            Err(ErrorCode::MountError(6))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+    use std::fs;
+
+    #[test]
+    fn mount_points_never_follow_symlinks() {
+        let root = TempDir::new("mount");
+        fs::create_dir_all(root.join("srv")).unwrap();
+        symlink("/etc", root.join("data")).unwrap();
+
+        let allowed = mount_point(root.path(), Path::new("srv/sub"));
+        let ancestor = mount_point(root.path(), Path::new("data/sub"));
+        let last = mount_point(root.path(), Path::new("data"));
+
+        assert_eq!(allowed.unwrap(), root.join("srv/sub"));
+        assert!(ancestor.is_err());
+        assert!(last.is_err());
+    }
+}
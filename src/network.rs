@@ -0,0 +1,525 @@
+use crate::{
+    config::ContainerOpts,
+    container::random_string,
+    error::ErrorCode,
+    ipc::{recv_bool, send_bool},
+    state::{lease_dir, state_dir},
+    volume::Volume,
+};
+use futures_util::stream::TryStreamExt;
+use nix::{errno::Errno, unistd::Pid};
+use rtnetlink::{
+    new_connection, Error, Handle, LinkBridge, LinkUnspec, LinkVeth, RouteMessageBuilder,
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, create_dir_all, OpenOptions},
+    future::Future,
+    io::Write,
+    net::{IpAddr, Ipv4Addr},
+    os::fd::RawFd,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+pub const BRIDGE_NAME: &str = "curn0";
+pub const DEFAULT_SUBNET: &str = "10.88.0.0/16";
+const CONTAINER_IFNAME: &str = "eth0";
+const HOST_RESOLV_CONF: [&str; 2] = ["/run/systemd/resolve/resolv.conf", "/etc/resolv.conf"];
+const FALLBACK_NAMESERVERS: [&str; 2] = ["8.8.8.8", "1.1.1.1"];
+
+/// `bridge` connects the container to `curn0`, `none` only brings up loopback
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NetworkMode {
+    Bridge,
+    None,
+}
+
+impl FromStr for NetworkMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bridge" => Ok(Self::Bridge),
+            "none" => Ok(Self::None),
+            _ => Err(format!("unknown network mode `{}`", s)),
+        }
+    }
+}
+
+/// IPv4 subnet in CIDR notation, e.g. `10.88.0.0/16`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Subnet {
+    pub addr: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Subnet {
+    fn mask(&self) -> u32 {
+        u32::MAX << (32 - self.prefix)
+    }
+
+    /// First address of the subnet is given to the bridge
+    pub fn gateway(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) + 1)
+    }
+
+    /// Addresses for containers, without network, gateway and broadcast addresses
+    fn hosts(&self) -> impl Iterator<Item = Ipv4Addr> {
+        let network = u32::from(self.addr);
+        let broadcast = network | !self.mask();
+        (network + 2..broadcast).map(Ipv4Addr::from)
+    }
+}
+
+impl FromStr for Subnet {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s.split_once('/').ok_or("must be ADDRESS/PREFIX")?;
+        let subnet = Self {
+            addr: addr
+                .parse()
+                .map_err(|_| format!("invalid address `{}`", addr))?,
+            prefix: prefix
+                .parse()
+                .map_err(|_| format!("invalid prefix `{}`", prefix))?,
+        };
+        // at least a gateway and a container address
+        if !(8..=30).contains(&subnet.prefix) {
+            return Err(format!("prefix must be between 8 and 30, got {}", prefix));
+        }
+        if u32::from(subnet.addr) & !subnet.mask() != 0 {
+            return Err(format!("`{}` has host bits set", s));
+        }
+        Ok(subnet)
+    }
+}
+
+impl fmt::Display for Subnet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
+/// Bridged network of a container, the peer is renamed to `eth0` inside the container
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Network {
+    pub subnet: Subnet,
+    pub address: Ipv4Addr,
+    pub veth_host: String,
+    pub veth_peer: String,
+}
+
+impl Network {
+    /// Lease a free address of the subnet, leases are files named after the address
+    pub fn allocate(subnet: Subnet, container_id: &str, suffix: &str) -> Result<Self, ErrorCode> {
+        let dir = lease_dir();
+        if let Err(e) = create_dir_all(&dir) {
+            log::error!("Cannot create lease directory {:?}: {}", dir, e);
+            return Err(ErrorCode::NetworkError(2));
+        }
+
+        for address in subnet.hosts() {
+            // creating the file fails if another container holds the address
+            let lease = OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(dir.join(address.to_string()));
+            if let Ok(mut lease) = lease {
+                let _ = lease.write_all(container_id.as_bytes());
+                log::debug!("Allocated address {} of subnet {}", address, subnet);
+                return Ok(Self {
+                    subnet,
+                    address,
+                    veth_host: format!("veth{}", suffix),
+                    veth_peer: format!("vpeer{}", suffix),
+                });
+            }
+        }
+
+        log::error!("No address left in subnet {}", subnet);
+        Err(ErrorCode::NetworkError(2))
+    }
+
+    fn release(&self) -> Result<(), ErrorCode> {
+        let lease = lease_dir().join(self.address.to_string());
+        match fs::remove_file(&lease) {
+            Ok(_) => Ok(()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(()),
+            Err(e) => {
+                log::error!("Cannot remove lease {:?}: {}", lease, e);
+                Err(ErrorCode::NetworkError(4))
+            }
+        }
+    }
+}
+
+/// Run netlink requests on a single-threaded runtime, the child process must not spawn threads
+fn run_netlink<F, Fut>(f: F) -> Result<(), Error>
+where
+    F: FnOnce(Handle) -> Fut,
+    Fut: Future<Output = Result<(), Error>>,
+{
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .map_err(|_| Error::RequestFailed)?;
+
+    runtime.block_on(async {
+        let (connection, handle, _) = new_connection().map_err(|_| Error::RequestFailed)?;
+        tokio::spawn(connection);
+        f(handle).await
+    })
+}
+
+fn is_errno(e: &Error, errno: Errno) -> bool {
+    matches!(e, Error::NetlinkError(msg) if msg.raw_code() == -(errno as i32))
+}
+
+async fn link_index(handle: &Handle, name: &str) -> Result<Option<u32>, Error> {
+    match handle
+        .link()
+        .get()
+        .match_name(name)
+        .execute()
+        .try_next()
+        .await
+    {
+        Ok(link) => Ok(link.map(|l| l.header.index)),
+        Err(e) if is_errno(&e, Errno::ENODEV) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+async fn set_link_up(handle: &Handle, index: u32) -> Result<(), Error> {
+    handle
+        .link()
+        .set(LinkUnspec::new_with_index(index).up().build())
+        .execute()
+        .await
+}
+
+/// Create `curn0` with the gateway address if it does not exist yet
+async fn setup_bridge(handle: &Handle, subnet: Subnet) -> Result<u32, Error> {
+    let index = match link_index(handle, BRIDGE_NAME).await? {
+        Some(index) => index,
+        None => {
+            log::debug!("Creating bridge {}", BRIDGE_NAME);
+            match handle
+                .link()
+                .add(LinkBridge::new(BRIDGE_NAME).build())
+                .execute()
+                .await
+            {
+                // another container may have created it meanwhile
+                Err(e) if !is_errno(&e, Errno::EEXIST) => return Err(e),
+                _ => {}
+            }
+            link_index(handle, BRIDGE_NAME)
+                .await?
+                .ok_or(Error::RequestFailed)?
+        }
+    };
+
+    match handle
+        .address()
+        .add(index, IpAddr::V4(subnet.gateway()), subnet.prefix)
+        .execute()
+        .await
+    {
+        Err(e) if !is_errno(&e, Errno::EEXIST) => return Err(e),
+        _ => {}
+    }
+    set_link_up(handle, index).await?;
+    Ok(index)
+}
+
+/// Connect the container to the bridge, then let the child configure its side
+pub fn setup_host_network(pid: Pid, network: &Network, fd: RawFd) -> Result<(), ErrorCode> {
+    log::debug!(
+        "Setting up veth pair {} <-> {} for pid {}",
+        network.veth_host,
+        network.veth_peer,
+        pid
+    );
+
+    let res = run_netlink(|handle| async move {
+        let bridge = setup_bridge(&handle, network.subnet).await?;
+        handle
+            .link()
+            .add(LinkVeth::new(&network.veth_host, &network.veth_peer).build())
+            .execute()
+            .await?;
+
+        let host = link_index(&handle, &network.veth_host)
+            .await?
+            .ok_or(Error::RequestFailed)?;
+        handle
+            .link()
+            .set(
+                LinkUnspec::new_with_index(host)
+                    .controller(bridge)
+                    .up()
+                    .build(),
+            )
+            .execute()
+            .await?;
+
+        let peer = link_index(&handle, &network.veth_peer)
+            .await?
+            .ok_or(Error::RequestFailed)?;
+        handle
+            .link()
+            .set(
+                LinkUnspec::new_with_index(peer)
+                    .setns_by_pid(pid.as_raw() as u32)
+                    .build(),
+            )
+            .execute()
+            .await
+    });
+
+    if let Err(e) = res {
+        log::error!("Cannot set up network of the container: {}", e);
+        // the child is waiting for us, let it exit
+        send_bool(fd, false)?;
+        return Err(ErrorCode::NetworkError(0));
+    }
+    send_bool(fd, true)
+}
+
+/// Bring up loopback, and configure `eth0` once the parent moved the veth peer in
+pub fn set_container_network(fd: RawFd, network: Option<&Network>) -> Result<(), ErrorCode> {
+    log::debug!("Setting up network of the container");
+
+    let network = match network {
+        Some(network) => {
+            if !recv_bool(fd)? {
+                log::error!("Network was not set up by the parent process");
+                return Err(ErrorCode::NetworkError(1));
+            }
+            Some(network)
+        }
+        None => None,
+    };
+
+    let res = run_netlink(|handle| async move {
+        let lo = link_index(&handle, "lo")
+            .await?
+            .ok_or(Error::RequestFailed)?;
+        set_link_up(&handle, lo).await?;
+
+        let network = match network {
+            Some(network) => network,
+            None => return Ok(()),
+        };
+        let index = link_index(&handle, &network.veth_peer)
+            .await?
+            .ok_or(Error::RequestFailed)?;
+        handle
+            .link()
+            .set(
+                LinkUnspec::new_with_index(index)
+                    .name(CONTAINER_IFNAME)
+                    .build(),
+            )
+            .execute()
+            .await?;
+        handle
+            .address()
+            .add(index, IpAddr::V4(network.address), network.subnet.prefix)
+            .execute()
+            .await?;
+        set_link_up(&handle, index).await?;
+
+        let route = RouteMessageBuilder::<Ipv4Addr>::new()
+            .gateway(network.subnet.gateway())
+            .build();
+        handle.route().add(route).execute().await
+    });
+
+    if let Err(e) = res {
+        log::error!("Cannot configure network inside the container: {}", e);
+        return Err(ErrorCode::NetworkError(1));
+    }
+    Ok(())
+}
+
+/// Delete the veth pair and release the address, the bridge is shared and kept
+pub fn clean_network(network: &Network) -> Result<(), ErrorCode> {
+    log::debug!("Cleaning network {}", network.veth_host);
+
+    // the pair is gone already if the network namespace was destroyed
+    let res = run_netlink(|handle| async move {
+        if let Some(index) = link_index(&handle, &network.veth_host).await? {
+            match handle.link().del(index).execute().await {
+                Err(e) if !is_errno(&e, Errno::ENODEV) => return Err(e),
+                _ => {}
+            }
+        }
+        Ok(())
+    });
+    // the address is released even if the veth is left behind
+    let released = network.release();
+    if let Err(e) = res {
+        log::error!("Cannot delete veth {}: {}", network.veth_host, e);
+        return Err(ErrorCode::NetworkError(4));
+    }
+    released
+}
+
+/// Allocate the network of a new container and write its `/etc` files into its state directory
+pub fn prepare_network(
+    config: ContainerOpts,
+    mode: Option<NetworkMode>,
+    subnet: Subnet,
+) -> Result<ContainerOpts, ErrorCode> {
+    // the bridge and veth pairs can only be created by root
+    let mode = match (mode, config.rootless) {
+        (Some(NetworkMode::Bridge), true) => {
+            return Err(ErrorCode::RootlessUnsupported("bridge network"))
+        }
+        (Some(mode), _) => mode,
+        (None, false) => NetworkMode::Bridge,
+        (None, true) => NetworkMode::None,
+    };
+    let network = match mode {
+        NetworkMode::Bridge => Some(Network::allocate(
+            subnet,
+            &config.container_id,
+            &random_string(8),
+        )?),
+        NetworkMode::None => None,
+    };
+
+    let dir = state_dir(&config.container_id);
+    match generate_etc_files(&dir, &config.hostname, network.as_ref()) {
+        Ok(etc_files) => Ok(config.with_network(network, etc_files)),
+        Err(e) => {
+            if let Some(network) = &network {
+                let _ = network.release();
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Write `/etc/hostname`, `/etc/hosts` and `/etc/resolv.conf` of the container into `dir`,
+/// return them as volumes to be bind mounted
+fn generate_etc_files(
+    dir: &Path,
+    hostname: &str,
+    network: Option<&Network>,
+) -> Result<Vec<Volume>, ErrorCode> {
+    let address = match network {
+        Some(network) => network.address.to_string(),
+        None => "127.0.1.1".to_string(),
+    };
+    let hosts = format!(
+        "127.0.0.1\tlocalhost\n::1\tlocalhost ip6-localhost ip6-loopback\n{}\t{}\n",
+        address, hostname
+    );
+    let files = [
+        ("hostname", format!("{}\n", hostname)),
+        ("hosts", hosts),
+        ("resolv.conf", generate_resolv_conf()),
+    ];
+
+    let mut binds = Vec::new();
+    for (name, content) in files {
+        let path = dir.join(name);
+        if let Err(e) = fs::write(&path, content) {
+            log::error!("Cannot write {:?}: {}", path, e);
+            return Err(ErrorCode::NetworkError(3));
+        }
+        binds.push(Volume::new(path, PathBuf::from("etc").join(name)));
+    }
+    Ok(binds)
+}
+
+/// Copy nameservers of the host, loopback ones are unreachable from the container
+fn generate_resolv_conf() -> String {
+    let host = HOST_RESOLV_CONF
+        .iter()
+        .find_map(|p| fs::read_to_string(p).ok())
+        .unwrap_or_default();
+
+    let mut content = String::new();
+    let mut has_nameserver = false;
+    for line in host.lines() {
+        let mut fields = line.split_ascii_whitespace();
+        match (fields.next(), fields.next()) {
+            (Some("nameserver"), Some(server)) => {
+                let loopback = server.parse::<IpAddr>().is_ok_and(|ip| ip.is_loopback());
+                if !loopback {
+                    content.push_str(&format!("nameserver {}\n", server));
+                    has_nameserver = true;
+                }
+            }
+            (Some("search" | "options"), Some(_)) => {
+                content.push_str(line.trim());
+                content.push('\n');
+            }
+            _ => {}
+        }
+    }
+
+    if !has_nameserver {
+        for server in FALLBACK_NAMESERVERS {
+            content.push_str(&format!("nameserver {}\n", server));
+        }
+    }
+    content
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smallest_subnet_has_one_container() {
+        let subnet = "192.168.7.4/30".parse::<Subnet>().unwrap();
+        assert_eq!(subnet.gateway(), Ipv4Addr::new(192, 168, 7, 5));
+        assert_eq!(
+            subnet.hosts().collect::<Vec<_>>(),
+            [Ipv4Addr::new(192, 168, 7, 6)]
+        );
+        assert_eq!(subnet.to_string(), "192.168.7.4/30");
+    }
+
+    #[test]
+    fn point_to_point_prefixes_are_refused() {
+        // no room for both a gateway and a container
+        assert!("192.168.7.4/31".parse::<Subnet>().is_err());
+        assert!("192.168.7.4/32".parse::<Subnet>().is_err());
+        assert!("10.0.0.0/33".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn short_prefixes_are_refused() {
+        assert_eq!(
+            "10.0.0.0/8".parse::<Subnet>().unwrap().hosts().count(),
+            (1 << 24) - 3
+        );
+        assert!("10.0.0.0/7".parse::<Subnet>().is_err());
+        // a shift by 32 bits would overflow the mask
+        assert!("0.0.0.0/0".parse::<Subnet>().is_err());
+    }
+
+    #[test]
+    fn host_bits_are_refused() {
+        assert!("10.88.0.1/16".parse::<Subnet>().is_err());
+        assert!("10.88.1.0/16".parse::<Subnet>().is_err());
+        assert!("10.88.1.0/24".parse::<Subnet>().is_ok());
+    }
+
+    #[test]
+    fn malformed_cidr() {
+        assert!("10.88.0.0".parse::<Subnet>().is_err());
+        assert!("10.88.0/16".parse::<Subnet>().is_err());
+        assert!("10.88.0.0/-1".parse::<Subnet>().is_err());
+        assert!("10.88.0.0/16/24".parse::<Subnet>().is_err());
+    }
+}
@@ -0,0 +1,843 @@
+use crate::{
+    capabilities::Capabilities,
+    cgroup::{IoMax, ResourceLimits, Rlimit},
+    error::ErrorCode,
+    mount::ProtectedPaths,
+    seccomp::SeccompProfile,
+    user_namespace::{IdMap, IdMappings},
+    volume::{container_path, Tmpfs, Volume},
+};
+use capctl::{Cap, CapSet};
+use rlimit::Resource;
+use serde::Deserialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+const CONFIG_FILE: &str = "config.json";
+
+// Namespaces curn always creates for a container
+const NAMESPACES_SUPPORTED: [&str; 7] = ["mount", "cgroup", "pid", "ipc", "network", "uts", "user"];
+
+// Mounts curn always sets up itself, as (type, destination)
+const MOUNTS_MANAGED: [(&str, &str); 6] = [
+    ("proc", "/proc"),
+    ("sysfs", "/sys"),
+    ("tmpfs", "/dev"),
+    ("devpts", "/dev/pts"),
+    ("tmpfs", "/dev/shm"),
+    ("mqueue", "/dev/mqueue"),
+];
+
+/// Subset of the OCI runtime spec, see https://github.com/opencontainers/runtime-spec
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Spec {
+    pub oci_version: String,
+    pub process: Option<Process>,
+    pub root: Option<Root>,
+    #[serde(default)]
+    pub mounts: Vec<Mount>,
+    pub hostname: Option<String>,
+    pub linux: Option<Linux>,
+    pub hooks: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Process {
+    #[serde(default)]
+    pub terminal: bool,
+    pub user: User,
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub env: Vec<String>,
+    pub cwd: PathBuf,
+    pub capabilities: Option<LinuxCapabilities>,
+    #[serde(default)]
+    pub rlimits: Vec<PosixRlimit>,
+    #[serde(default)]
+    pub no_new_privileges: bool,
+    pub apparmor_profile: Option<String>,
+    pub selinux_label: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LinuxCapabilities {
+    #[serde(default)]
+    pub bounding: Vec<String>,
+    #[serde(default)]
+    pub effective: Vec<String>,
+    #[serde(default)]
+    pub permitted: Vec<String>,
+    #[serde(default)]
+    pub inheritable: Vec<String>,
+    #[serde(default)]
+    pub ambient: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PosixRlimit {
+    #[serde(rename = "type")]
+    pub rltype: String,
+    pub hard: u64,
+    pub soft: u64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct User {
+    pub uid: u32,
+    pub gid: u32,
+    pub umask: Option<u32>,
+    #[serde(default)]
+    pub additional_gids: Vec<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Root {
+    pub path: PathBuf,
+    #[serde(default)]
+    pub readonly: bool,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Mount {
+    pub destination: PathBuf,
+    #[serde(rename = "type")]
+    pub fstype: Option<String>,
+    pub source: Option<PathBuf>,
+    #[serde(default)]
+    pub options: Vec<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Linux {
+    #[serde(default)]
+    pub namespaces: Vec<Namespace>,
+    #[serde(default)]
+    pub uid_mappings: Vec<IdMap>,
+    #[serde(default)]
+    pub gid_mappings: Vec<IdMap>,
+    pub resources: Option<Resources>,
+    pub seccomp: Option<SeccompProfile>,
+    #[serde(default)]
+    pub devices: Vec<serde_json::Value>,
+    pub masked_paths: Option<Vec<PathBuf>>,
+    pub readonly_paths: Option<Vec<PathBuf>>,
+    pub sysctl: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Namespace {
+    #[serde(rename = "type")]
+    pub nstype: String,
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Resources {
+    #[serde(default)]
+    pub devices: Vec<DeviceRule>,
+    pub memory: Option<Memory>,
+    pub cpu: Option<Cpu>,
+    pub pids: Option<Pids>,
+    #[serde(rename = "blockIO")]
+    pub block_io: Option<BlockIo>,
+    #[serde(default)]
+    pub hugepage_limits: Vec<serde_json::Value>,
+    pub network: Option<serde_json::Value>,
+    pub rdma: Option<serde_json::Value>,
+    pub unified: Option<serde_json::Value>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Memory {
+    pub limit: Option<i64>,
+    pub reservation: Option<i64>,
+    /// Limit of memory plus swap, unlike `memory.swap.max`
+    pub swap: Option<i64>,
+    pub kernel: Option<i64>,
+    pub kernel_tcp: Option<i64>,
+    pub swappiness: Option<u64>,
+    #[serde(rename = "disableOOMKiller")]
+    pub disable_oom_killer: Option<bool>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Cpu {
+    pub shares: Option<u64>,
+    pub quota: Option<i64>,
+    pub period: Option<u64>,
+    pub realtime_runtime: Option<i64>,
+    pub realtime_period: Option<u64>,
+    pub cpus: Option<String>,
+    pub mems: Option<String>,
+    pub idle: Option<i64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Pids {
+    pub limit: i64,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BlockIo {
+    pub weight: Option<u16>,
+    pub leaf_weight: Option<u16>,
+    #[serde(default)]
+    pub weight_device: Vec<serde_json::Value>,
+    #[serde(default)]
+    pub throttle_read_bps_device: Vec<ThrottleDevice>,
+    #[serde(default)]
+    pub throttle_write_bps_device: Vec<ThrottleDevice>,
+    #[serde(rename = "throttleReadIOPSDevice", default)]
+    pub throttle_read_iops_device: Vec<ThrottleDevice>,
+    #[serde(rename = "throttleWriteIOPSDevice", default)]
+    pub throttle_write_iops_device: Vec<ThrottleDevice>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ThrottleDevice {
+    pub major: u64,
+    pub minor: u64,
+    pub rate: u64,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeviceRule {
+    pub allow: bool,
+    #[serde(rename = "type")]
+    pub devtype: Option<String>,
+    pub major: Option<i64>,
+    pub minor: Option<i64>,
+}
+
+/// Parse `config.json` of a bundle and refuse fields that curn cannot honor
+pub fn load_spec(bundle: &Path) -> Result<Spec, ErrorCode> {
+    let path = bundle.join(CONFIG_FILE);
+    log::debug!("Loading OCI runtime spec from {:?}", path);
+
+    let content = fs::read_to_string(&path).map_err(|e| {
+        log::error!("Cannot read {:?}: {}", path, e);
+        ErrorCode::BundleError(0)
+    })?;
+    let spec: Spec = serde_json::from_str(&content).map_err(|e| {
+        log::error!("Cannot parse {:?}: {}", path, e);
+        ErrorCode::BundleError(1)
+    })?;
+
+    spec.check_supported()?;
+    Ok(spec)
+}
+
+impl Spec {
+    fn check_supported(&self) -> Result<(), ErrorCode> {
+        if !self.oci_version.starts_with("1.") {
+            log::error!("Unsupported OCI version {}", self.oci_version);
+            return Err(ErrorCode::BundleUnsupported("ociVersion"));
+        }
+        if self.hooks.is_some() {
+            return Err(ErrorCode::BundleUnsupported("hooks"));
+        }
+
+        let process = self.process.as_ref().ok_or_else(|| {
+            log::error!("Field `process` is required");
+            ErrorCode::BundleError(2)
+        })?;
+        process.check_supported()?;
+
+        if self.root.is_none() {
+            log::error!("Field `root` is required");
+            return Err(ErrorCode::BundleError(2));
+        }
+
+        for mount in self.mounts.iter() {
+            mount.check_supported()?;
+        }
+
+        if let Some(linux) = &self.linux {
+            linux.check_supported()?;
+        }
+
+        Ok(())
+    }
+
+    /// Mappings not given are allocated by curn when the container is created
+    pub fn id_mappings(&self) -> IdMappings {
+        match &self.linux {
+            Some(linux) => IdMappings::new(linux.uid_mappings.clone(), linux.gid_mappings.clone()),
+            None => IdMappings::default(),
+        }
+    }
+
+    /// Default capabilities of curn if `process.capabilities` is not given
+    pub fn capabilities(&self) -> Capabilities {
+        let Some(caps) = self.process.as_ref().and_then(|p| p.capabilities.as_ref()) else {
+            return Capabilities::default();
+        };
+        // names are checked by load_spec
+        let to_set = |names: &[String]| -> CapSet {
+            names.iter().filter_map(|n| Cap::from_str(n).ok()).collect()
+        };
+        Capabilities {
+            bounding: to_set(&caps.bounding),
+            effective: to_set(&caps.effective),
+            permitted: to_set(&caps.permitted),
+            inheritable: to_set(&caps.inheritable),
+            ambient: to_set(&caps.ambient),
+        }
+    }
+
+    /// Default paths of curn for each of `linux.maskedPaths` and `linux.readonlyPaths` not given
+    pub fn protected_paths(&self) -> ProtectedPaths {
+        let mut paths = ProtectedPaths::default();
+        if let Some(linux) = &self.linux {
+            if let Some(masked) = &linux.masked_paths {
+                paths.masked = masked.clone();
+            }
+            if let Some(readonly) = &linux.readonly_paths {
+                paths.readonly = readonly.clone();
+            }
+        }
+        paths
+    }
+
+    /// Built-in profile of curn if `linux.seccomp` is not given
+    pub fn seccomp_profile(&self) -> SeccompProfile {
+        self.linux
+            .as_ref()
+            .and_then(|linux| linux.seccomp.clone())
+            .unwrap_or_default()
+    }
+
+    /// Return default limits overridden by `linux.resources` and `process.rlimits`
+    pub fn resource_limits(&self) -> ResourceLimits {
+        let mut limits = ResourceLimits::default();
+
+        if let Some(process) = &self.process {
+            for rlimit in process.rlimits.iter() {
+                limits.set_rlimit(Rlimit {
+                    resource: rlimit.rltype.clone(),
+                    soft: rlimit.soft,
+                    hard: rlimit.hard,
+                });
+            }
+        }
+
+        let resources = match self.linux.as_ref().and_then(|l| l.resources.as_ref()) {
+            Some(resources) => resources,
+            None => return limits,
+        };
+        if let Some(memory) = &resources.memory {
+            if let Some(limit) = memory.limit {
+                limits.memory_max = limit;
+            }
+            limits.memory_swap = memory.swap.map(|swap| match swap {
+                -1 => -1,
+                swap if limits.memory_max == -1 => swap,
+                swap => (swap - limits.memory_max).max(0),
+            });
+        }
+        if let Some(cpu) = &resources.cpu {
+            // cpu.shares of cgroup v1 is between 2 and 262144, cpu.weight between 1 and 10000
+            if let Some(shares) = cpu.shares.filter(|s| *s != 0) {
+                let shares = shares.clamp(2, 262144);
+                limits.cpu_shares = 1 + (shares - 2) * 9999 / 262142;
+            }
+            limits.cpu_quota = cpu.quota.or(limits.cpu_quota);
+            limits.cpu_period = cpu.period.or(limits.cpu_period);
+            limits.cpuset_cpus = cpu.cpus.clone().or(limits.cpuset_cpus.take());
+            limits.cpuset_mems = cpu.mems.clone().or(limits.cpuset_mems.take());
+        }
+        if let Some(pids) = &resources.pids {
+            limits.pids_max = pids.limit;
+        }
+        if let Some(block_io) = &resources.block_io {
+            // weight of cgroup v1 is between 10 and 1000, io.weight between 1 and 10000
+            if let Some(weight) = block_io.weight.filter(|w| *w != 0) {
+                let weight = weight.clamp(10, 1000) as u32;
+                limits.io_weight = Some((1 + (weight - 10) * 9999 / 990) as u16);
+            }
+            for device in block_io.throttle_read_bps_device.iter() {
+                let mut io_max = IoMax::new(device.major, device.minor);
+                io_max.rbps = Some(device.rate);
+                limits.set_io_max(io_max);
+            }
+            for device in block_io.throttle_write_bps_device.iter() {
+                let mut io_max = IoMax::new(device.major, device.minor);
+                io_max.wbps = Some(device.rate);
+                limits.set_io_max(io_max);
+            }
+            for device in block_io.throttle_read_iops_device.iter() {
+                let mut io_max = IoMax::new(device.major, device.minor);
+                io_max.riops = Some(device.rate);
+                limits.set_io_max(io_max);
+            }
+            for device in block_io.throttle_write_iops_device.iter() {
+                let mut io_max = IoMax::new(device.major, device.minor);
+                io_max.wiops = Some(device.rate);
+                limits.set_io_max(io_max);
+            }
+        }
+
+        limits
+    }
+
+    /// Return the bind mounts, their sources are relative to the bundle
+    pub fn bind_mounts(&self, bundle: &Path) -> Result<Vec<Volume>, ErrorCode> {
+        let mut volumes = Vec::new();
+        for mount in self
+            .mounts
+            .iter()
+            .filter(|m| !m.is_managed() && m.is_bind())
+        {
+            let source = mount.source.as_ref().ok_or_else(|| {
+                log::error!("Bind mount {:?} has no source", mount.destination);
+                ErrorCode::BundleError(2)
+            })?;
+            let from_path = bundle.join(source).canonicalize().map_err(|e| {
+                log::error!("Cannot canonicalize bind mount source {:?}: {}", source, e);
+                ErrorCode::BundleError(2)
+            })?;
+            let mut volume = Volume::new(from_path, mount.container_path()?);
+            for option in mount.options.iter() {
+                volume.set_option(option);
+            }
+            volumes.push(volume);
+        }
+        Ok(volumes)
+    }
+
+    /// Return the tmpfs mounts besides the ones of `/dev`
+    pub fn tmpfs_mounts(&self) -> Result<Vec<Tmpfs>, ErrorCode> {
+        let mut mounts = Vec::new();
+        for mount in self
+            .mounts
+            .iter()
+            .filter(|m| !m.is_managed() && !m.is_bind())
+        {
+            let mut tmpfs = Tmpfs::new(mount.container_path()?);
+            for option in mount.options.iter() {
+                tmpfs.set_option(option);
+            }
+            mounts.push(tmpfs);
+        }
+        Ok(mounts)
+    }
+}
+
+impl Process {
+    fn check_supported(&self) -> Result<(), ErrorCode> {
+        if self.args.is_empty() {
+            log::error!("Field `process.args` must not be empty");
+            return Err(ErrorCode::BundleError(2));
+        }
+        if !self.cwd.is_absolute() {
+            log::error!("Field `process.cwd` must be an absolute path");
+            return Err(ErrorCode::BundleError(2));
+        }
+        if self.terminal {
+            return Err(ErrorCode::BundleUnsupported("process.terminal"));
+        }
+        if self.user.umask.is_some() {
+            return Err(ErrorCode::BundleUnsupported("process.user.umask"));
+        }
+        if let Some(caps) = &self.capabilities {
+            let names = caps
+                .bounding
+                .iter()
+                .chain(&caps.effective)
+                .chain(&caps.permitted)
+                .chain(&caps.inheritable)
+                .chain(&caps.ambient);
+            for name in names {
+                if Cap::from_str(name).is_err() {
+                    log::error!("Unknown capability {:?} in `process.capabilities`", name);
+                    return Err(ErrorCode::BundleError(2));
+                }
+            }
+        }
+        for rlimit in self.rlimits.iter() {
+            if Resource::from_str(&rlimit.rltype).is_err() || rlimit.soft > rlimit.hard {
+                log::error!("Invalid rlimit {:?}", rlimit);
+                return Err(ErrorCode::BundleError(2));
+            }
+        }
+        // loading the seccomp filter always sets no_new_privs
+        if !self.no_new_privileges {
+            return Err(ErrorCode::BundleUnsupported("process.noNewPrivileges"));
+        }
+        if self.apparmor_profile.is_some() {
+            return Err(ErrorCode::BundleUnsupported("process.apparmorProfile"));
+        }
+        if self.selinux_label.is_some() {
+            return Err(ErrorCode::BundleUnsupported("process.selinuxLabel"));
+        }
+        Ok(())
+    }
+}
+
+impl Mount {
+    fn is_bind(&self) -> bool {
+        self.fstype.as_deref() == Some("bind")
+            || self.options.iter().any(|o| o == "bind" || o == "rbind")
+    }
+
+    fn is_managed(&self) -> bool {
+        MOUNTS_MANAGED.iter().any(|(fstype, destination)| {
+            self.fstype.as_deref() == Some(fstype) && self.destination == Path::new(destination)
+        })
+    }
+
+    fn container_path(&self) -> Result<PathBuf, ErrorCode> {
+        container_path(&self.destination).ok_or_else(|| {
+            log::error!("Mount destination {:?} must be absolute", self.destination);
+            ErrorCode::BundleError(2)
+        })
+    }
+
+    fn check_supported(&self) -> Result<(), ErrorCode> {
+        // options of these are the defaults of curn
+        if self.is_managed() {
+            return Ok(());
+        }
+        let mut volume = Volume::new(PathBuf::new(), PathBuf::new());
+        let mut tmpfs = Tmpfs::new(PathBuf::new());
+        let unsupported = match (self.is_bind(), self.fstype.as_deref()) {
+            (true, _) => self
+                .options
+                .iter()
+                .find(|o| *o != "bind" && *o != "rbind" && !volume.set_option(o)),
+            (false, Some("tmpfs")) => self.options.iter().find(|o| !tmpfs.set_option(o)),
+            (false, _) => {
+                log::error!(
+                    "Mount of type {:?} on {:?} is not supported",
+                    self.fstype,
+                    self.destination
+                );
+                return Err(ErrorCode::BundleUnsupported("mounts.type"));
+            }
+        };
+        if let Some(option) = unsupported {
+            log::error!(
+                "Mount option `{}` on {:?} is not supported",
+                option,
+                self.destination
+            );
+            return Err(ErrorCode::BundleUnsupported("mounts.options"));
+        }
+        Ok(())
+    }
+}
+
+impl Linux {
+    fn check_supported(&self) -> Result<(), ErrorCode> {
+        for ns in self.namespaces.iter() {
+            if !NAMESPACES_SUPPORTED.contains(&ns.nstype.as_str()) {
+                log::error!("Namespace `{}` is not supported", ns.nstype);
+                return Err(ErrorCode::BundleUnsupported("linux.namespaces.type"));
+            }
+            if ns.path.is_some() {
+                return Err(ErrorCode::BundleUnsupported("linux.namespaces.path"));
+            }
+        }
+        for nstype in NAMESPACES_SUPPORTED {
+            if !self.namespaces.iter().any(|ns| ns.nstype == nstype) {
+                log::warn!("Namespace `{}` is always created by curn", nstype);
+            }
+        }
+
+        if self
+            .uid_mappings
+            .iter()
+            .chain(self.gid_mappings.iter())
+            .any(|m| m.size == 0)
+        {
+            log::error!("Field `linux.uidMappings/gidMappings` has an empty range");
+            return Err(ErrorCode::BundleError(2));
+        }
+
+        if let Some(resources) = &self.resources {
+            resources.check_supported()?;
+        }
+        if let Some(action) = self.seccomp.as_ref().and_then(|s| s.unsupported_action()) {
+            log::error!("Field `linux.seccomp` uses unsupported {}", action);
+            return Err(ErrorCode::BundleUnsupported("linux.seccomp"));
+        }
+        if !self.devices.is_empty() {
+            return Err(ErrorCode::BundleUnsupported("linux.devices"));
+        }
+        if let Some(path) = self
+            .masked_paths
+            .iter()
+            .chain(self.readonly_paths.iter())
+            .flatten()
+            .find(|p| !p.is_absolute())
+        {
+            log::error!(
+                "Field `linux.maskedPaths/readonlyPaths` has relative path {:?}",
+                path
+            );
+            return Err(ErrorCode::BundleError(2));
+        }
+        if self.sysctl.is_some() {
+            return Err(ErrorCode::BundleUnsupported("linux.sysctl"));
+        }
+        Ok(())
+    }
+}
+
+impl Resources {
+    fn check_supported(&self) -> Result<(), ErrorCode> {
+        // mknod is never allowed in the container, which matches a deny-all rule
+        if self
+            .devices
+            .iter()
+            .any(|d| d.allow || d.devtype.is_some() || d.major.is_some() || d.minor.is_some())
+        {
+            return Err(ErrorCode::BundleUnsupported("linux.resources.devices"));
+        }
+        if let Some(memory) = &self.memory {
+            if memory.reservation.is_some() {
+                return Err(ErrorCode::BundleUnsupported(
+                    "linux.resources.memory.reservation",
+                ));
+            }
+            if memory.kernel.is_some() || memory.kernel_tcp.is_some() {
+                return Err(ErrorCode::BundleUnsupported(
+                    "linux.resources.memory.kernel",
+                ));
+            }
+            if memory.swappiness.is_some() {
+                return Err(ErrorCode::BundleUnsupported(
+                    "linux.resources.memory.swappiness",
+                ));
+            }
+            if memory.disable_oom_killer.is_some() {
+                return Err(ErrorCode::BundleUnsupported(
+                    "linux.resources.memory.disableOOMKiller",
+                ));
+            }
+            // swap of the spec includes memory, so it cannot be lower than the memory limit
+            if let (Some(swap), Some(limit)) = (memory.swap, memory.limit) {
+                if swap != -1 && limit != -1 && swap < limit {
+                    log::error!("Memory swap {} is lower than memory limit {}", swap, limit);
+                    return Err(ErrorCode::BundleError(2));
+                }
+            }
+        }
+        if let Some(cpu) = &self.cpu {
+            if cpu.realtime_runtime.is_some() || cpu.realtime_period.is_some() {
+                return Err(ErrorCode::BundleUnsupported("linux.resources.cpu.realtime"));
+            }
+            if cpu.idle.is_some() {
+                return Err(ErrorCode::BundleUnsupported("linux.resources.cpu.idle"));
+            }
+        }
+        if let Some(block_io) = &self.block_io {
+            if block_io.leaf_weight.is_some() {
+                return Err(ErrorCode::BundleUnsupported(
+                    "linux.resources.blockIO.leafWeight",
+                ));
+            }
+            if !block_io.weight_device.is_empty() {
+                return Err(ErrorCode::BundleUnsupported(
+                    "linux.resources.blockIO.weightDevice",
+                ));
+            }
+        }
+        if !self.hugepage_limits.is_empty() {
+            return Err(ErrorCode::BundleUnsupported(
+                "linux.resources.hugepageLimits",
+            ));
+        }
+        if self.network.is_some() {
+            return Err(ErrorCode::BundleUnsupported("linux.resources.network"));
+        }
+        if self.rdma.is_some() {
+            return Err(ErrorCode::BundleUnsupported("linux.resources.rdma"));
+        }
+        if self.unified.is_some() {
+            return Err(ErrorCode::BundleUnsupported("linux.resources.unified"));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{config::ContainerOpts, testing::TempDir, volume::Propagation};
+    use serde_json::{json, Value};
+    use std::ffi::CString;
+
+    /// Bundle in a temporary directory, with `rootfs` and `data` directories
+    fn bundle(name: &str, config: &Value) -> TempDir {
+        let dir = TempDir::new(&format!("oci-{}", name));
+        fs::create_dir_all(dir.join("rootfs")).unwrap();
+        fs::create_dir_all(dir.join("data")).unwrap();
+        fs::write(dir.join(CONFIG_FILE), config.to_string()).unwrap();
+        dir
+    }
+
+    fn minimal_config() -> Value {
+        json!({
+            "ociVersion": "1.0.2",
+            "process": {
+                "user": { "uid": 0, "gid": 0 },
+                "args": ["/bin/sh", "-c", "exit 3"],
+                "env": ["PATH=/bin:/usr/bin", "TERM=xterm"],
+                "cwd": "/srv",
+                "noNewPrivileges": true
+            },
+            "root": { "path": "rootfs", "readonly": true },
+            "hostname": "oci-test",
+            "mounts": [
+                { "destination": "/proc", "type": "proc", "source": "proc" },
+                {
+                    "destination": "/data",
+                    "type": "bind",
+                    "source": "data",
+                    "options": ["rbind", "ro", "rslave"]
+                },
+                {
+                    "destination": "/run",
+                    "type": "tmpfs",
+                    "source": "tmpfs",
+                    "options": ["nosuid", "size=1m"]
+                }
+            ],
+            "linux": {
+                "namespaces": [{ "type": "pid" }, { "type": "mount" }, { "type": "user" }]
+            }
+        })
+    }
+
+    /// Load `minimal_config` changed by `edit`
+    fn load_edited(name: &str, edit: impl FnOnce(&mut Value)) -> Result<Spec, ErrorCode> {
+        let mut config = minimal_config();
+        edit(&mut config);
+        load_spec(bundle(name, &config).path())
+    }
+
+    #[test]
+    fn minimal_bundle() {
+        let bundle = bundle("minimal", &minimal_config());
+        let config = ContainerOpts::from_bundle(
+            Some("oci".to_string()),
+            bundle.path(),
+            -1,
+            Vec::new(),
+            None,
+        )
+        .unwrap();
+        let bundle_dir = bundle.path().canonicalize().unwrap();
+
+        let cstrings = |args: &[&str]| args.iter().map(|a| CString::new(*a).unwrap()).collect();
+        let argv: Vec<CString> = cstrings(&["/bin/sh", "-c", "exit 3"]);
+        assert_eq!(config.path, argv[0]);
+        assert_eq!(config.argv, argv);
+        let env: Vec<CString> = cstrings(&["PATH=/bin:/usr/bin", "TERM=xterm"]);
+        assert_eq!(config.env, env);
+        assert_eq!(config.cwd, Path::new("/srv"));
+        assert_eq!(config.uid, 0);
+        assert_eq!(config.hostname, "oci-test");
+        assert_eq!(config.container_id, "oci");
+        assert_eq!(config.mount_dir, bundle_dir.join("rootfs"));
+        assert_eq!(config.bundle, Some(bundle_dir.clone()));
+        assert!(config.readonly_root);
+        // proc is mounted by curn itself
+        assert_eq!(config.volumes.len(), 1);
+        let volume = &config.volumes[0];
+        assert_eq!(volume.source, bundle_dir.join("data"));
+        assert_eq!(volume.destination, Path::new("data"));
+        assert!(volume.options.readonly);
+        assert_eq!(volume.propagation, Propagation::Slave);
+        assert_eq!(config.tmpfs.len(), 1);
+        assert_eq!(config.tmpfs[0].destination, Path::new("run"));
+        assert_eq!(config.tmpfs[0].data, ["size=1m"]);
+    }
+
+    #[test]
+    fn resources_of_cgroup_v1() {
+        let spec = load_edited("resources", |config| {
+            config["linux"]["resources"] = json!({
+                "cpu": { "shares": 1024 },
+                "blockIO": { "weight": 500 }
+            });
+        })
+        .unwrap();
+        let limits = spec.resource_limits();
+        assert_eq!(limits.cpu_shares, 39);
+        assert_eq!(limits.io_weight, Some(4950));
+        assert!(limits.validate().is_ok());
+    }
+
+    #[test]
+    fn generated_hostname_and_id() {
+        let mut config = minimal_config();
+        config["hostname"] = json!("");
+        let bundle = bundle("generated", &config);
+        let config = ContainerOpts::from_bundle(None, bundle.path(), -1, Vec::new(), None).unwrap();
+        assert!(!config.hostname.is_empty());
+        assert!(config.container_id.starts_with("cunrc."));
+    }
+
+    #[test]
+    fn unsupported_fields() {
+        let refused = |name: &str, edit: fn(&mut Value)| match load_edited(name, edit) {
+            Err(ErrorCode::BundleUnsupported(field)) => field,
+            res => panic!("`{}` is not refused: {:?}", name, res.map(|_| ())),
+        };
+        assert_eq!(
+            refused("hooks", |c| c["hooks"] = json!({ "prestart": [] })),
+            "hooks"
+        );
+        assert_eq!(
+            refused("version", |c| c["ociVersion"] = json!("2.0.0")),
+            "ociVersion"
+        );
+        assert_eq!(
+            refused("nstype", |c| c["linux"]["namespaces"] =
+                json!([{ "type": "time" }])),
+            "linux.namespaces.type"
+        );
+        assert_eq!(
+            refused("nsjoin", |c| {
+                c["linux"]["namespaces"] = json!([{ "type": "network", "path": "/proc/1/ns/net" }])
+            }),
+            "linux.namespaces.path"
+        );
+        assert_eq!(
+            refused("sysctl", |c| c["linux"]["sysctl"] =
+                json!({ "net.ipv4.ip_forward": "1" })),
+            "linux.sysctl"
+        );
+        assert_eq!(
+            refused("nfs", |c| {
+                c["mounts"] = json!([{ "destination": "/mnt", "type": "nfs", "source": "srv:/" }])
+            }),
+            "mounts.type"
+        );
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let invalid = |name: &str, edit: fn(&mut Value)| {
+            matches!(load_edited(name, edit), Err(ErrorCode::BundleError(_)))
+        };
+        assert!(invalid("noprocess", |c| c["process"] = Value::Null));
+        assert!(invalid("noroot", |c| c["root"] = Value::Null));
+        assert!(invalid("noargs", |c| c["process"]["args"] = json!([])));
+        assert!(invalid("relcwd", |c| c["process"]["cwd"] = json!("srv")));
+        assert!(invalid("uidtype", |c| c["process"]["user"]["uid"] = json!("root")));
+    }
+}
@@ -0,0 +1,1073 @@
+use crate::error::ErrorCode;
+use capctl::{Cap, CapSet};
+use libc::TIOCSTI;
+use nix::{errno::Errno, sched::CloneFlags, sys::stat::Mode, sys::utsname::uname};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    fs,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+use syscallz::{Action, Cmp, Comparator};
+
+pub const EPERM: u16 = 1;
+const ENOSYS: u16 = Errno::ENOSYS as u16;
+const S_ISUID: u64 = Mode::S_ISUID.bits() as u64;
+const S_ISGID: u64 = Mode::S_ISGID.bits() as u64;
+const CLONE_NEWUSER: u64 = CloneFlags::CLONE_NEWUSER.bits() as u64;
+// syscallz has no action for SCMP_ACT_LOG, it is compiled as SCMP_ACT_TRACE first
+pub const LOG_MARKER: u16 = 0x6c67;
+
+// Unconditional syscalls deny of the default profile
+const SYSCALLS_REFUSED: [&str; 9] = [
+    "keyctl",
+    "add_key",
+    "request_key",
+    "mbind",
+    "migrate_pages",
+    "move_pages",
+    "set_mempolicy",
+    "userfaultfd",
+    "perf_event_open",
+];
+
+// Conditional syscalls deny of the default profile
+const SYSCALL_REFUSE_IFCOMP: [(&str, u32, u64); 11] = [
+    ("chmod", 1, S_ISUID),
+    ("chmod", 1, S_ISGID),
+    ("fchmod", 1, S_ISUID),
+    ("fchmod", 1, S_ISGID),
+    ("fchmodat", 2, S_ISUID),
+    ("fchmodat", 2, S_ISGID),
+    ("fchmodat2", 2, S_ISUID),
+    ("fchmodat2", 2, S_ISGID),
+    ("unshare", 0, CLONE_NEWUSER),
+    ("clone", 0, CLONE_NEWUSER),
+    ("ioctl", 1, TIOCSTI),
+];
+
+// Syscalls of typical userland allowed by the allowlist profile, left out are e.g.
+// `bpf`, `io_uring_setup`, `open_by_handle_at`, `mount`, `ptrace` and `keyctl`
+const SYSCALLS_ALLOWED: &[&str] = &[
+    "accept",
+    "accept4",
+    "access",
+    "adjtimex",
+    "alarm",
+    "arch_prctl",
+    "bind",
+    "brk",
+    "cachestat",
+    "capget",
+    "capset",
+    "chdir",
+    "chown",
+    "clock_adjtime",
+    "clock_getres",
+    "clock_gettime",
+    "clock_nanosleep",
+    "close",
+    "close_range",
+    "connect",
+    "copy_file_range",
+    "creat",
+    "dup",
+    "dup2",
+    "dup3",
+    "epoll_create",
+    "epoll_create1",
+    "epoll_ctl",
+    "epoll_pwait",
+    "epoll_pwait2",
+    "epoll_wait",
+    "eventfd",
+    "eventfd2",
+    "execve",
+    "execveat",
+    "exit",
+    "exit_group",
+    "faccessat",
+    "faccessat2",
+    "fadvise64",
+    "fallocate",
+    "fanotify_mark",
+    "fchdir",
+    "fchown",
+    "fchownat",
+    "fcntl",
+    "fdatasync",
+    "fgetxattr",
+    "flistxattr",
+    "flock",
+    "fork",
+    "fremovexattr",
+    "fsetxattr",
+    "fstat",
+    "fstatfs",
+    "fsync",
+    "ftruncate",
+    "futex",
+    "futex_waitv",
+    "futimesat",
+    "getcpu",
+    "getcwd",
+    "getdents",
+    "getdents64",
+    "getegid",
+    "geteuid",
+    "getgid",
+    "getgroups",
+    "getitimer",
+    "getpeername",
+    "getpgid",
+    "getpgrp",
+    "getpid",
+    "getppid",
+    "getpriority",
+    "getrandom",
+    "getresgid",
+    "getresuid",
+    "getrlimit",
+    "get_robust_list",
+    "getrusage",
+    "getsid",
+    "getsockname",
+    "getsockopt",
+    "gettid",
+    "gettimeofday",
+    "getuid",
+    "getxattr",
+    "inotify_add_watch",
+    "inotify_init",
+    "inotify_init1",
+    "inotify_rm_watch",
+    "io_cancel",
+    "io_destroy",
+    "io_getevents",
+    "io_pgetevents",
+    "ioprio_get",
+    "ioprio_set",
+    "io_setup",
+    "io_submit",
+    "kill",
+    "landlock_add_rule",
+    "landlock_create_ruleset",
+    "landlock_restrict_self",
+    "lchown",
+    "lgetxattr",
+    "link",
+    "linkat",
+    "listen",
+    "listxattr",
+    "llistxattr",
+    "lremovexattr",
+    "lseek",
+    "lsetxattr",
+    "lstat",
+    "madvise",
+    "membarrier",
+    "memfd_create",
+    "mincore",
+    "mkdir",
+    "mkdirat",
+    "mknod",
+    "mknodat",
+    "mlock",
+    "mlock2",
+    "mlockall",
+    "mmap",
+    "mprotect",
+    "mq_getsetattr",
+    "mq_notify",
+    "mq_open",
+    "mq_timedreceive",
+    "mq_timedsend",
+    "mq_unlink",
+    "mremap",
+    "msgctl",
+    "msgget",
+    "msgrcv",
+    "msgsnd",
+    "msync",
+    "munlock",
+    "munlockall",
+    "munmap",
+    "nanosleep",
+    "newfstatat",
+    "open",
+    "openat",
+    "openat2",
+    "pause",
+    "pidfd_open",
+    "pidfd_send_signal",
+    "pipe",
+    "pipe2",
+    "pkey_alloc",
+    "pkey_free",
+    "pkey_mprotect",
+    "poll",
+    "ppoll",
+    "prctl",
+    "pread64",
+    "preadv",
+    "preadv2",
+    "prlimit64",
+    "pselect6",
+    "pwrite64",
+    "pwritev",
+    "pwritev2",
+    "read",
+    "readahead",
+    "readlink",
+    "readlinkat",
+    "readv",
+    "recvfrom",
+    "recvmmsg",
+    "recvmsg",
+    "remap_file_pages",
+    "removexattr",
+    "rename",
+    "renameat",
+    "renameat2",
+    "restart_syscall",
+    "rmdir",
+    "rseq",
+    "rt_sigaction",
+    "rt_sigpending",
+    "rt_sigprocmask",
+    "rt_sigqueueinfo",
+    "rt_sigreturn",
+    "rt_sigsuspend",
+    "rt_sigtimedwait",
+    "rt_tgsigqueueinfo",
+    "sched_getaffinity",
+    "sched_getattr",
+    "sched_getparam",
+    "sched_get_priority_max",
+    "sched_get_priority_min",
+    "sched_getscheduler",
+    "sched_rr_get_interval",
+    "sched_setaffinity",
+    "sched_setattr",
+    "sched_setparam",
+    "sched_setscheduler",
+    "sched_yield",
+    "seccomp",
+    "select",
+    "semctl",
+    "semget",
+    "semop",
+    "semtimedop",
+    "sendfile",
+    "sendmmsg",
+    "sendmsg",
+    "sendto",
+    "setfsgid",
+    "setfsuid",
+    "setgid",
+    "setgroups",
+    "setitimer",
+    "setpgid",
+    "setpriority",
+    "setregid",
+    "setresgid",
+    "setresuid",
+    "setreuid",
+    "setrlimit",
+    "set_robust_list",
+    "setsid",
+    "setsockopt",
+    "set_tid_address",
+    "setuid",
+    "setxattr",
+    "shmat",
+    "shmctl",
+    "shmdt",
+    "shmget",
+    "shutdown",
+    "sigaltstack",
+    "signalfd",
+    "signalfd4",
+    "socket",
+    "socketpair",
+    "splice",
+    "stat",
+    "statfs",
+    "statx",
+    "symlink",
+    "symlinkat",
+    "sync",
+    "sync_file_range",
+    "syncfs",
+    "sysinfo",
+    "tee",
+    "tgkill",
+    "time",
+    "timer_create",
+    "timer_delete",
+    "timer_getoverrun",
+    "timer_gettime",
+    "timer_settime",
+    "timerfd_create",
+    "timerfd_gettime",
+    "timerfd_settime",
+    "times",
+    "tkill",
+    "truncate",
+    "umask",
+    "uname",
+    "unlink",
+    "unlinkat",
+    "utime",
+    "utimensat",
+    "utimes",
+    "vfork",
+    "vmsplice",
+    "wait4",
+    "waitid",
+    "write",
+    "writev",
+];
+
+// Syscalls allowed by the allowlist profile only if the argument masked by the value is 0
+const SYSCALL_ALLOW_IFZERO: [(&str, u32, u64); 6] = [
+    ("chmod", 1, S_ISUID | S_ISGID),
+    ("fchmod", 1, S_ISUID | S_ISGID),
+    ("fchmodat", 2, S_ISUID | S_ISGID),
+    ("fchmodat2", 2, S_ISUID | S_ISGID),
+    ("unshare", 0, CLONE_NEWUSER),
+    ("clone", 0, CLONE_NEWUSER),
+];
+
+/// `--seccomp` mode of a container
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SeccompMode {
+    /// Built-in profile of curn
+    Default,
+    /// Allow only the syscalls of typical userland, others fail with EPERM
+    Allowlist,
+    /// Allow only the syscalls of typical userland, others kill the container
+    AllowlistKill,
+    /// No seccomp filter at all
+    Unconfined,
+}
+
+impl SeccompMode {
+    pub fn profile(self) -> Option<SeccompProfile> {
+        match self {
+            SeccompMode::Default => Some(SeccompProfile::default()),
+            SeccompMode::Allowlist => Some(SeccompProfile::allowlist(SeccompAction::Errno)),
+            SeccompMode::AllowlistKill => {
+                Some(SeccompProfile::allowlist(SeccompAction::KillProcess))
+            }
+            SeccompMode::Unconfined => None,
+        }
+    }
+}
+
+impl FromStr for SeccompMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "default" => Ok(Self::Default),
+            "allowlist" => Ok(Self::Allowlist),
+            "allowlist-kill" => Ok(Self::AllowlistKill),
+            "unconfined" => Ok(Self::Unconfined),
+            _ => Err(format!("unknown seccomp mode `{}`", s)),
+        }
+    }
+}
+
+/// Syscalls caught by the filter are reported to curn instead of being handled by the kernel
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeccompNotify {
+    /// Refuse them with their errno and record them in the audit log
+    Log,
+    /// Allow and record them, a profile which allows them is written to the path
+    Learn(PathBuf),
+}
+
+impl SeccompNotify {
+    /// Kill and trap actions are left to the kernel while logging
+    pub fn notifies(&self, action: SeccompAction) -> bool {
+        match self {
+            SeccompNotify::Log => action == SeccompAction::Errno,
+            SeccompNotify::Learn(_) => !matches!(action, SeccompAction::Allow | SeccompAction::Log),
+        }
+    }
+}
+
+/// Seccomp profile in the JSON format of Docker, `linux.seccomp` of OCI is a subset of it.
+/// Only the native architecture is filtered, `architectures` is ignored
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SeccompProfile {
+    pub default_action: SeccompAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default_errno_ret: Option<u16>,
+    #[serde(default)]
+    pub syscalls: Vec<SyscallRule>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeccompAction {
+    #[serde(rename = "SCMP_ACT_KILL_PROCESS")]
+    KillProcess,
+    #[serde(rename = "SCMP_ACT_KILL_THREAD", alias = "SCMP_ACT_KILL")]
+    KillThread,
+    #[serde(rename = "SCMP_ACT_TRAP")]
+    Trap,
+    #[serde(rename = "SCMP_ACT_ERRNO")]
+    Errno,
+    #[serde(rename = "SCMP_ACT_TRACE")]
+    Trace,
+    #[serde(rename = "SCMP_ACT_LOG")]
+    Log,
+    #[serde(rename = "SCMP_ACT_ALLOW")]
+    Allow,
+    /// Parsed to be refused explicitly, curn is the only listener of notifications
+    #[serde(rename = "SCMP_ACT_NOTIFY")]
+    Notify,
+}
+
+impl SeccompAction {
+    /// `ret` is the errno of `SCMP_ACT_ERRNO`, or the message of `SCMP_ACT_TRACE`.
+    /// `SCMP_ACT_LOG` is compiled as a marked `SCMP_ACT_TRACE`, see `LOG_MARKER`
+    pub fn to_action(self, ret: u16) -> Action {
+        match self {
+            SeccompAction::KillProcess => Action::KillProcess,
+            SeccompAction::KillThread => Action::KillThread,
+            SeccompAction::Trap => Action::Trap,
+            SeccompAction::Errno => Action::Errno(ret),
+            SeccompAction::Trace => Action::Trace(ret),
+            SeccompAction::Log => Action::Trace(LOG_MARKER),
+            SeccompAction::Allow => Action::Allow,
+            SeccompAction::Notify => unreachable!("Profiles with SCMP_ACT_NOTIFY are refused"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyscallRule {
+    #[serde(default)]
+    pub names: Vec<String>,
+    /// Single name of older Docker profiles
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub action: SeccompAction,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub errno_ret: Option<u16>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub args: Vec<SyscallArg>,
+    #[serde(default, skip_serializing_if = "RuleFilter::is_empty")]
+    pub includes: RuleFilter,
+    #[serde(default, skip_serializing_if = "RuleFilter::is_empty")]
+    pub excludes: RuleFilter,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SyscallArg {
+    pub index: u32,
+    pub value: u64,
+    #[serde(default)]
+    pub value_two: u64,
+    pub op: SeccompOp,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SeccompOp {
+    #[serde(rename = "SCMP_CMP_NE")]
+    Ne,
+    #[serde(rename = "SCMP_CMP_LT")]
+    Lt,
+    #[serde(rename = "SCMP_CMP_LE")]
+    Le,
+    #[serde(rename = "SCMP_CMP_EQ")]
+    Eq,
+    #[serde(rename = "SCMP_CMP_GE")]
+    Ge,
+    #[serde(rename = "SCMP_CMP_GT")]
+    Gt,
+    /// `value` is the mask, `valueTwo` the masked argument is compared to
+    #[serde(rename = "SCMP_CMP_MASKED_EQ")]
+    MaskedEq,
+}
+
+/// Conditions on the container which Docker profiles put on a rule
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RuleFilter {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub caps: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub arches: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min_kernel: Option<String>,
+}
+
+impl Default for SeccompProfile {
+    /// Allow every syscall but the ones curn always refused
+    fn default() -> Self {
+        let mut syscalls = vec![SyscallRule::new(&SYSCALLS_REFUSED, SeccompAction::Errno)];
+        for (name, index, bits) in SYSCALL_REFUSE_IFCOMP {
+            let mut rule = SyscallRule::new(&[name], SeccompAction::Errno);
+            rule.args.push(SyscallArg {
+                index,
+                value: bits,
+                value_two: bits,
+                op: SeccompOp::MaskedEq,
+            });
+            syscalls.push(rule);
+        }
+
+        Self {
+            default_action: SeccompAction::Allow,
+            default_errno_ret: None,
+            syscalls,
+        }
+    }
+}
+
+impl SeccompProfile {
+    /// Deny every syscall but the ones of typical userland by `default_action`
+    pub fn allowlist(default_action: SeccompAction) -> Self {
+        let mut syscalls = vec![SyscallRule::new(SYSCALLS_ALLOWED, SeccompAction::Allow)];
+        for (name, index, mask) in SYSCALL_ALLOW_IFZERO {
+            let mut rule = SyscallRule::new(&[name], SeccompAction::Allow);
+            rule.args.push(SyscallArg {
+                index,
+                value: mask,
+                value_two: 0,
+                op: SeccompOp::MaskedEq,
+            });
+            syscalls.push(rule);
+        }
+        let mut rule = SyscallRule::new(&["ioctl"], SeccompAction::Allow);
+        rule.args.push(SyscallArg {
+            index: 1,
+            value: TIOCSTI,
+            value_two: 0,
+            op: SeccompOp::Ne,
+        });
+        syscalls.push(rule);
+        // the flags of clone3 cannot be filtered, ENOSYS makes libc fall back to clone
+        let mut rule = SyscallRule::new(&["clone3"], SeccompAction::Errno);
+        rule.errno_ret = Some(ENOSYS);
+        syscalls.push(rule);
+        let mut rule = SyscallRule::new(&["chroot"], SeccompAction::Allow);
+        rule.includes.caps.push(Cap::SYS_CHROOT.to_string());
+        syscalls.push(rule);
+
+        Self {
+            default_action,
+            default_errno_ret: None,
+            syscalls,
+        }
+    }
+
+    /// Read a profile given by `--seccomp-profile`
+    pub fn load(path: &Path) -> Result<Self, ErrorCode> {
+        let content = fs::read_to_string(path).map_err(|e| {
+            log::error!("Cannot read seccomp profile {:?}: {}", path, e);
+            ErrorCode::ArgumentInvaild("seccomp-profile")
+        })?;
+        let profile: Self = serde_json::from_str(&content).map_err(|e| {
+            log::error!("Cannot parse seccomp profile {:?}: {}", path, e);
+            ErrorCode::ArgumentInvaild("seccomp-profile")
+        })?;
+        match profile.unsupported_action() {
+            Some(action) => {
+                log::error!("Seccomp profile {:?} uses unsupported {}", path, action);
+                Err(ErrorCode::ArgumentInvaild("seccomp-profile"))
+            }
+            None => Ok(profile),
+        }
+    }
+
+    /// `SCMP_ACT_NOTIFY` would need a listener other than curn
+    pub fn unsupported_action(&self) -> Option<&'static str> {
+        let notify = self.default_action == SeccompAction::Notify
+            || self
+                .syscalls
+                .iter()
+                .any(|r| r.action == SeccompAction::Notify);
+        notify.then_some("action SCMP_ACT_NOTIFY")
+    }
+
+    /// `SCMP_ACT_LOG` reuses the slot of `SCMP_ACT_TRACE` as well
+    pub fn uses_log(&self) -> bool {
+        self.default_action == SeccompAction::Log
+            || self.syscalls.iter().any(|r| r.action == SeccompAction::Log)
+    }
+
+    pub fn default_errno(&self) -> u16 {
+        self.default_errno_ret.unwrap_or(EPERM)
+    }
+
+    /// Notifications of the kernel reuse the slot of `SCMP_ACT_TRACE`
+    pub fn uses_trace(&self) -> bool {
+        self.default_action == SeccompAction::Trace
+            || self
+                .syscalls
+                .iter()
+                .any(|r| r.action == SeccompAction::Trace)
+    }
+
+    /// Actions and errnos of the rules matching a syscall, followed by the default one
+    pub fn matching<'a>(
+        &'a self,
+        name: &'a str,
+        args: &'a [u64],
+        bounding: CapSet,
+    ) -> impl Iterator<Item = (SeccompAction, u16)> + 'a {
+        let default_errno = self.default_errno();
+        self.syscalls
+            .iter()
+            .filter(move |r| r.names().any(|n| n == name) && r.matches(args))
+            .filter(move |r| r.applies(bounding))
+            .map(move |r| (r.action, r.errno_ret.unwrap_or(default_errno)))
+            .chain(std::iter::once((self.default_action, default_errno)))
+    }
+
+    /// Same profile, but the syscalls observed while learning are allowed
+    pub fn learn(&self, observed: &BTreeSet<String>) -> Self {
+        let mut profile = self.clone();
+        for rule in profile
+            .syscalls
+            .iter_mut()
+            .filter(|r| r.action != SeccompAction::Allow)
+        {
+            rule.names.retain(|n| !observed.contains(n));
+            if rule.name.as_ref().is_some_and(|n| observed.contains(n)) {
+                rule.name = None;
+            }
+        }
+        profile.syscalls.retain(|r| r.names().next().is_some());
+
+        let names = observed.iter().map(String::as_str).collect::<Vec<_>>();
+        if !names.is_empty() {
+            profile
+                .syscalls
+                .push(SyscallRule::new(&names, SeccompAction::Allow));
+        }
+        profile
+    }
+}
+
+impl SyscallRule {
+    fn new(names: &[&str], action: SeccompAction) -> Self {
+        Self {
+            names: names.iter().map(|n| n.to_string()).collect(),
+            name: None,
+            action,
+            errno_ret: None,
+            args: Vec::new(),
+            includes: RuleFilter::default(),
+            excludes: RuleFilter::default(),
+        }
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.names.iter().chain(self.name.as_ref())
+    }
+
+    /// Comparators of a rule must all match, but libseccomp refuses two on the same
+    /// argument, so any of these matches instead
+    pub fn any_arg(&self) -> bool {
+        self.args
+            .iter()
+            .enumerate()
+            .any(|(i, a)| self.args[..i].iter().any(|b| b.index == a.index))
+    }
+
+    /// Whether the arguments of a syscall match the comparators, as the filter does
+    pub fn matches(&self, args: &[u64]) -> bool {
+        match self.any_arg() {
+            true => self.args.iter().any(|a| a.matches(args)),
+            false => self.args.iter().all(|a| a.matches(args)),
+        }
+    }
+
+    /// Every condition of `includes` holds and none of `excludes`, like Docker the
+    /// capabilities are looked up in the bounding set
+    pub fn applies(&self, bounding: CapSet) -> bool {
+        let has_cap = |name: &String| Cap::from_str(name).is_ok_and(|cap| bounding.has(cap));
+        let kernel = kernel_version();
+        let kernel_at_least =
+            |min: &String| kernel.is_some_and(|k| parse_version(min).is_some_and(|m| k >= m));
+
+        let included = self.includes.caps.iter().all(has_cap)
+            && (self.includes.arches.is_empty() || self.includes.arches.iter().any(is_native_arch))
+            && self
+                .includes
+                .min_kernel
+                .as_ref()
+                .is_none_or(kernel_at_least);
+        let excluded = self.excludes.caps.iter().any(has_cap)
+            || self.excludes.arches.iter().any(is_native_arch)
+            || self
+                .excludes
+                .min_kernel
+                .as_ref()
+                .is_some_and(kernel_at_least);
+        included && !excluded
+    }
+}
+
+impl SyscallArg {
+    pub fn to_comparator(&self) -> Comparator {
+        let op = match self.op {
+            SeccompOp::Ne => Cmp::Ne,
+            SeccompOp::Lt => Cmp::Lt,
+            SeccompOp::Le => Cmp::Le,
+            SeccompOp::Eq => Cmp::Eq,
+            SeccompOp::Ge => Cmp::Ge,
+            SeccompOp::Gt => Cmp::Gt,
+            SeccompOp::MaskedEq => Cmp::MaskedEq,
+        };
+        Comparator::new(self.index, op, self.value, Some(self.value_two))
+    }
+
+    fn matches(&self, args: &[u64]) -> bool {
+        let Some(&arg) = args.get(self.index as usize) else {
+            return false;
+        };
+        match self.op {
+            SeccompOp::Ne => arg != self.value,
+            SeccompOp::Lt => arg < self.value,
+            SeccompOp::Le => arg <= self.value,
+            SeccompOp::Eq => arg == self.value,
+            SeccompOp::Ge => arg >= self.value,
+            SeccompOp::Gt => arg > self.value,
+            SeccompOp::MaskedEq => arg & self.value == self.value_two,
+        }
+    }
+}
+
+impl RuleFilter {
+    fn is_empty(&self) -> bool {
+        self.caps.is_empty() && self.arches.is_empty() && self.min_kernel.is_none()
+    }
+}
+
+/// Architecture names of Docker profiles and image platforms are the ones of Go
+pub fn is_native_arch(arch: &String) -> bool {
+    let native = std::env::consts::ARCH;
+    arch == native
+        || match native {
+            "x86_64" => arch == "amd64",
+            "x86" => arch == "386",
+            "aarch64" => arch == "arm64",
+            "powerpc64" => arch == "ppc64le",
+            _ => false,
+        }
+}
+
+/// Major and minor version, e.g. `(6, 8)` of `6.8.0-45-generic`
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let mut parts = version.split(['.', '-']);
+    let major = parts.next()?.parse().ok()?;
+    let minor = parts.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+fn kernel_version() -> Option<(u32, u32)> {
+    let host = uname().ok()?;
+    parse_version(host.release().to_str()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    const DOCKER_PROFILE: &str = r#"{
+        "defaultAction": "SCMP_ACT_ERRNO",
+        "defaultErrnoRet": 38,
+        "archMap": [{ "architecture": "SCMP_ARCH_X86_64", "subArchitectures": [] }],
+        "syscalls": [
+            { "names": ["read", "write"], "action": "SCMP_ACT_ALLOW" },
+            { "name": "ptrace", "action": "SCMP_ACT_KILL" },
+            {
+                "names": ["personality"],
+                "action": "SCMP_ACT_ALLOW",
+                "args": [{ "index": 0, "value": 131072, "op": "SCMP_CMP_EQ" }]
+            },
+            {
+                "names": ["chmod"],
+                "action": "SCMP_ACT_ERRNO",
+                "errnoRet": 13,
+                "args": [{ "index": 1, "value": 2048, "valueTwo": 2048, "op": "SCMP_CMP_MASKED_EQ" }]
+            },
+            {
+                "names": ["mount"],
+                "action": "SCMP_ACT_ALLOW",
+                "includes": { "caps": ["CAP_SYS_ADMIN"] },
+                "excludes": { "arches": ["s390x"] }
+            }
+        ]
+    }"#;
+
+    fn load_str(name: &str, content: &str) -> Result<SeccompProfile, ErrorCode> {
+        let dir = TempDir::new(&format!("seccomp-{}", name));
+        let path = dir.join("seccomp.json");
+        fs::write(&path, content).unwrap();
+        SeccompProfile::load(&path)
+    }
+
+    fn rule_with(includes: RuleFilter, excludes: RuleFilter) -> SyscallRule {
+        let mut rule = SyscallRule::new(&["mount"], SeccompAction::Allow);
+        rule.includes = includes;
+        rule.excludes = excludes;
+        rule
+    }
+
+    #[test]
+    fn docker_profile() {
+        let profile = load_str("docker", DOCKER_PROFILE).unwrap();
+        assert_eq!(profile.default_action, SeccompAction::Errno);
+        assert_eq!(profile.default_errno(), 38);
+        assert_eq!(profile.syscalls.len(), 5);
+
+        let names = profile.syscalls[0].names().collect::<Vec<_>>();
+        assert_eq!(names, ["read", "write"]);
+        // `name` of older profiles and the old name of SCMP_ACT_KILL_THREAD
+        let ptrace = &profile.syscalls[1];
+        assert_eq!(ptrace.names().collect::<Vec<_>>(), ["ptrace"]);
+        assert_eq!(ptrace.action, SeccompAction::KillThread);
+
+        let chmod = &profile.syscalls[3];
+        assert_eq!(chmod.errno_ret, Some(13));
+        assert_eq!(chmod.args[0].op, SeccompOp::MaskedEq);
+        assert_eq!((chmod.args[0].value, chmod.args[0].value_two), (2048, 2048));
+        assert_eq!(profile.syscalls[2].args[0].value_two, 0);
+
+        let mount = &profile.syscalls[4];
+        assert_eq!(mount.includes.caps, ["CAP_SYS_ADMIN"]);
+        assert_eq!(mount.excludes.arches, ["s390x"]);
+    }
+
+    #[test]
+    fn broken_profiles() {
+        let unknown_action = r#"{ "defaultAction": "SCMP_ACT_UNKNOWN" }"#;
+        assert!(matches!(
+            load_str("action", unknown_action),
+            Err(ErrorCode::ArgumentInvaild("seccomp-profile"))
+        ));
+        let notify = DOCKER_PROFILE.replace("SCMP_ACT_KILL", "SCMP_ACT_NOTIFY");
+        let notify_profile: SeccompProfile = serde_json::from_str(&notify).unwrap();
+        assert!(notify_profile.unsupported_action().is_some());
+        assert!(load_str("notify", &notify).is_err());
+        let log = load_str("log", r#"{ "defaultAction": "SCMP_ACT_LOG" }"#).unwrap();
+        assert!(log.uses_log());
+        assert!(!log.uses_trace());
+        let unknown_op = DOCKER_PROFILE.replace("SCMP_CMP_MASKED_EQ", "SCMP_CMP_MASKED_NE");
+        assert!(load_str("op", &unknown_op).is_err());
+        assert!(SeccompProfile::load(Path::new("/nonexistent/seccomp.json")).is_err());
+        assert_eq!(
+            load_str("empty", r#"{ "defaultAction": "SCMP_ACT_ALLOW" }"#)
+                .unwrap()
+                .default_errno(),
+            EPERM
+        );
+    }
+
+    #[test]
+    fn rules_filtered_by_capabilities() {
+        let sys_admin = RuleFilter {
+            caps: vec!["CAP_SYS_ADMIN".to_string()],
+            ..RuleFilter::default()
+        };
+        let with_cap = [Cap::SYS_ADMIN, Cap::CHOWN].into_iter().collect::<CapSet>();
+        let without_cap = [Cap::CHOWN].into_iter().collect::<CapSet>();
+
+        let included = rule_with(sys_admin.clone(), RuleFilter::default());
+        assert!(included.applies(with_cap));
+        assert!(!included.applies(without_cap));
+        let excluded = rule_with(RuleFilter::default(), sys_admin);
+        assert!(!excluded.applies(with_cap));
+        assert!(excluded.applies(without_cap));
+
+        // an unknown capability is never held
+        let unknown = RuleFilter {
+            caps: vec!["CAP_UNKNOWN".to_string()],
+            ..RuleFilter::default()
+        };
+        assert!(!rule_with(unknown.clone(), RuleFilter::default()).applies(with_cap));
+        assert!(rule_with(RuleFilter::default(), unknown).applies(with_cap));
+    }
+
+    #[test]
+    fn rules_filtered_by_architecture() {
+        let arches = |arches: &[&str]| RuleFilter {
+            arches: arches.iter().map(|a| a.to_string()).collect(),
+            ..RuleFilter::default()
+        };
+        let native = std::env::consts::ARCH;
+        let caps = CapSet::empty();
+
+        assert!(rule_with(arches(&["s390x", native]), RuleFilter::default()).applies(caps));
+        assert!(!rule_with(arches(&["s390x"]), RuleFilter::default()).applies(caps));
+        assert!(!rule_with(RuleFilter::default(), arches(&[native])).applies(caps));
+        assert!(rule_with(RuleFilter::default(), arches(&["s390x"])).applies(caps));
+        if native == "x86_64" {
+            assert!(is_native_arch(&"amd64".to_string()));
+            assert!(!is_native_arch(&"arm64".to_string()));
+        }
+    }
+
+    #[test]
+    fn rules_filtered_by_kernel() {
+        let min_kernel = |version: &str| RuleFilter {
+            min_kernel: Some(version.to_string()),
+            ..RuleFilter::default()
+        };
+        let caps = CapSet::empty();
+
+        assert!(rule_with(min_kernel("3.0"), RuleFilter::default()).applies(caps));
+        assert!(!rule_with(min_kernel("99.0"), RuleFilter::default()).applies(caps));
+        assert!(!rule_with(RuleFilter::default(), min_kernel("3.0")).applies(caps));
+        assert!(rule_with(RuleFilter::default(), min_kernel("99.0")).applies(caps));
+        // a version which cannot be parsed never holds
+        assert!(!rule_with(min_kernel("latest"), RuleFilter::default()).applies(caps));
+    }
+
+    #[test]
+    fn kernel_versions() {
+        assert_eq!(parse_version("6.8.0-45-generic"), Some((6, 8)));
+        assert_eq!(parse_version("5.10"), Some((5, 10)));
+        assert_eq!(parse_version("4.14-rc1"), Some((4, 14)));
+        assert_eq!(parse_version("6"), None);
+        // minor versions are compared as numbers
+        assert!(parse_version("5.10") > parse_version("5.9"));
+    }
+
+    fn arg(index: u32, op: SeccompOp, value: u64, value_two: u64) -> SyscallArg {
+        SyscallArg {
+            index,
+            value,
+            value_two,
+            op,
+        }
+    }
+
+    #[test]
+    fn masked_eq_compares_masked_bits() {
+        // `value` is the mask, `valueTwo` what the masked argument must be
+        let setuid = arg(1, SeccompOp::MaskedEq, S_ISUID | S_ISGID, S_ISUID);
+        assert!(setuid.matches(&[0, 0o4755]));
+        assert!(!setuid.matches(&[0, 0o6755]));
+        assert!(!setuid.matches(&[0, 0o755]));
+        // missing arguments never match
+        assert!(!setuid.matches(&[0]));
+
+        let profile = SeccompProfile::default();
+        let refused = |args: &[u64]| {
+            profile.matching("fchmod", args, CapSet::empty()).next()
+                == Some((SeccompAction::Errno, EPERM))
+        };
+        assert!(refused(&[3, 0o4755]));
+        assert!(refused(&[3, 0o2755]));
+        assert!(!refused(&[3, 0o755]));
+    }
+
+    #[test]
+    fn comparators_on_one_argument_are_any_of() {
+        let mut rule = SyscallRule::new(&["personality"], SeccompAction::Allow);
+        rule.args = vec![arg(0, SeccompOp::Eq, 0, 0), arg(0, SeccompOp::Eq, 8, 0)];
+        assert!(rule.any_arg());
+        assert!(rule.matches(&[0]) && rule.matches(&[8]));
+        assert!(!rule.matches(&[4]));
+
+        // comparators on different arguments must all match
+        rule.args = vec![arg(0, SeccompOp::Ge, 8, 0), arg(1, SeccompOp::Lt, 2, 0)];
+        assert!(!rule.any_arg());
+        assert!(rule.matches(&[8, 1]));
+        assert!(!rule.matches(&[8, 2]) && !rule.matches(&[7, 1]));
+        // no comparator matches any arguments
+        rule.args.clear();
+        assert!(rule.matches(&[]));
+    }
+
+    #[test]
+    fn matching_rules_then_default() {
+        let profile = load_str("matching", DOCKER_PROFILE).unwrap();
+        let sys_admin = [Cap::SYS_ADMIN].into_iter().collect::<CapSet>();
+        let matching = |name: &str, args: &[u64], caps: CapSet| {
+            profile.matching(name, args, caps).collect::<Vec<_>>()
+        };
+
+        let errno = (SeccompAction::Errno, 38);
+        assert_eq!(
+            matching("read", &[], CapSet::empty()),
+            [(SeccompAction::Allow, 38), errno]
+        );
+        assert_eq!(
+            matching("chmod", &[0, 0o4755], CapSet::empty()),
+            [(SeccompAction::Errno, 13), errno]
+        );
+        assert_eq!(matching("chmod", &[0, 0o755], CapSet::empty()), [errno]);
+        assert_eq!(matching("mount", &[], CapSet::empty()), [errno]);
+        assert_eq!(
+            matching("mount", &[], sys_admin),
+            [(SeccompAction::Allow, 38), errno]
+        );
+        assert_eq!(matching("reboot", &[], sys_admin), [errno]);
+    }
+
+    #[test]
+    fn learn_allows_observed_syscalls() {
+        let profile = load_str("learn", DOCKER_PROFILE).unwrap();
+        let observed = ["chmod", "ptrace", "reboot"]
+            .into_iter()
+            .map(String::from)
+            .collect::<BTreeSet<_>>();
+        let learned = profile.learn(&observed);
+
+        // rules refusing only observed syscalls are gone, allowing ones are kept as is
+        let names = learned
+            .syscalls
+            .iter()
+            .map(|r| r.names().cloned().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            names,
+            [
+                vec!["read", "write"],
+                vec!["personality"],
+                vec!["mount"],
+                vec!["chmod", "ptrace", "reboot"],
+            ]
+        );
+        assert_eq!(learned.syscalls[1].args.len(), 1);
+        assert_eq!(learned.syscalls[3].action, SeccompAction::Allow);
+        assert_eq!(learned.default_action, profile.default_action);
+        assert_eq!(learned.default_errno(), 38);
+
+        // the observed arguments are not known, so chmod is allowed with any of them
+        let allowed = learned
+            .matching("chmod", &[0, 0o4755], CapSet::empty())
+            .next();
+        assert_eq!(allowed, Some((SeccompAction::Allow, 38)));
+    }
+
+    #[test]
+    fn learn_keeps_rules_of_other_syscalls() {
+        let mut profile = SeccompProfile::default();
+        profile.syscalls[0].name = Some("reboot".to_string());
+        let observed = ["keyctl", "reboot"].into_iter().map(String::from).collect();
+        let learned = profile.learn(&observed);
+
+        let refused = &learned.syscalls[0];
+        assert!(refused.names().all(|n| n != "keyctl" && n != "reboot"));
+        assert_eq!(refused.names().count(), SYSCALLS_REFUSED.len() - 1);
+        assert_eq!(learned.syscalls.len(), profile.syscalls.len() + 1);
+
+        let unchanged = profile.learn(&BTreeSet::new());
+        assert_eq!(unchanged.syscalls.len(), profile.syscalls.len());
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{
+    audit::{AuditEvent, AuditLog, AuditRecord},
+    config::ContainerOpts,
+    error::ErrorCode,
+    ipc::recv_fd,
+    seccomp::{SeccompAction, SeccompNotify, SeccompProfile, EPERM},
+};
+use libc::{
+    seccomp_notif, seccomp_notif_resp, SECCOMP_IOCTL_NOTIF_RECV, SECCOMP_IOCTL_NOTIF_SEND,
+    SECCOMP_USER_NOTIF_FLAG_CONTINUE,
+};
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::{
+        signal::{SigSet, SigmaskHow},
+        wait::{waitid, Id, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+use std::{
+    collections::BTreeSet,
+    ffi::{c_char, c_int, CStr},
+    fs,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+    thread::{self, JoinHandle},
+};
+
+// linked through syscallz, seccomp-sys does not bind it
+extern "C" {
+    fn seccomp_syscall_resolve_num_arch(arch: u32, num: c_int) -> *mut c_char;
+}
+const SCMP_ARCH_NATIVE: u32 = 0;
+
+/// Thread of curn answering the seccomp notifications of the container
+pub struct SeccompNotifier {
+    notify: SeccompNotify,
+    profile: SeccompProfile,
+    handle: JoinHandle<BTreeSet<String>>,
+}
+
+impl SeccompNotifier {
+    /// Receive the listener from the child through `socket` and start answering
+    pub fn start(
+        config: &ContainerOpts,
+        notify: SeccompNotify,
+        pid: Pid,
+        socket: RawFd,
+    ) -> Result<Self, ErrorCode> {
+        log::debug!("Receiving seccomp notifications of the container");
+
+        let listener = receive_listener(pid, socket)?;
+        let profile = config.seccomp.clone().ok_or(ErrorCode::SyscallError(4))?;
+        let (thread_config, thread_notify, thread_profile) =
+            (config.clone(), notify.clone(), profile.clone());
+        // the thread inherits the mask, signals of the container are left to the forwarder
+        let old_mask = SigSet::all().thread_swap_mask(SigmaskHow::SIG_BLOCK);
+        let handle = thread::spawn(move || {
+            answer(listener, &thread_config, &thread_notify, &thread_profile)
+        });
+        if let Ok(old_mask) = old_mask {
+            let _ = old_mask.thread_set_mask();
+        }
+
+        Ok(Self {
+            notify,
+            profile,
+            handle,
+        })
+    }
+
+    /// Wait until every process of the container exited, then write the learned profile
+    pub fn finish(self) -> Result<(), ErrorCode> {
+        let Ok(observed) = self.handle.join() else {
+            log::error!("Thread answering seccomp notifications panicked");
+            return Err(ErrorCode::SyscallError(4));
+        };
+        let SeccompNotify::Learn(path) = &self.notify else {
+            return Ok(());
+        };
+
+        let profile = self.profile.learn(&observed);
+        let res = serde_json::to_string_pretty(&profile)
+            .map_err(|e| e.to_string())
+            .and_then(|content| fs::write(path, content + "\n").map_err(|e| e.to_string()));
+        if let Err(e) = res {
+            log::error!("Cannot write learned seccomp profile {:?}: {}", path, e);
+            return Err(ErrorCode::SyscallError(5));
+        }
+        log::info!(
+            "Learned seccomp profile allowing {} more syscalls written to {:?}",
+            observed.len(),
+            path
+        );
+        Ok(())
+    }
+}
+
+/// Wait for the listener, the child exits without sending it if it fails to set up
+fn receive_listener(pid: Pid, socket: RawFd) -> Result<OwnedFd, ErrorCode> {
+    let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT;
+    loop {
+        let fd = unsafe { BorrowedFd::borrow_raw(socket) };
+        match poll(
+            &mut [PollFd::new(fd, PollFlags::POLLIN)],
+            PollTimeout::from(100u8),
+        ) {
+            Ok(0) | Err(Errno::EINTR) => {}
+            Ok(_) => return recv_fd(socket),
+            Err(e) => {
+                log::error!("Cannot wait for seccomp listener: {:?}", e);
+                return Err(ErrorCode::SyscallError(4));
+            }
+        }
+        match waitid(Id::Pid(pid), flags) {
+            Ok(WaitStatus::StillAlive) | Err(Errno::EINTR) => {}
+            _ => {
+                log::error!("Child exited before sending seccomp listener");
+                return Err(ErrorCode::SyscallError(4));
+            }
+        }
+    }
+}
+
+/// Answer notifications until the filter has no process left,
+/// return the names of the syscalls allowed while learning
+fn answer(
+    listener: OwnedFd,
+    config: &ContainerOpts,
+    notify: &SeccompNotify,
+    profile: &SeccompProfile,
+) -> BTreeSet<String> {
+    let mut audit = match config.rootless {
+        true => {
+            log::warn!("Audit log needs root, caught syscalls are only logged");
+            None
+        }
+        false => AuditLog::open(&config.container_id).ok(),
+    };
+    let mut observed = BTreeSet::new();
+
+    loop {
+        let mut fds = [PollFd::new(listener.as_fd(), PollFlags::POLLIN)];
+        match poll(&mut fds, PollTimeout::NONE) {
+            Ok(_) | Err(Errno::EINTR) => {}
+            Err(e) => {
+                log::error!("Cannot poll seccomp listener: {:?}", e);
+                break;
+            }
+        }
+        let revents = fds[0].revents().unwrap_or(PollFlags::POLLHUP);
+        if !revents.contains(PollFlags::POLLIN) {
+            if revents.intersects(PollFlags::POLLHUP | PollFlags::POLLERR) {
+                break;
+            }
+            continue;
+        }
+
+        let mut req: seccomp_notif = unsafe { std::mem::zeroed() };
+        let res = unsafe { libc::ioctl(listener.as_raw_fd(), SECCOMP_IOCTL_NOTIF_RECV, &mut req) };
+        if res < 0 {
+            // the process was killed meanwhile
+            match Errno::last() {
+                Errno::EINTR | Errno::ENOENT => continue,
+                e => {
+                    log::error!("Cannot receive seccomp notification: {:?}", e);
+                    break;
+                }
+            }
+        }
+
+        let syscall = syscall_name(req.data.nr);
+        let args = req.data.args;
+        let bounding = config.capabilities.bounding;
+        let (action, errno) = profile
+            .matching(&syscall, &args, bounding)
+            .find(|(action, _)| notify.notifies(*action))
+            .unwrap_or((SeccompAction::Errno, EPERM));
+        let allowed = matches!(notify, SeccompNotify::Learn(_));
+
+        // the process is blocked until answered, read it before
+        let pid = req.pid as i32;
+        let record = AuditRecord::seccomp(
+            pid,
+            process_uid(pid),
+            process_comm(pid),
+            AuditEvent::Seccomp {
+                syscall: syscall.clone(),
+                args: args.to_vec(),
+                action,
+                allowed,
+            },
+            config,
+        );
+        let mut resp = seccomp_notif_resp {
+            id: req.id,
+            val: 0,
+            error: 0,
+            flags: 0,
+        };
+        match allowed {
+            true => resp.flags = SECCOMP_USER_NOTIF_FLAG_CONTINUE as u32,
+            false => resp.error = -(errno as i32),
+        }
+        let res = unsafe { libc::ioctl(listener.as_raw_fd(), SECCOMP_IOCTL_NOTIF_SEND, &resp) };
+        if res < 0 && Errno::last() != Errno::ENOENT {
+            log::error!("Cannot answer seccomp notification: {:?}", Errno::last());
+        }
+
+        log::warn!("{}", record);
+        if let Some(audit) = audit.as_mut() {
+            let _ = audit.append(&record);
+        }
+        if allowed {
+            match syscall.parse::<i32>() {
+                Ok(_) => log::warn!("Cannot learn syscall {} without a name", syscall),
+                Err(_) => {
+                    observed.insert(syscall);
+                }
+            }
+        }
+    }
+
+    log::debug!("No process of the container is filtered anymore");
+    observed
+}
+
+/// Name of a syscall of the native architecture, its number if libseccomp does not know it
+fn syscall_name(nr: c_int) -> String {
+    let name = unsafe { seccomp_syscall_resolve_num_arch(SCMP_ARCH_NATIVE, nr) };
+    if name.is_null() {
+        return nr.to_string();
+    }
+    let value = unsafe { CStr::from_ptr(name) }
+        .to_string_lossy()
+        .into_owned();
+    unsafe { libc::free(name as *mut libc::c_void) };
+    value
+}
+
+/// Real uid of a process, as seen by the host
+fn process_uid(pid: i32) -> i32 {
+    fs::read_to_string(format!("/proc/{}/status", pid))
+        .ok()
+        .and_then(|status| {
+            status
+                .lines()
+                .find_map(|l| l.strip_prefix("Uid:"))
+                .and_then(|uids| uids.split_whitespace().next())
+                .and_then(|uid| uid.parse().ok())
+        })
+        .unwrap_or(-1)
+}
+
+fn process_comm(pid: i32) -> String {
+    fs::read_to_string(format!("/proc/{}/comm", pid))
+        .map(|comm| comm.trim_end().to_string())
+        .unwrap_or_default()
+}
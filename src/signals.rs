@@ -0,0 +1,166 @@
+use crate::error::ErrorCode;
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    sys::{
+        signal::{kill, SigSet, SigmaskHow, Signal},
+        signalfd::{SfdFlags, SignalFd},
+        wait::{waitid, Id, WaitPidFlag, WaitStatus},
+    },
+    unistd::Pid,
+};
+use std::{
+    os::fd::{AsFd, BorrowedFd},
+    time::{Duration, Instant},
+};
+
+pub const DEFAULT_GRACE_PERIOD: &str = "10s";
+
+pub const FORWARDED_SIGNALS: [Signal; 6] = [
+    Signal::SIGTERM,
+    Signal::SIGINT,
+    Signal::SIGHUP,
+    Signal::SIGUSR1,
+    Signal::SIGUSR2,
+    Signal::SIGQUIT,
+];
+// forwarding one of these starts the grace period before SIGKILL
+const STOP_SIGNALS: [Signal; 4] = [
+    Signal::SIGTERM,
+    Signal::SIGINT,
+    Signal::SIGHUP,
+    Signal::SIGQUIT,
+];
+
+/// Receive the signals sent to curn through a signalfd and forward them to the container,
+/// so curn itself is never killed before cleaning up
+pub struct SignalForwarder {
+    pid: Pid,
+    grace_period: Duration,
+    deadline: Option<Instant>,
+    signals: SignalFd,
+    old_mask: SigSet,
+}
+
+impl SignalForwarder {
+    /// `extra` signals are not forwarded but returned by `handle`
+    pub fn new(pid: Pid, grace_period: Duration, extra: &[Signal]) -> Result<Self, ErrorCode> {
+        log::debug!("Forwarding signals to container process (pid {})", pid);
+
+        let mut mask = SigSet::empty();
+        for signal in FORWARDED_SIGNALS.iter().chain(extra) {
+            mask.add(*signal);
+        }
+        // SIGCHLD makes the signalfd readable when the container exits
+        mask.add(Signal::SIGCHLD);
+
+        let old_mask = mask.thread_swap_mask(SigmaskHow::SIG_BLOCK).map_err(|e| {
+            log::error!("Cannot block signals: {:?}", e);
+            ErrorCode::ContainerError(5)
+        })?;
+        match SignalFd::with_flags(&mask, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC) {
+            Ok(signals) => Ok(Self {
+                pid,
+                grace_period,
+                deadline: None,
+                signals,
+                old_mask,
+            }),
+            Err(e) => {
+                log::error!("Cannot create signalfd: {:?}", e);
+                let _ = old_mask.thread_set_mask();
+                Err(ErrorCode::ContainerError(5))
+            }
+        }
+    }
+
+    /// Forward pending signals to the container, return the ones which are not forwarded
+    pub fn handle(&mut self) -> Vec<Signal> {
+        let mut others = Vec::new();
+        while let Ok(Some(info)) = self.signals.read_signal() {
+            let Ok(signal) = Signal::try_from(info.ssi_signo as i32) else {
+                continue;
+            };
+            if !FORWARDED_SIGNALS.contains(&signal) {
+                others.push(signal);
+                continue;
+            }
+
+            log::info!(
+                "Forwarding {} to container process (pid {})",
+                signal,
+                self.pid
+            );
+            let _ = kill(self.pid, signal);
+            if STOP_SIGNALS.contains(&signal) && self.deadline.is_none() {
+                self.deadline = Some(Instant::now() + self.grace_period);
+            }
+        }
+        others
+    }
+
+    /// How long to wait before the grace period is over
+    pub fn timeout(&self) -> PollTimeout {
+        match self.deadline {
+            Some(deadline) => deadline
+                .saturating_duration_since(Instant::now())
+                .try_into()
+                .unwrap_or(PollTimeout::MAX),
+            None => PollTimeout::NONE,
+        }
+    }
+
+    /// Kill the container if it did not stop within the grace period
+    pub fn check_grace_period(&mut self) {
+        if self.deadline.is_some_and(|d| Instant::now() >= d) {
+            log::info!(
+                "Container process (pid {}) did not exit within {:?}, sending SIGKILL",
+                self.pid,
+                self.grace_period
+            );
+            let _ = kill(self.pid, Signal::SIGKILL);
+            self.deadline = None;
+        }
+    }
+
+    /// The container has exited, it is left unreaped for `waitpid`
+    pub fn has_exited(&self) -> bool {
+        let flags = WaitPidFlag::WEXITED | WaitPidFlag::WNOHANG | WaitPidFlag::WNOWAIT;
+        !matches!(
+            waitid(Id::Pid(self.pid), flags),
+            Ok(WaitStatus::StillAlive) | Err(Errno::EINTR)
+        )
+    }
+
+    /// Forward signals until the container exits
+    pub fn wait(&mut self) -> Result<(), ErrorCode> {
+        // SIGCHLD is lost if the container exited before it was blocked
+        while !self.has_exited() {
+            let mut fds = [PollFd::new(self.signals.as_fd(), PollFlags::POLLIN)];
+            match poll(&mut fds, self.timeout()) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(e) => {
+                    log::error!("Cannot poll signalfd: {:?}", e);
+                    return Err(ErrorCode::ContainerError(5));
+                }
+            }
+            self.handle();
+            self.check_grace_period();
+        }
+        Ok(())
+    }
+}
+
+impl AsFd for SignalForwarder {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.signals.as_fd()
+    }
+}
+
+impl Drop for SignalForwarder {
+    /// Signals received meanwhile are dropped instead of killing curn once unblocked
+    fn drop(&mut self) {
+        while let Ok(Some(_)) = self.signals.read_signal() {}
+        let _ = self.old_mask.thread_set_mask();
+    }
+}
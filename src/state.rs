@@ -0,0 +1,191 @@
+use crate::{config::ContainerOpts, error::ErrorCode, user_namespace::is_rootless};
+use nix::{
+    sys::signal::kill,
+    unistd::{getuid, Pid},
+};
+use serde::{Deserialize, Serialize};
+use std::{
+    env,
+    fs::{self, create_dir_all, remove_dir_all},
+    path::PathBuf,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+const OCI_VERSION: &str = "1.0.2";
+const STATE_ROOT: &str = "/run/curn";
+const ROOTLESS_STATE_DIR: &str = "curn";
+const STATE_FILE: &str = "state.json";
+const START_FIFO: &str = "exec.fifo";
+const MONITOR_LOG: &str = "monitor.log";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Creating,
+    Created,
+    Running,
+    Stopped,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ContainerState {
+    pub oci_version: String,
+    pub id: String,
+    pub status: Status,
+    pub pid: Option<i32>,
+    pub bundle: PathBuf,
+    pub created: u64,
+    pub ebpf_pid: Option<i32>,
+    pub exit_code: Option<i32>,
+    pub config: ContainerOpts,
+}
+
+impl ContainerState {
+    pub fn new(config: &ContainerOpts, pid: Option<Pid>, ebpf_pid: Option<Pid>) -> Self {
+        let created = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+
+        Self {
+            oci_version: OCI_VERSION.to_string(),
+            id: config.container_id.clone(),
+            status: Status::Creating,
+            pid: pid.map(|p| p.as_raw()),
+            bundle: config.bundle.clone().unwrap_or(config.mount_dir.clone()),
+            created,
+            ebpf_pid: ebpf_pid.map(|p| p.as_raw()),
+            exit_code: None,
+            config: config.clone(),
+        }
+    }
+
+    /// Load state of given container, stopped processes are detected on the fly
+    pub fn load(container_id: &str) -> Result<Self, ErrorCode> {
+        let path = state_dir(container_id).join(STATE_FILE);
+        if !path.exists() {
+            log::error!("Container `{}` does not exist", container_id);
+            return Err(ErrorCode::StateError(3));
+        }
+
+        let content = fs::read_to_string(&path).map_err(|e| {
+            log::error!("Cannot read state file {:?}: {}", path, e);
+            ErrorCode::StateError(2)
+        })?;
+        let mut state: Self = serde_json::from_str(&content).map_err(|e| {
+            log::error!("Cannot parse state file {:?}: {}", path, e);
+            ErrorCode::StateError(2)
+        })?;
+
+        if matches!(state.status, Status::Created | Status::Running) && !state.is_alive() {
+            state.status = Status::Stopped;
+        }
+
+        Ok(state)
+    }
+
+    pub fn save(&self) -> Result<(), ErrorCode> {
+        let path = state_dir(&self.id).join(STATE_FILE);
+        let tmp_path = path.with_extension("tmp");
+        log::debug!("Saving state `{:?}` to {:?}", self.status, path);
+
+        let content = serde_json::to_string_pretty(self).map_err(|e| {
+            log::error!("Cannot serialize state: {}", e);
+            ErrorCode::StateError(1)
+        })?;
+
+        // write to a temp file first so readers never see a partial state
+        if let Err(e) = fs::write(&tmp_path, content).and_then(|_| fs::rename(&tmp_path, &path)) {
+            log::error!("Cannot write state file {:?}: {}", path, e);
+            return Err(ErrorCode::StateError(1));
+        }
+
+        Ok(())
+    }
+
+    pub fn is_alive(&self) -> bool {
+        match self.pid {
+            Some(pid) => kill(Pid::from_raw(pid), None).is_ok(),
+            None => false,
+        }
+    }
+}
+
+/// Load the states of all containers, stopped ones included
+pub fn list_states() -> Vec<ContainerState> {
+    let Ok(entries) = fs::read_dir(state_root()) else {
+        return Vec::new();
+    };
+    entries
+        .flatten()
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        // a container being created has no state yet
+        .filter(|id| !id.starts_with('.') && state_dir(id).join(STATE_FILE).exists())
+        .filter_map(|id| ContainerState::load(&id).ok())
+        .collect()
+}
+
+/// Return root of all states, rootless ones are kept in the user's runtime directory
+fn state_root() -> PathBuf {
+    if !is_rootless() {
+        return PathBuf::from(STATE_ROOT);
+    }
+    match env::var_os("XDG_RUNTIME_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(ROOTLESS_STATE_DIR),
+        _ => PathBuf::from(format!("/tmp/{}-{}", ROOTLESS_STATE_DIR, getuid())),
+    }
+}
+
+/// Return state directory of a container, e.g. /run/curn/cunrc.xxx...
+pub fn state_dir(container_id: &str) -> PathBuf {
+    state_root().join(container_id)
+}
+
+/// Return the fifo which `start` writes to, e.g. /run/curn/cunrc.xxx.../exec.fifo
+pub fn start_fifo(container_id: &str) -> PathBuf {
+    state_dir(container_id).join(START_FIFO)
+}
+
+/// Return the log of a detached container, its monitor and command write to it,
+/// e.g. /run/curn/cunrc.xxx.../monitor.log
+pub fn monitor_log(container_id: &str) -> PathBuf {
+    state_dir(container_id).join(MONITOR_LOG)
+}
+
+/// Return directory of address leases, container IDs never start with `.`
+pub fn lease_dir() -> PathBuf {
+    state_root().join(".network")
+}
+
+/// Return directory of subordinate id leases
+pub fn idmap_lease_dir() -> PathBuf {
+    state_root().join(".idmap")
+}
+
+pub fn create_state_dir(container_id: &str) -> Result<(), ErrorCode> {
+    let dir = state_dir(container_id);
+    if dir.exists() {
+        log::error!("Container `{}` already exists", container_id);
+        return Err(ErrorCode::StateError(4));
+    }
+
+    if let Err(e) = create_dir_all(&dir) {
+        log::error!("Cannot create state directory {:?}: {}", dir, e);
+        return Err(ErrorCode::StateError(0));
+    }
+
+    Ok(())
+}
+
+pub fn remove_state_dir(container_id: &str) -> Result<(), ErrorCode> {
+    let dir = state_dir(container_id);
+    log::debug!("Removing state directory {:?}", dir);
+
+    if let Err(e) = remove_dir_all(&dir) {
+        log::error!("Cannot remove state directory {:?}: {}", dir, e);
+        return Err(ErrorCode::StateError(6));
+    }
+
+    Ok(())
+}
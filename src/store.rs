@@ -0,0 +1,56 @@
+use crate::{error::ErrorCode, user_namespace::is_rootless};
+use std::{
+    env,
+    path::{Path, PathBuf},
+    sync::OnceLock,
+};
+
+const STORE_ROOT: &str = "/var/lib/curn";
+const ROOTLESS_STORE_DIR: &str = "curn";
+
+static ROOT: OnceLock<PathBuf> = OnceLock::new();
+
+/// Use `--root` as root of the store, it is joined into overlay options so it must be
+/// absolute and must not contain `:` or `,`
+pub fn set_store_root(root: Option<PathBuf>) -> Result<(), ErrorCode> {
+    let Some(root) = root else {
+        return Ok(());
+    };
+    let root = std::path::absolute(&root).map_err(|_| ErrorCode::ArgumentInvaild("root"))?;
+    let valid = root
+        .to_str()
+        .is_some_and(|p| !p.contains(':') && !p.contains(','));
+    if !valid {
+        return Err(ErrorCode::ArgumentInvaild("root"));
+    }
+    let _ = ROOT.set(root);
+    Ok(())
+}
+
+/// Return root of what curn keeps across runs: images, root filesystems of containers
+/// and audit logs, e.g. /var/lib/curn
+pub fn store_root() -> &'static Path {
+    ROOT.get_or_init(default_store_root)
+}
+
+/// Rootless stores are kept in the user's data directory
+fn default_store_root() -> PathBuf {
+    if !is_rootless() {
+        return PathBuf::from(STORE_ROOT);
+    }
+    match env::var_os("XDG_DATA_HOME") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir).join(ROOTLESS_STORE_DIR),
+        _ => match env::var_os("HOME") {
+            Some(home) => PathBuf::from(home)
+                .join(".local/share")
+                .join(ROOTLESS_STORE_DIR),
+            None => PathBuf::from(format!("/tmp/{}-store", ROOTLESS_STORE_DIR)),
+        },
+    }
+}
+
+/// Return the directory holding the root filesystem of a container and its overlay,
+/// e.g. /var/lib/curn/containers/cunrc.xxx...
+pub fn container_dir(container_id: &str) -> PathBuf {
+    store_root().join("containers").join(container_id)
+}
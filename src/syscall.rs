@@ -1,78 +1,219 @@
-use crate::error::ErrorCode;
-use libc::TIOCSTI;
-use nix::{sched::CloneFlags, sys::stat::Mode};
+use crate::{
+    error::ErrorCode,
+    seccomp::{SeccompNotify, SeccompProfile, SyscallRule, LOG_MARKER},
+};
+use capctl::{prctl, CapSet};
+use libc::{
+    sock_filter, sock_fprog, BPF_K, BPF_RET, SECCOMP_FILTER_FLAG_NEW_LISTENER, SECCOMP_RET_LOG,
+    SECCOMP_RET_TRACE, SECCOMP_RET_USER_NOTIF, SECCOMP_SET_MODE_FILTER,
+};
+use nix::sys::memfd::{memfd_create, MemFdCreateFlag};
+use std::{
+    fs::File,
+    io::{Read, Seek, SeekFrom},
+    os::fd::{FromRawFd, OwnedFd, RawFd},
+};
 use syscallz::{Action, Cmp, Comparator, Context, Syscall};
 
-const EPERM: u16 = 1;
-const S_ISUID: u64 = Mode::S_ISUID.bits() as u64;
-const S_ISGID: u64 = Mode::S_ISGID.bits() as u64;
-const CLONE_NEWUSER: u64 = CloneFlags::CLONE_NEWUSER.bits() as u64;
+// libseccomp has no action for notifications, they are compiled as SCMP_ACT_TRACE first
+const NOTIFY_MARKER: u16 = 0x6375;
+// the child sends the listener of the notifications with it
+const HANDSHAKE_SYSCALL: &str = "sendmsg";
 
-// Unconditional syscalls deny
-const SYSCALLS_REFUSED: [Syscall; 9] = [
-    Syscall::keyctl,
-    Syscall::add_key,
-    Syscall::request_key,
-    Syscall::mbind,
-    Syscall::migrate_pages,
-    Syscall::move_pages,
-    Syscall::set_mempolicy,
-    Syscall::userfaultfd,
-    Syscall::perf_event_open,
-];
-
-// Conditional syscalls deny
-const SYSCALL_REFUSE_IFCOMP: [(Syscall, u32, u64); 9] = [
-    (Syscall::chmod, 1, S_ISUID),
-    (Syscall::chmod, 1, S_ISGID),
-    (Syscall::fchmod, 1, S_ISUID),
-    (Syscall::fchmod, 1, S_ISGID),
-    (Syscall::fchmodat, 2, S_ISUID),
-    (Syscall::fchmodat, 2, S_ISGID),
-    (Syscall::unshare, 0, CLONE_NEWUSER),
-    (Syscall::clone, 0, CLONE_NEWUSER),
-    (Syscall::ioctl, 1, TIOCSTI),
-];
-
-pub fn set_syscalls() -> Result<(), ErrorCode> {
+/// Compile the profile into a seccomp filter and load it, rules depending on
+/// capabilities are kept according to the bounding set.
+/// Return the listener of the notifications if `notify` is set, it is sent through `socket`
+pub fn set_syscalls(
+    profile: Option<&SeccompProfile>,
+    bounding: CapSet,
+    notify: Option<&SeccompNotify>,
+    socket: RawFd,
+) -> Result<Option<OwnedFd>, ErrorCode> {
+    let Some(profile) = profile else {
+        log::debug!("Seccomp is unconfined");
+        // loading a filter sets it as well, the command never gains privileges on execve
+        return match prctl::set_no_new_privs() {
+            Ok(_) => Ok(None),
+            Err(_) => Err(ErrorCode::SyscallError(0)),
+        };
+    };
     log::debug!("Refusing and filter unwanted syscalls");
 
-    if let Ok(mut ctx) = Context::init_with_action(Action::Allow) {
-        for sc in SYSCALLS_REFUSED {
-            refuse_syscall(&mut ctx, sc)?;
+    let default_errno = profile.default_errno();
+    let to_action = |action, errno| match notify {
+        Some(notify) if notify.notifies(action) => Action::Trace(NOTIFY_MARKER),
+        _ => action.to_action(errno),
+    };
+    let default_action = to_action(profile.default_action, default_errno);
+    let Ok(mut ctx) = Context::init_with_action(default_action) else {
+        return Err(ErrorCode::SyscallError(1));
+    };
+
+    // actions of the rules on the syscall sending the listener, and whether they have args
+    let mut handshake = Vec::new();
+    for rule in profile.syscalls.iter().filter(|r| r.applies(bounding)) {
+        let action = to_action(rule.action, rule.errno_ret.unwrap_or(default_errno));
+        // libseccomp refuses rules whose action is the default one
+        if u32::from(action) == u32::from(default_action) {
+            continue;
         }
-        for (sc, index, biteq) in SYSCALL_REFUSE_IFCOMP {
-            refuse_syscall_ifcomp(&mut ctx, sc, index, biteq)?;
+        for name in rule.names() {
+            if name == HANDSHAKE_SYSCALL {
+                handshake.push((action, !rule.args.is_empty()));
+            }
+            match Syscall::from_name(name) {
+                Some(sc) => add_rule(&mut ctx, action, sc, rule)?,
+                None => log::debug!("Skip syscall `{}` unknown on this architecture", name),
+            }
         }
+    }
 
-        if let Err(_) = ctx.load() {
-            return Err(ErrorCode::SyscallError(0));
-        }
-        Ok(())
-    } else {
-        Err(ErrorCode::SyscallError(1))
+    if notify.is_some() {
+        allow_handshake(&mut ctx, socket, default_action, &handshake)?;
+        return load_rewritten(&ctx, true);
+    }
+    if profile.uses_log() {
+        return load_rewritten(&ctx, false);
     }
+    if ctx.load().is_err() {
+        return Err(ErrorCode::SyscallError(0));
+    }
+    Ok(None)
 }
 
-fn refuse_syscall(ctx: &mut Context, sc: Syscall) -> Result<(), ErrorCode> {
-    match ctx.set_action_for_syscall(Action::Errno(EPERM), sc) {
-        Ok(_) => Ok(()),
-        Err(_) => Err(ErrorCode::SyscallError(2)),
+/// The listener is sent once the filter is loaded, a notification of `sendmsg` would wait
+/// for curn which waits for the listener. The socket is allowed when the default action
+/// notifies it, it is closed before the command is executed. Profiles notifying `sendmsg`
+/// by their own rules are refused
+fn allow_handshake(
+    ctx: &mut Context,
+    socket: RawFd,
+    default_action: Action,
+    rules: &[(Action, bool)],
+) -> Result<(), ErrorCode> {
+    let notified = |action: Action| u32::from(action) == u32::from(Action::Trace(NOTIFY_MARKER));
+    let Some(sc) = Syscall::from_name(HANDSHAKE_SYSCALL) else {
+        return Ok(());
+    };
+    if rules.is_empty() {
+        if !notified(default_action) {
+            return Ok(());
+        }
+        let socket = Comparator::new(0, Cmp::Eq, socket as u64, None);
+        return match ctx.set_rule_for_syscall(Action::Allow, sc, &[socket]) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(ErrorCode::SyscallError(3)),
+        };
+    }
+
+    // rules with args let the other calls reach the default action
+    let unconditional = rules.iter().any(|(_, args)| !args);
+    if rules.iter().any(|(action, _)| notified(*action))
+        || (notified(default_action) && !unconditional)
+    {
+        log::error!(
+            "`{}` sends the seccomp listener, the profile must not notify it",
+            HANDSHAKE_SYSCALL
+        );
+        return Err(ErrorCode::SyscallError(3));
     }
+    Ok(())
 }
 
-fn refuse_syscall_ifcomp(
+fn add_rule(
     ctx: &mut Context,
+    action: Action,
     sc: Syscall,
-    index: u32,
-    biteq: u64,
+    rule: &SyscallRule,
 ) -> Result<(), ErrorCode> {
-    match ctx.set_rule_for_syscall(
-        Action::Errno(EPERM),
-        sc,
-        &[Comparator::new(index, Cmp::MaskedEq, biteq, Some(biteq))],
-    ) {
+    if rule.args.is_empty() {
+        return match ctx.set_action_for_syscall(action, sc) {
+            Ok(_) => Ok(()),
+            Err(_) => Err(ErrorCode::SyscallError(2)),
+        };
+    }
+
+    let comparators = rule
+        .args
+        .iter()
+        .map(|a| a.to_comparator())
+        .collect::<Vec<_>>();
+    let res = match rule.any_arg() {
+        true => comparators
+            .iter()
+            .try_for_each(|c| ctx.set_rule_for_syscall(action, sc, std::slice::from_ref(c))),
+        false => ctx.set_rule_for_syscall(action, sc, &comparators),
+    };
+    match res {
         Ok(_) => Ok(()),
         Err(_) => Err(ErrorCode::SyscallError(3)),
     }
 }
+
+/// Export the filter, turn the notification markers into `SECCOMP_RET_USER_NOTIF` and
+/// the log markers into `SECCOMP_RET_LOG`, and load it, with a listener if `listener` is
+/// set, which libseccomp of most distributions cannot do
+fn load_rewritten(ctx: &Context, listener: bool) -> Result<Option<OwnedFd>, ErrorCode> {
+    let mut program = Vec::new();
+    let exported = memfd_create(c"curn-seccomp", MemFdCreateFlag::MFD_CLOEXEC)
+        .map(File::from)
+        .map_err(|e| e.to_string())
+        .and_then(|mut file| {
+            ctx.export_bpf(&mut file).map_err(|e| e.to_string())?;
+            file.seek(SeekFrom::Start(0))
+                .and_then(|_| file.read_to_end(&mut program))
+                .map_err(|e| e.to_string())
+        });
+    if let Err(e) = exported {
+        log::error!("Cannot export seccomp filter: {}", e);
+        return Err(ErrorCode::SyscallError(0));
+    }
+
+    let mut filter = program
+        .chunks_exact(size_of::<sock_filter>())
+        .map(|b| sock_filter {
+            code: u16::from_ne_bytes([b[0], b[1]]),
+            jt: b[2],
+            jf: b[3],
+            k: u32::from_ne_bytes([b[4], b[5], b[6], b[7]]),
+        })
+        .collect::<Vec<_>>();
+    for insn in filter
+        .iter_mut()
+        .filter(|insn| insn.code == (BPF_RET | BPF_K) as u16)
+    {
+        if insn.k == SECCOMP_RET_TRACE | NOTIFY_MARKER as u32 {
+            insn.k = SECCOMP_RET_USER_NOTIF;
+        } else if insn.k == SECCOMP_RET_TRACE | LOG_MARKER as u32 {
+            insn.k = SECCOMP_RET_LOG;
+        }
+    }
+    let prog = sock_fprog {
+        len: filter.len() as u16,
+        filter: filter.as_mut_ptr(),
+    };
+
+    if prctl::set_no_new_privs().is_err() {
+        return Err(ErrorCode::SyscallError(0));
+    }
+    let flags = match listener {
+        true => SECCOMP_FILTER_FLAG_NEW_LISTENER,
+        false => 0,
+    };
+    let fd = unsafe {
+        libc::syscall(
+            libc::SYS_seccomp,
+            SECCOMP_SET_MODE_FILTER,
+            flags,
+            &prog as *const sock_fprog,
+        )
+    };
+    if fd < 0 {
+        log::error!(
+            "Cannot load seccomp filter: {}",
+            std::io::Error::last_os_error()
+        );
+        return Err(ErrorCode::SyscallError(0));
+    }
+    Ok(listener.then(|| unsafe { OwnedFd::from_raw_fd(fd as i32) }))
+}
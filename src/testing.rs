@@ -0,0 +1,34 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process,
+};
+
+/// Directory of a test under the temporary directory, removed with its content when
+/// dropped, even if the test panics
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    /// `name` tells apart the directories of the tests running at the same time
+    pub fn new(name: &str) -> Self {
+        let path = env::temp_dir().join(format!("curn-{}-{}", name, process::id()));
+        // left over by a test killed before it could clean up
+        let _ = fs::remove_dir_all(&path);
+        fs::create_dir_all(&path).unwrap();
+        Self(path)
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.0
+    }
+
+    pub fn join(&self, path: impl AsRef<Path>) -> PathBuf {
+        self.0.join(path)
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
@@ -0,0 +1,188 @@
+use crate::{error::ErrorCode, signals::SignalForwarder};
+use nix::{
+    errno::Errno,
+    libc,
+    poll::{poll, PollFd, PollFlags, PollTimeout},
+    pty::{openpty, Winsize},
+    sys::{
+        signal::Signal,
+        termios::{cfmakeraw, tcgetattr, tcsetattr, SetArg, Termios},
+    },
+    unistd::{close, dup2, isatty, read, setsid, write},
+};
+use std::{
+    io::stdin,
+    os::fd::{AsFd, BorrowedFd, IntoRawFd, RawFd},
+};
+
+const STDIN: RawFd = 0;
+const STDOUT: RawFd = 1;
+const BUFFER_SIZE: usize = 4096;
+
+nix::ioctl_read_bad!(get_winsize, libc::TIOCGWINSZ, Winsize);
+nix::ioctl_write_ptr_bad!(set_winsize, libc::TIOCSWINSZ, Winsize);
+nix::ioctl_write_int_bad!(set_controlling_tty, libc::TIOCSCTTY);
+
+/// Pseudo-terminal pair, the slave becomes the terminal of the container
+#[derive(Debug, Clone, Copy)]
+pub struct Pty {
+    pub master: RawFd,
+    pub slave: RawFd,
+}
+
+/// Window size of curn's own terminal, if it runs in one
+fn host_winsize() -> Option<Winsize> {
+    if !isatty(STDIN).unwrap_or(false) {
+        return None;
+    }
+    let mut winsize: Winsize = unsafe { std::mem::zeroed() };
+    unsafe { get_winsize(STDIN, &mut winsize) }.ok()?;
+    Some(winsize)
+}
+
+pub fn open_pty() -> Result<Pty, ErrorCode> {
+    log::debug!("Allocating pseudo-terminal");
+
+    match openpty(host_winsize().as_ref(), None) {
+        Ok(pty) => Ok(Pty {
+            master: pty.master.into_raw_fd(),
+            slave: pty.slave.into_raw_fd(),
+        }),
+        Err(e) => {
+            log::error!("Cannot allocate pseudo-terminal: {:?}", e);
+            Err(ErrorCode::TtyError(0))
+        }
+    }
+}
+
+/// Make the slave the controlling terminal and standard streams of the calling process
+pub fn set_controlling_terminal(pty: &Pty) -> Result<(), ErrorCode> {
+    log::debug!("Setting controlling terminal");
+
+    let _ = close(pty.master);
+    let res = setsid()
+        .and_then(|_| unsafe { set_controlling_tty(pty.slave, 0) })
+        .and_then(|_| (0..3).try_for_each(|fd| dup2(pty.slave, fd).map(|_| ())));
+    if let Err(e) = res {
+        log::error!("Cannot set controlling terminal: {:?}", e);
+        return Err(ErrorCode::TtyError(1));
+    }
+    let _ = close(pty.slave);
+    Ok(())
+}
+
+/// Put curn's terminal into raw mode, the original mode is restored when dropped
+struct RawMode {
+    original: Termios,
+}
+
+impl RawMode {
+    fn enable() -> Result<Option<Self>, ErrorCode> {
+        if !isatty(STDIN).unwrap_or(false) {
+            return Ok(None);
+        }
+        let original = tcgetattr(stdin()).map_err(|e| {
+            log::error!("Cannot get terminal attributes: {:?}", e);
+            ErrorCode::TtyError(2)
+        })?;
+        let mut raw = original.clone();
+        cfmakeraw(&mut raw);
+        tcsetattr(stdin(), SetArg::TCSANOW, &raw).map_err(|e| {
+            log::error!("Cannot set terminal to raw mode: {:?}", e);
+            ErrorCode::TtyError(2)
+        })?;
+        Ok(Some(Self { original }))
+    }
+}
+
+impl Drop for RawMode {
+    fn drop(&mut self) {
+        let _ = tcsetattr(stdin(), SetArg::TCSANOW, &self.original);
+    }
+}
+
+/// Copy everything readable from one fd to another, return false on EOF
+fn copy(from: RawFd, to: RawFd) -> Result<bool, Errno> {
+    let mut buf = [0u8; BUFFER_SIZE];
+    let n = match read(from, &mut buf) {
+        Ok(0) | Err(Errno::EIO) => return Ok(false),
+        Ok(n) => n,
+        Err(Errno::EINTR | Errno::EAGAIN) => return Ok(true),
+        Err(e) => return Err(e),
+    };
+
+    let to = unsafe { BorrowedFd::borrow_raw(to) };
+    let mut written = 0;
+    while written < n {
+        match write(to, &buf[written..n]) {
+            Ok(m) => written += m,
+            Err(Errno::EINTR) => {}
+            Err(e) => return Err(e),
+        }
+    }
+    Ok(true)
+}
+
+fn forward_winsize(master: RawFd) {
+    if let Some(winsize) = host_winsize() {
+        let _ = unsafe { set_winsize(master, &winsize) };
+    }
+}
+
+fn proxy_loop(master: RawFd, forwarder: &mut SignalForwarder) -> Result<(), Errno> {
+    let mut stdin_open = true;
+    while !forwarder.has_exited() {
+        let (stdin_ready, master_ready) = {
+            let stdin = stdin();
+            let master_fd = unsafe { BorrowedFd::borrow_raw(master) };
+            let mut fds = vec![
+                PollFd::new(master_fd, PollFlags::POLLIN),
+                PollFd::new(forwarder.as_fd(), PollFlags::POLLIN),
+            ];
+            if stdin_open {
+                fds.push(PollFd::new(stdin.as_fd(), PollFlags::POLLIN));
+            }
+            match poll(&mut fds, forwarder.timeout()) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(e) => return Err(e),
+            }
+            let ready = |fd: &PollFd| fd.any().unwrap_or(false);
+            (fds.get(2).is_some_and(ready), ready(&fds[0]))
+        };
+
+        // the container is exiting once its terminal is closed
+        if master_ready && !copy(master, STDOUT)? {
+            return Ok(());
+        }
+        if stdin_ready && !copy(STDIN, master)? {
+            stdin_open = false;
+        }
+        if forwarder.handle().contains(&Signal::SIGWINCH) {
+            forward_winsize(master);
+        }
+        forwarder.check_grace_period();
+    }
+
+    // drain what the container wrote before exiting
+    let master_fd = unsafe { BorrowedFd::borrow_raw(master) };
+    let mut fds = [PollFd::new(master_fd, PollFlags::POLLIN)];
+    while poll(&mut fds, PollTimeout::ZERO).unwrap_or(0) > 0 {
+        if !copy(master, STDOUT)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Proxy curn's terminal to the container's one until the container exits
+/// or closes it, `forwarder` must also receive SIGWINCH
+pub fn proxy_tty(master: RawFd, forwarder: &mut SignalForwarder) -> Result<(), ErrorCode> {
+    log::debug!("Proxying terminal of container");
+
+    forward_winsize(master);
+    let _raw = RawMode::enable()?;
+    proxy_loop(master, forwarder).map_err(|e| {
+        log::error!("Cannot proxy terminal: {:?}", e);
+        ErrorCode::TtyError(3)
+    })
+}
@@ -1,23 +1,288 @@
 use crate::{
     error::ErrorCode,
     ipc::{recv_bool, send_bool},
+    state::idmap_lease_dir,
 };
 use nix::{
     sched::{unshare, CloneFlags},
-    unistd::{setgroups, setresgid, setresuid, Gid, Pid, Uid},
+    unistd::{getegid, geteuid, getuid, setgroups, setresgid, setresuid, Gid, Pid, Uid, User},
 };
-use std::{fs::File, io::Write, os::fd::RawFd};
+use serde::{Deserialize, Serialize};
+use std::{
+    fmt,
+    fs::{self, create_dir_all, File, OpenOptions},
+    io::Write,
+    os::fd::RawFd,
+    path::{Path, PathBuf},
+    process,
+    str::FromStr,
+};
+
+// ids of the host reserved for containers by systemd, ranges are allocated from them
+// when root has no subordinate ids
+const CONTAINER_IDS_START: u32 = 0x80000;
+const CONTAINER_IDS_COUNT: u32 = 0x70000000 - CONTAINER_IDS_START;
+const SUBUID_FILE: &str = "/etc/subuid";
+const SUBGID_FILE: &str = "/etc/subgid";
+// ids allocated to a container, enough for `nobody` (65534) of most images
+const SUBID_RANGE_SIZE: u32 = 65536;
+// setuid helpers of shadow-utils which map subordinate ids for unprivileged users
+const NEWUIDMAP: &str = "newuidmap";
+const NEWGIDMAP: &str = "newgidmap";
+
+/// curn runs without real root, the container gets a user namespace of its own first
+pub fn is_rootless() -> bool {
+    !geteuid().is_root()
+}
 
-const USERNS_OFFSET: u64 = 10000;
-const USERNS_COUNT: u64 = 2000;
+/// Range of ids inside the container mapped to host ids, e.g. `0:100000:65536`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct IdMap {
+    #[serde(rename = "containerID")]
+    pub container_id: u32,
+    #[serde(rename = "hostID")]
+    pub host_id: u32,
+    pub size: u32,
+}
+
+impl IdMap {
+    fn contains(&self, id: u32) -> bool {
+        id >= self.container_id
+            && u64::from(id) < u64::from(self.container_id) + u64::from(self.size)
+    }
 
-pub fn set_user_namespace(fd: RawFd, uid: u32) -> Result<(), ErrorCode> {
-    log::debug!("Setting up user namespaces with UID {}", uid);
+    /// Both ranges share an id, inside or outside of the container
+    fn overlaps(&self, other: &IdMap) -> bool {
+        let intersect = |a: u32, b: u32| {
+            u64::from(a) < u64::from(b) + u64::from(other.size)
+                && u64::from(b) < u64::from(a) + u64::from(self.size)
+        };
+        intersect(self.container_id, other.container_id) || intersect(self.host_id, other.host_id)
+    }
+}
+
+impl FromStr for IdMap {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let fields = s
+            .split(':')
+            .map(|f| f.parse::<u32>().map_err(|e| format!("`{}`: {}", f, e)))
+            .collect::<Result<Vec<_>, _>>()?;
+        match fields[..] {
+            // the kernel refuses ranges wrapping around the id space
+            [container_id, host_id, size]
+                if size > 0
+                    && container_id.checked_add(size - 1).is_some()
+                    && host_id.checked_add(size - 1).is_some() =>
+            {
+                Ok(Self {
+                    container_id,
+                    host_id,
+                    size,
+                })
+            }
+            _ => Err(format!("expected CONTAINER_ID:HOST_ID:SIZE, got `{}`", s)),
+        }
+    }
+}
+
+/// Same format as a line of `/proc/<pid>/uid_map`
+impl fmt::Display for IdMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {} {}", self.container_id, self.host_id, self.size)
+    }
+}
+
+/// UID and GID mappings of a container's user namespace
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct IdMappings {
+    pub uid: Vec<IdMap>,
+    pub gid: Vec<IdMap>,
+    /// Leases of ranges allocated from `/etc/subuid` and `/etc/subgid`
+    leases: Vec<PathBuf>,
+    /// Only the rootless user is mapped, as root, and setgroups is denied
+    pub self_map: bool,
+}
+
+impl IdMappings {
+    pub fn new(uid: Vec<IdMap>, gid: Vec<IdMap>) -> Self {
+        Self {
+            uid,
+            gid,
+            leases: Vec::new(),
+            self_map: false,
+        }
+    }
+
+    /// Map the current user and group as root, the only mapping an unprivileged
+    /// user can write without the setuid helpers
+    fn current_user() -> Self {
+        let map = |id| IdMap {
+            container_id: 0,
+            host_id: id,
+            size: 1,
+        };
+        Self {
+            uid: vec![map(geteuid().as_raw())],
+            gid: vec![map(getegid().as_raw())],
+            leases: Vec::new(),
+            self_map: true,
+        }
+    }
+
+    /// Empty mappings are allocated from the subordinate ids of curn's user
+    pub fn allocate(
+        uid: Vec<IdMap>,
+        gid: Vec<IdMap>,
+        container_id: &str,
+        rootless: bool,
+    ) -> Result<Self, ErrorCode> {
+        if rootless && uid.is_empty() && gid.is_empty() && !has_subid_helpers() {
+            log::info!("No subordinate ids or newuidmap/newgidmap, mapping only the current user");
+            return Ok(Self::current_user());
+        }
+
+        let mut mappings = Self::new(uid, gid);
+        if mappings.uid.is_empty() {
+            let (map, lease) = allocate_range(SUBUID_FILE, "uid", container_id, rootless)?;
+            mappings.uid.push(map);
+            mappings.leases.extend(lease);
+        }
+        if mappings.gid.is_empty() {
+            let (map, lease) = allocate_range(SUBGID_FILE, "gid", container_id, rootless)
+                .inspect_err(|_| mappings.release())?;
+            mappings.gid.push(map);
+            mappings.leases.extend(lease);
+        }
+        Ok(mappings)
+    }
+
+    /// Ranges must not overlap and ids the container switches to must be mapped
+    pub fn validate(&self, uid: u32, gid: u32, groups: &[u32]) -> Result<(), ErrorCode> {
+        for (maps, kind) in [(&self.uid, "uidmap"), (&self.gid, "gidmap")] {
+            for (i, map) in maps.iter().enumerate() {
+                if let Some(other) = maps[i + 1..].iter().find(|m| map.overlaps(m)) {
+                    log::error!("Ranges {} and {} overlap", map, other);
+                    return Err(ErrorCode::ArgumentInvaild(kind));
+                }
+            }
+        }
+        if !self.uid.iter().any(|m| m.contains(uid)) {
+            log::error!("UID {} is not mapped by {:?}", uid, self.uid);
+            return Err(ErrorCode::ArgumentInvaild("uid"));
+        }
+        for id in std::iter::once(&gid).chain(groups) {
+            if !self.gid.iter().any(|m| m.contains(*id)) {
+                log::error!("GID {} is not mapped by {:?}", id, self.gid);
+                return Err(ErrorCode::ArgumentInvaild("gid"));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn release(&self) {
+        for lease in self.leases.iter() {
+            if let Err(e) = fs::remove_file(lease) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    log::error!("Cannot remove lease {:?}: {}", lease, e);
+                }
+            }
+        }
+    }
+}
+
+/// Return `(start, count)` of the subordinate ids of the current user
+fn read_subids(file: &Path) -> Vec<(u32, u32)> {
+    let uid = getuid();
+    let name = User::from_uid(uid)
+        .ok()
+        .flatten()
+        .map(|u| u.name)
+        .unwrap_or_default();
+    let content = fs::read_to_string(file).unwrap_or_default();
+
+    content
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.trim().split(':');
+            let owner = fields.next()?;
+            let start = fields.next()?.parse().ok()?;
+            let count = fields.next()?.parse().ok()?;
+            (owner == name || owner == uid.to_string()).then_some((start, count))
+        })
+        .collect()
+}
 
-    let has_userns = match unshare(CloneFlags::CLONE_NEWUSER) {
-        Ok(_) => true,
-        Err(_) => false,
-    };
+fn has_subid_helpers() -> bool {
+    !read_subids(Path::new(SUBUID_FILE)).is_empty()
+        && !read_subids(Path::new(SUBGID_FILE)).is_empty()
+        && which::which(NEWUIDMAP).is_ok()
+        && which::which(NEWGIDMAP).is_ok()
+}
+
+/// Lease a range of ids which no other container uses, leases are files named after the range
+fn allocate_range(
+    file: &str,
+    kind: &str,
+    container_id: &str,
+    rootless: bool,
+) -> Result<(IdMap, Option<PathBuf>), ErrorCode> {
+    let mut subids = read_subids(Path::new(file));
+    if subids.is_empty() && rootless {
+        log::error!("No subordinate ids of the current user in {}", file);
+        return Err(ErrorCode::NamespacesError(8));
+    }
+    if subids.is_empty() {
+        log::info!(
+            "No subordinate ids in {}, allocating {}s from the container range of systemd",
+            file,
+            kind
+        );
+        subids.push((CONTAINER_IDS_START, CONTAINER_IDS_COUNT));
+    }
+
+    let dir = idmap_lease_dir();
+    if let Err(e) = create_dir_all(&dir) {
+        log::error!("Cannot create lease directory {:?}: {}", dir, e);
+        return Err(ErrorCode::NamespacesError(9));
+    }
+    for (start, count) in subids {
+        let end = u64::from(start) + u64::from(count);
+        let mut host_id = u64::from(start);
+        while host_id + u64::from(SUBID_RANGE_SIZE) <= end {
+            let path = dir.join(format!("{}-{}", kind, host_id));
+            // creating the file fails if another container holds the range
+            let lease = OpenOptions::new().write(true).create_new(true).open(&path);
+            if let Ok(mut lease) = lease {
+                let _ = lease.write_all(container_id.as_bytes());
+                let map = IdMap {
+                    container_id: 0,
+                    host_id: host_id as u32,
+                    size: SUBID_RANGE_SIZE,
+                };
+                log::debug!("Allocated {} range {}", kind, map);
+                return Ok((map, Some(path)));
+            }
+            host_id += u64::from(SUBID_RANGE_SIZE);
+        }
+    }
+
+    log::error!(
+        "No {} range of {} ids left in {}",
+        kind,
+        SUBID_RANGE_SIZE,
+        file
+    );
+    Err(ErrorCode::NamespacesError(8))
+}
+
+/// Create the user namespace, or only synchronize with the parent if the child was cloned
+/// into one, then wait for the parent to write the UID and GID maps
+pub fn set_user_namespace(fd: RawFd, rootless: bool) -> Result<(), ErrorCode> {
+    log::debug!("Setting up user namespaces");
+
+    let has_userns = rootless || unshare(CloneFlags::CLONE_NEWUSER).is_ok();
     send_bool(fd, has_userns)?;
     if recv_bool(fd)? {
         return Err(ErrorCode::NamespacesError(0));
@@ -28,48 +293,107 @@ pub fn set_user_namespace(fd: RawFd, uid: u32) -> Result<(), ErrorCode> {
     } else {
         log::info!("User namespaces not supported, continuing");
     }
+    Ok(())
+}
 
-    log::debug!("Switching to UID {} and GID {} ...", uid, uid);
+/// Switch to the user of the command, groups cannot be set if setgroups is denied
+pub fn switch_user(
+    uid: u32,
+    gid: u32,
+    groups: &[u32],
+    setgroups_denied: bool,
+) -> Result<(), ErrorCode> {
+    log::debug!(
+        "Switching to UID {}, GID {} and groups {:?} ...",
+        uid,
+        gid,
+        groups
+    );
 
-    let gid = Gid::from_raw(uid);
+    let gid = Gid::from_raw(gid);
     let uid = Uid::from_raw(uid);
+    let groups = groups.iter().map(|g| Gid::from_raw(*g)).collect::<Vec<_>>();
 
-    if let Err(_) = setgroups(&[gid]) {
+    if !setgroups_denied && setgroups(&groups).is_err() {
         return Err(ErrorCode::NamespacesError(1));
     }
 
-    if let Err(_) = setresgid(gid, gid, gid) {
+    if setresgid(gid, gid, gid).is_err() {
         return Err(ErrorCode::NamespacesError(2));
     }
 
-    if let Err(_) = setresuid(uid, uid, uid) {
-        return Err(ErrorCode::NamespacesError(2));
+    if setresuid(uid, uid, uid).is_err() {
+        return Err(ErrorCode::NamespacesError(3));
+    }
+
+    Ok(())
+}
+
+fn write_id_map(pid: Pid, file: &str, maps: &[IdMap]) -> Result<(), std::io::Error> {
+    let content = maps.iter().map(|m| format!("{}\n", m)).collect::<String>();
+    // the kernel only accepts the whole map in a single write
+    File::create(format!("/proc/{}/{}", pid.as_raw(), file))?.write_all(content.as_bytes())
+}
+
+/// Write a map of subordinate ids through newuidmap or newgidmap
+fn run_id_map_helper(pid: Pid, helper: &str, maps: &[IdMap]) -> Result<(), std::io::Error> {
+    let mut command = process::Command::new(helper);
+    command.arg(pid.to_string());
+    for map in maps {
+        command.args([
+            map.container_id.to_string(),
+            map.host_id.to_string(),
+            map.size.to_string(),
+        ]);
     }
 
+    let status = command.status()?;
+    if !status.success() {
+        return Err(std::io::Error::other(format!("{} {}", helper, status)));
+    }
     Ok(())
 }
 
-pub fn handle_child_uid_gid_map(pid: Pid, fd: RawFd) -> Result<(), ErrorCode> {
+fn write_id_maps(pid: Pid, mappings: &IdMappings, rootless: bool) -> Result<(), ErrorCode> {
+    if rootless && !mappings.self_map {
+        run_id_map_helper(pid, NEWUIDMAP, &mappings.uid).map_err(|e| {
+            log::error!("Cannot map uids {:?}: {}", mappings.uid, e);
+            ErrorCode::NamespacesError(4)
+        })?;
+        return run_id_map_helper(pid, NEWGIDMAP, &mappings.gid).map_err(|e| {
+            log::error!("Cannot map gids {:?}: {}", mappings.gid, e);
+            ErrorCode::NamespacesError(6)
+        });
+    }
+
+    write_id_map(pid, "uid_map", &mappings.uid).map_err(|e| {
+        log::error!("Cannot write uid_map {:?}: {}", mappings.uid, e);
+        ErrorCode::NamespacesError(4)
+    })?;
+    // an unprivileged user may only write gid_map once setgroups is denied
+    if mappings.self_map {
+        if let Err(e) = fs::write(format!("/proc/{}/setgroups", pid.as_raw()), "deny") {
+            log::error!("Cannot deny setgroups: {}", e);
+            return Err(ErrorCode::NamespacesError(6));
+        }
+    }
+    write_id_map(pid, "gid_map", &mappings.gid).map_err(|e| {
+        log::error!("Cannot write gid_map {:?}: {}", mappings.gid, e);
+        ErrorCode::NamespacesError(6)
+    })
+}
+
+pub fn handle_child_uid_gid_map(
+    pid: Pid,
+    fd: RawFd,
+    mappings: &IdMappings,
+    rootless: bool,
+) -> Result<(), ErrorCode> {
     match recv_bool(fd)? {
         true => {
-            if let Ok(mut uid_map) = File::create(format!("/proc/{}/{}", pid.as_raw(), "uid_map")) {
-                if let Err(_) =
-                    uid_map.write_all(format!("0 {} {}", USERNS_OFFSET, USERNS_COUNT).as_bytes())
-                {
-                    return Err(ErrorCode::NamespacesError(4));
-                }
-            } else {
-                return Err(ErrorCode::NamespacesError(5));
-            }
-
-            if let Ok(mut gid_map) = File::create(format!("/proc/{}/{}", pid.as_raw(), "gid_map")) {
-                if let Err(_) =
-                    gid_map.write_all(format!("0 {} {}", USERNS_OFFSET, USERNS_COUNT).as_bytes())
-                {
-                    return Err(ErrorCode::NamespacesError(6));
-                }
-            } else {
-                return Err(ErrorCode::NamespacesError(7));
+            if let Err(e) = write_id_maps(pid, mappings, rootless) {
+                let _ = send_bool(fd, true);
+                return Err(e);
             }
         }
         false => log::info!("No user namespace set up from child process"),
@@ -78,3 +402,72 @@ pub fn handle_child_uid_gid_map(pid: Pid, fd: RawFd) -> Result<(), ErrorCode> {
     log::debug!("Child UID/GID map done, sending signal to child to continue ...");
     send_bool(fd, false)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map(container_id: u32, host_id: u32, size: u32) -> IdMap {
+        IdMap {
+            container_id,
+            host_id,
+            size,
+        }
+    }
+
+    #[test]
+    fn id_map_fields() {
+        assert_eq!("0:100000:65536".parse(), Ok(map(0, 100000, 65536)));
+        assert_eq!(map(1000, 2000, 1).to_string(), "1000 2000 1");
+        assert!("0:100000".parse::<IdMap>().is_err());
+        assert!("0:100000:65536:0".parse::<IdMap>().is_err());
+        assert!("0:100000:0".parse::<IdMap>().is_err());
+        assert!("0:-1:1".parse::<IdMap>().is_err());
+    }
+
+    #[test]
+    fn id_map_wrapping_around() {
+        assert_eq!("4294967295:0:1".parse(), Ok(map(u32::MAX, 0, 1)));
+        assert!("4294967295:0:2".parse::<IdMap>().is_err());
+        assert!("0:4294967295:2".parse::<IdMap>().is_err());
+        assert!(map(u32::MAX, 0, 1).contains(u32::MAX));
+    }
+
+    #[test]
+    fn overlapping_ranges() {
+        let root = map(0, 100000, 1000);
+        // inside of the container only, or outside of it only
+        assert!(root.overlaps(&map(999, 300000, 1)));
+        assert!(root.overlaps(&map(5000, 100999, 10)));
+        assert!(map(5000, 100999, 10).overlaps(&root));
+        // adjacent ranges
+        assert!(!root.overlaps(&map(1000, 101000, 1000)));
+        assert!(!map(1000, 101000, 1000).overlaps(&root));
+    }
+
+    #[test]
+    fn overlapping_ranges_are_refused() {
+        let mappings = IdMappings::new(
+            vec![map(0, 100000, 1000), map(1000, 101000, 1000)],
+            vec![map(0, 100000, 1000), map(2000, 100500, 1000)],
+        );
+        assert!(matches!(
+            mappings.validate(0, 0, &[]),
+            Err(ErrorCode::ArgumentInvaild("gidmap"))
+        ));
+
+        let mappings = IdMappings::new(
+            vec![map(0, 100000, 1000), map(1000, 101000, 1000)],
+            vec![map(0, 100000, 2000)],
+        );
+        assert!(mappings.validate(1999, 0, &[1999]).is_ok());
+        assert!(matches!(
+            mappings.validate(2000, 0, &[]),
+            Err(ErrorCode::ArgumentInvaild("uid"))
+        ));
+        assert!(matches!(
+            mappings.validate(0, 0, &[2000]),
+            Err(ErrorCode::ArgumentInvaild("gid"))
+        ));
+    }
+}
@@ -0,0 +1,270 @@
+use crate::error::ErrorCode;
+use nix::mount::MsFlags;
+use serde::{Deserialize, Serialize};
+use std::path::{Component, Path, PathBuf};
+
+// data options of tmpfs, flags are given by `MountOptions`
+const TMPFS_OPTIONS_SUPPORTED: [&str; 5] = ["size", "mode", "nr_inodes", "uid", "gid"];
+
+/// Propagation of mount events between a volume and the host
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Propagation {
+    /// Mounts of the host are received, the ones of the container are propagated to its peers
+    Shared,
+    /// Mounts of the host under the source are seen by the container only
+    Slave,
+    Private,
+}
+
+impl Propagation {
+    /// Always recursive, volumes are bound with their submounts
+    pub fn flags(self) -> MsFlags {
+        let flag = match self {
+            Propagation::Shared => MsFlags::MS_SHARED,
+            Propagation::Slave => MsFlags::MS_SLAVE,
+            Propagation::Private => MsFlags::MS_PRIVATE,
+        };
+        MsFlags::MS_REC | flag
+    }
+}
+
+/// Flags shared by volumes and tmpfs mounts
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MountOptions {
+    pub readonly: bool,
+    pub nosuid: bool,
+    pub nodev: bool,
+    pub noexec: bool,
+}
+
+impl MountOptions {
+    /// Return false if `option` is not a flag
+    fn set(&mut self, option: &str) -> bool {
+        match option {
+            "ro" => self.readonly = true,
+            "rw" => self.readonly = false,
+            "nosuid" => self.nosuid = true,
+            "suid" => self.nosuid = false,
+            "nodev" => self.nodev = true,
+            "dev" => self.nodev = false,
+            "noexec" => self.noexec = true,
+            "exec" => self.noexec = false,
+            _ => return false,
+        }
+        true
+    }
+
+    pub fn flags(&self) -> MsFlags {
+        let mut flags = MsFlags::empty();
+        flags.set(MsFlags::MS_RDONLY, self.readonly);
+        flags.set(MsFlags::MS_NOSUID, self.nosuid);
+        flags.set(MsFlags::MS_NODEV, self.nodev);
+        flags.set(MsFlags::MS_NOEXEC, self.noexec);
+        flags
+    }
+}
+
+/// Host file or directory bound into the container
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Volume {
+    pub source: PathBuf,
+    /// Relative to the root of the container, e.g. etc/hosts
+    pub destination: PathBuf,
+    pub options: MountOptions,
+    pub propagation: Propagation,
+}
+
+impl Volume {
+    /// Read-write and private bind of `source`
+    pub fn new(source: PathBuf, destination: PathBuf) -> Self {
+        Self {
+            source,
+            destination,
+            options: MountOptions::default(),
+            propagation: Propagation::Private,
+        }
+    }
+
+    /// Parse `--volume host:ctr[:ro|rw][,rshared|rslave|rprivate][,nosuid,nodev,noexec]`,
+    /// the host path must exist
+    pub fn parse(spec: &str) -> Result<Self, ErrorCode> {
+        let parts = spec.split(':').collect::<Vec<_>>();
+        let (source, destination, options) = match parts[..] {
+            [source, destination] => (source, destination, None),
+            [source, destination, options] => (source, destination, Some(options)),
+            _ => {
+                log::error!("Volume `{}` is not HOST:CONTAINER[:OPTIONS]", spec);
+                return Err(ErrorCode::ArgumentInvaild("volume"));
+            }
+        };
+
+        let source = Path::new(source).canonicalize().map_err(|e| {
+            log::error!("Cannot canonicalize volume source `{}`: {}", source, e);
+            ErrorCode::ArgumentInvaild("volume")
+        })?;
+        let destination = container_path(Path::new(destination)).ok_or_else(|| {
+            log::error!("Volume destination `{}` must be absolute", destination);
+            ErrorCode::ArgumentInvaild("volume")
+        })?;
+
+        let mut volume = Self::new(source, destination);
+        for option in options.into_iter().flat_map(|o| o.split(',')) {
+            if !volume.set_option(option) {
+                log::error!("Unknown option `{}` of volume `{}`", option, spec);
+                return Err(ErrorCode::ArgumentInvaild("volume"));
+            }
+        }
+        Ok(volume)
+    }
+
+    /// Apply an option of `--volume` or of a bind mount of OCI, return false if unknown
+    pub fn set_option(&mut self, option: &str) -> bool {
+        match option {
+            "rshared" | "shared" => self.propagation = Propagation::Shared,
+            "rslave" | "slave" => self.propagation = Propagation::Slave,
+            "rprivate" | "private" => self.propagation = Propagation::Private,
+            _ => return self.options.set(option),
+        }
+        true
+    }
+}
+
+/// Empty tmpfs mounted into the container, nosuid and nodev by default
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Tmpfs {
+    /// Relative to the root of the container, e.g. tmp
+    pub destination: PathBuf,
+    pub options: MountOptions,
+    /// Options given to tmpfs, e.g. `size=64m`
+    pub data: Vec<String>,
+}
+
+impl Tmpfs {
+    pub fn new(destination: PathBuf) -> Self {
+        Self {
+            destination,
+            options: MountOptions {
+                nosuid: true,
+                nodev: true,
+                ..MountOptions::default()
+            },
+            data: Vec::new(),
+        }
+    }
+
+    /// Parse `--tmpfs /path[:size=..,mode=..]`, flags of `--volume` are accepted as well
+    pub fn parse(spec: &str) -> Result<Self, ErrorCode> {
+        let (destination, options) = match spec.split_once(':') {
+            Some((destination, options)) => (destination, Some(options)),
+            None => (spec, None),
+        };
+        let destination = container_path(Path::new(destination)).ok_or_else(|| {
+            log::error!("Tmpfs destination `{}` must be absolute", destination);
+            ErrorCode::ArgumentInvaild("tmpfs")
+        })?;
+
+        let mut tmpfs = Self::new(destination);
+        for option in options.into_iter().flat_map(|o| o.split(',')) {
+            if !tmpfs.set_option(option) {
+                log::error!("Invalid option `{}` of tmpfs `{}`", option, spec);
+                return Err(ErrorCode::ArgumentInvaild("tmpfs"));
+            }
+        }
+        Ok(tmpfs)
+    }
+
+    /// Apply an option of `--tmpfs` or of a tmpfs mount of OCI, return false if invalid
+    pub fn set_option(&mut self, option: &str) -> bool {
+        if self.options.set(option) {
+            return true;
+        }
+        let Some((key, value)) = option.split_once('=') else {
+            return false;
+        };
+        let valid = match key {
+            "mode" => u32::from_str_radix(value, 8).is_ok(),
+            _ => TMPFS_OPTIONS_SUPPORTED.contains(&key) && !value.is_empty(),
+        };
+        if valid {
+            self.data.push(option.to_string());
+        }
+        valid
+    }
+}
+
+/// Return an absolute path of the container relative to its root,
+/// `None` if it is relative or escapes the root by `..`
+pub fn container_path(path: &Path) -> Option<PathBuf> {
+    if path.components().any(|c| c == Component::ParentDir) {
+        return None;
+    }
+    path.strip_prefix("/")
+        .ok()
+        .filter(|p| !p.as_os_str().is_empty())
+        .map(Path::to_path_buf)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(res: Result<impl std::fmt::Debug, ErrorCode>, element: &str) -> bool {
+        matches!(res, Err(ErrorCode::ArgumentInvaild(e)) if e == element)
+    }
+
+    #[test]
+    fn volume_options() {
+        let volume = Volume::parse("/tmp:/data:ro,rslave,nosuid").unwrap();
+        assert_eq!(volume.source, Path::new("/tmp").canonicalize().unwrap());
+        assert_eq!(volume.destination, Path::new("data"));
+        assert_eq!(volume.propagation, Propagation::Slave);
+        assert_eq!(
+            volume.options.flags(),
+            MsFlags::MS_RDONLY | MsFlags::MS_NOSUID
+        );
+        // the last of `ro` and `rw` wins
+        assert!(!Volume::parse("/tmp:/data:ro,rw").unwrap().options.readonly);
+    }
+
+    #[test]
+    fn invalid_volumes() {
+        assert!(invalid(Volume::parse("/tmp:/a:ro:rw"), "volume"));
+        assert!(invalid(Volume::parse("/tmp"), "volume"));
+        assert!(invalid(Volume::parse("/tmp:"), "volume"));
+        assert!(invalid(Volume::parse("/tmp:data"), "volume"));
+        assert!(invalid(Volume::parse("/tmp:/data/../../etc"), "volume"));
+        assert!(invalid(Volume::parse("/tmp:/data:rbind"), "volume"));
+        assert!(invalid(Volume::parse("/curn-missing:/data"), "volume"));
+    }
+
+    #[test]
+    fn tmpfs_options() {
+        let tmpfs = Tmpfs::parse("/run:size=64m,mode=1777,noexec").unwrap();
+        assert_eq!(tmpfs.destination, Path::new("run"));
+        assert_eq!(tmpfs.data, ["size=64m", "mode=1777"]);
+        assert_eq!(
+            tmpfs.options.flags(),
+            MsFlags::MS_NOSUID | MsFlags::MS_NODEV | MsFlags::MS_NOEXEC
+        );
+    }
+
+    #[test]
+    fn invalid_tmpfs() {
+        assert!(invalid(Tmpfs::parse("/run:size"), "tmpfs"));
+        assert!(invalid(Tmpfs::parse("/run:size="), "tmpfs"));
+        assert!(invalid(Tmpfs::parse("/run:mode=999"), "tmpfs"));
+        assert!(invalid(Tmpfs::parse("/run:huge=always"), "tmpfs"));
+        assert!(invalid(Tmpfs::parse("run"), "tmpfs"));
+    }
+
+    #[test]
+    fn container_paths() {
+        assert_eq!(
+            container_path(Path::new("/etc/hosts")).unwrap(),
+            Path::new("etc/hosts")
+        );
+        assert_eq!(container_path(Path::new("/")), None);
+        assert_eq!(container_path(Path::new("etc")), None);
+        assert_eq!(container_path(Path::new("/a/../..")), None);
+    }
+}